@@ -0,0 +1,615 @@
+//! 把实现了 embedded-hal 的外设包装成框架的设备
+//! 厂商 HAL 已经实现了串口、SPI、I2C、GPIO 的 trait 时，不需要再编写寄存器级的 BSP
+//! HalSerial -> DeviceSerial、HalSpiBus -> BusSpi、HalI2cBus -> SBusI2CTrans、HalPin -> DeviceLed
+//! 外设的时钟、引脚和波特率等配置由厂商 HAL 在构造时完成，框架的配置操作被忽略
+//!
+//! ```ignore
+//! let serial = HalSerial::new(vendor_uart);
+//! let irq = serial.irq_handle();
+//! register_device(Serial::new(serial), "uart1")?;
+//! register_device(Led::new(HalPin::new(vendor_pin, true)), "led0")?;
+//! // 在串口的中断处理函数中
+//! irq.on_irq();
+//! ```
+
+use crate::alloc::sync::Arc;
+use crate::alloc::vec::Vec;
+use crate::device::base::DynCycleQueue;
+use crate::device::i2c_bus::{I2CAddressType, I2CBusError, I2CMsg, SBusI2CTrans, I2CRW};
+use crate::device::led::{DeviceLed, LedError};
+use crate::device::serial::bsp::BspSerial;
+use crate::device::serial::{
+    bsp, DeviceSerial, SerialBaudRate, SerialBitOrder, SerialDataBits, SerialError, SerialParity,
+    SerialStopBits,
+};
+use crate::device::spi_bus::bsp::BspBusSpi;
+use crate::device::spi_bus::BusSpi;
+use crate::device::spi_device::{SpiConfig, SpiError};
+use crate::os::{spin_until, Os, OsApi};
+use crate::{IOError, OpenFlag};
+use core::cell::{Cell, RefCell, UnsafeCell};
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::i2c::{self, I2c, Operation};
+use embedded_hal::spi::{self, SpiBus};
+use embedded_hal_nb::nb;
+use embedded_hal_nb::serial;
+
+/* 串口 */
+
+const SERIAL_BUF_SIZE: usize = 128;
+// dma_write 等待外设空闲的时间，9600 波特率下一个字节约 1ms
+const BYTE_TIMEOUT_MS: u32 = 2;
+
+fn serial_error<E: serial::Error>(e: E) -> SerialError {
+    match e.kind() {
+        serial::ErrorKind::Overrun => SerialError::Overrun,
+        serial::ErrorKind::FrameFormat => SerialError::Framing,
+        serial::ErrorKind::Parity => SerialError::Parity,
+        _ => SerialError::ReadError,
+    }
+}
+
+// 使用 embedded-hal-nb 串口的设备
+// 外设没有中断接口，中断读和异步读需要在中断处理函数或周期任务中调用 HalSerialIrq::on_irq
+// 发送中断同样由 on_irq 模拟，每次调用在外设空闲时发送一个字节，外设忙时留到下一次
+pub struct HalSerial<T> {
+    dev: UnsafeCell<T>,
+    hp: BspSerial,
+    // read_able 时已经从外设读出的字节或错误，由 read_char 取走
+    peek: Cell<Option<Result<u8, SerialError>>>,
+    flag: Cell<OpenFlag>,
+    rx_irq: AtomicBool,
+    tx_irq: AtomicBool,
+    // 打开后指向自身，供中断入口使用
+    ptr: Arc<AtomicUsize>,
+}
+
+impl<T: serial::Read + serial::Write> HalSerial<T> {
+    pub fn new(dev: T) -> HalSerial<T> {
+        HalSerial {
+            dev: UnsafeCell::new(dev),
+            hp: BspSerial {
+                read_async_helper: UnsafeCell::new(None),
+                r_buffer: UnsafeCell::new(DynCycleQueue::new(SERIAL_BUF_SIZE)),
+                w_buffer: UnsafeCell::new(DynCycleQueue::new(SERIAL_BUF_SIZE)),
+                rx_indicate: UnsafeCell::new(None),
+            },
+            peek: Cell::new(None),
+            flag: Cell::new(OpenFlag::zero()),
+            rx_irq: AtomicBool::new(false),
+            tx_irq: AtomicBool::new(false),
+            ptr: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // 注册前取得中断入口，设备关闭时中断入口不做任何事
+    pub fn irq_handle(&self) -> HalSerialIrq<T> {
+        HalSerialIrq {
+            ptr: self.ptr.clone(),
+            _dev: PhantomData,
+        }
+    }
+
+    fn with<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        Os::no_irq(|| unsafe { f(&mut *self.dev.get()) })
+    }
+
+    // 与寄存器级 BSP 的中断处理函数流程相同
+    fn service(&self) {
+        let dev = self as *const Self as *mut Self;
+        Os::no_irq(|| {
+            let flag = self.flag.get();
+            while self.rx_irq.load(Ordering::Acquire) && self.read_able() {
+                // 中断中无法返回错误，出错的字符被丢弃
+                if let Ok(ch) = self.read_char() {
+                    bsp::irq_receive_char(dev, ch);
+                    if flag.get_read_c_type() {
+                        bsp::call_rx_indicate(dev);
+                    } else if flag.get_read_async() {
+                        bsp::notify_form_irq(dev);
+                    }
+                }
+            }
+            // 不能在关中断时等待外设，忙时直接返回
+            if self.tx_irq.load(Ordering::Acquire) && self.write_able() {
+                bsp::irq_send_char(dev);
+            }
+        })
+    }
+}
+
+impl<T: serial::Read + serial::Write> DeviceSerial for HalSerial<T> {
+    fn init(&self, f: &OpenFlag) -> Result<(), SerialError> {
+        self.flag.set(*f);
+        self.ptr
+            .store(self as *const Self as usize, Ordering::Release);
+        Ok(())
+    }
+
+    fn uninit(&self) -> Result<(), SerialError> {
+        self.ptr.store(0, Ordering::Release);
+        self.rx_irq.store(false, Ordering::Release);
+        self.tx_irq.store(false, Ordering::Release);
+        self.peek.set(None);
+        Ok(())
+    }
+
+    fn get_helper(&self) -> &BspSerial {
+        &self.hp
+    }
+
+    fn read_char(&self) -> Result<u8, SerialError> {
+        Os::no_irq(|| match self.peek.take() {
+            Some(r) => r,
+            None => match self.with(|d| d.read()) {
+                Ok(ch) => Ok(ch),
+                Err(nb::Error::WouldBlock) => Err(SerialError::ReadError),
+                Err(nb::Error::Other(e)) => Err(serial_error(e)),
+            },
+        })
+    }
+
+    // 外设只能通过读取来判断是否有数据，读出的数据暂存起来
+    fn read_able(&self) -> bool {
+        Os::no_irq(|| {
+            if self.peek.get().is_some() {
+                return true;
+            }
+            let r = match self.with(|d| d.read()) {
+                Ok(ch) => Ok(ch),
+                Err(nb::Error::WouldBlock) => return false,
+                Err(nb::Error::Other(e)) => Err(serial_error(e)),
+            };
+            self.peek.set(Some(r));
+            true
+        })
+    }
+
+    // 只尝试一次，调用前应先通过 write_able 确认外设空闲
+    fn write_char(&self, val: u8) -> Result<(), SerialError> {
+        match self.with(|d| d.write(val)) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(SerialError::WriteError),
+        }
+    }
+
+    // 外设没有查询发送寄存器的接口，发送完成时一定可写
+    fn write_able(&self) -> bool {
+        self.write_finish()
+    }
+
+    fn write_finish(&self) -> bool {
+        self.with(|d| d.flush()).is_ok()
+    }
+
+    fn rx_irq_en(&self, f: bool) {
+        self.rx_irq.store(f, Ordering::Release);
+        if f {
+            self.service();
+        }
+    }
+
+    fn tx_irq_en(&self, f: bool) {
+        self.tx_irq.store(f, Ordering::Release);
+        if f {
+            self.service();
+        }
+    }
+
+    fn dma_write(&self, ptr: *const u8, len: usize) -> Result<(), SerialError> {
+        let data = unsafe { core::slice::from_raw_parts(ptr, len) };
+        for ch in data {
+            spin_until(|| self.write_able(), BYTE_TIMEOUT_MS)
+                .map_err(|_| SerialError::WriteError)?;
+            self.write_char(*ch)?;
+        }
+        Ok(())
+    }
+
+    // embedded-hal 没有配置串口参数的接口，由创建外设时决定
+    fn config_baud(&self, _val: SerialBaudRate) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn stop_bots(&self, _val: SerialStopBits) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn data_bits(&self, _val: SerialDataBits) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn parity(&self, _val: SerialParity) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn bit_order(&self, _val: SerialBitOrder) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn update_flags(&self, f: OpenFlag) -> OpenFlag {
+        self.flag.set(f);
+        f
+    }
+}
+
+// HalSerial 的中断入口，可以放在静态变量中由中断处理函数使用
+pub struct HalSerialIrq<T> {
+    ptr: Arc<AtomicUsize>,
+    _dev: PhantomData<fn() -> T>,
+}
+
+impl<T: serial::Read + serial::Write> HalSerialIrq<T> {
+    // 把外设收到的数据放入接收缓冲区并唤醒等待者
+    pub fn on_irq(&self) {
+        Os::no_irq(|| {
+            let dev = self.ptr.load(Ordering::Acquire) as *const HalSerial<T>;
+            if !dev.is_null() {
+                unsafe { (*dev).service() }
+            }
+        })
+    }
+}
+
+/* SPI 总线 */
+
+fn spi_error<E: spi::Error>(e: E) -> SpiError {
+    match e.kind() {
+        spi::ErrorKind::Overrun => SpiError::ReadError,
+        _ => SpiError::WriteError,
+    }
+}
+
+// 使用 embedded-hal SpiBus 的总线，片选由挂在总线上的设备控制
+pub struct HalSpiBus<T> {
+    bus: RefCell<T>,
+    hp: BspBusSpi,
+}
+
+impl<T: SpiBus<u8>> HalSpiBus<T> {
+    pub fn new(bus: T) -> HalSpiBus<T> {
+        HalSpiBus {
+            bus: RefCell::new(bus),
+            hp: BspBusSpi {
+                r_buffer: UnsafeCell::new(DynCycleQueue::new(32)),
+                w_buffer: UnsafeCell::new(DynCycleQueue::new(32)),
+            },
+        }
+    }
+}
+
+impl<T: SpiBus<u8>> BusSpi for HalSpiBus<T> {
+    fn init(&self, _f: &OpenFlag, _cfg: &SpiConfig) -> Result<(), SpiError> {
+        Ok(())
+    }
+
+    fn np_init(&self) -> Result<(), SpiError> {
+        Ok(())
+    }
+
+    fn uninit(&self) -> Result<(), SpiError> {
+        Ok(())
+    }
+
+    fn trans_bit(&self, data: u8) -> Result<u8, SpiError> {
+        let mut bus = self.bus.borrow_mut();
+        let mut buf = [data];
+        bus.transfer_in_place(&mut buf)
+            .and_then(|_| bus.flush())
+            .map_err(spi_error)?;
+        Ok(buf[0])
+    }
+
+    fn trans_bits_dma(&self, ptr: *const u8, len: usize) -> Result<(), SpiError> {
+        let data = unsafe { core::slice::from_raw_parts(ptr, len) };
+        let mut bus = self.bus.borrow_mut();
+        bus.write(data).and_then(|_| bus.flush()).map_err(spi_error)
+    }
+
+    fn get_helper(&self) -> &BspBusSpi {
+        &self.hp
+    }
+}
+
+/* I2C 总线 */
+
+fn i2c_error<E: i2c::Error>(e: E, address: u16) -> I2CBusError {
+    match e.kind() {
+        i2c::ErrorKind::NoAcknowledge(_) => I2CBusError::Nack(address),
+        _ => I2CBusError::Bus,
+    }
+}
+
+// 使用 embedded-hal I2c 的硬件总线
+// 不支持 10 位地址和忽略应答，超出 7 位的地址返回 I2CBusError::Address
+pub struct HalI2cBus<T> {
+    bus: RefCell<T>,
+    init: AtomicBool,
+    num: AtomicU32,
+}
+
+impl<T: I2c> HalI2cBus<T> {
+    pub fn new(bus: T) -> HalI2cBus<T> {
+        HalI2cBus {
+            bus: RefCell::new(bus),
+            init: AtomicBool::new(false),
+            num: AtomicU32::new(0),
+        }
+    }
+}
+
+impl<T: I2c> SBusI2CTrans for HalI2cBus<T> {
+    // 地址相同的连续消息在一次传输中完成，中间使用重复起始条件
+    fn i2c_trans(&self, msgs: &mut [I2CMsg]) -> Result<(), I2CBusError> {
+        for m in msgs.iter() {
+            if let I2CAddressType::Bits10 = m.address_type {
+                return Err(I2CBusError::Address(m.address));
+            }
+            I2CAddressType::Bits7.check(m.address)?;
+        }
+        let mut bus = self.bus.borrow_mut();
+        for group in msgs.chunk_by_mut(|a, b| a.address == b.address) {
+            let address = group[0].address;
+            let mut ops: Vec<Operation<'_>> = group
+                .iter_mut()
+                .map(|m| match m.rw {
+                    I2CRW::Read => {
+                        m.buf.resize(m.len as usize, 0);
+                        Operation::Read(&mut m.buf[..])
+                    }
+                    I2CRW::Write => {
+                        let len = (m.len as usize).min(m.buf.len());
+                        Operation::Write(&m.buf[..len])
+                    }
+                })
+                .collect();
+            bus.transaction(address as u8, &mut ops)
+                .map_err(|e| i2c_error(e, address))?;
+        }
+        Ok(())
+    }
+
+    fn i2c_read_into(&self, address: u16, buf: &mut [u8]) -> Result<usize, I2CBusError> {
+        I2CAddressType::Bits7.check(address)?;
+        self.bus
+            .borrow_mut()
+            .read(address as u8, buf)
+            .map_err(|e| i2c_error(e, address))?;
+        Ok(buf.len())
+    }
+
+    fn i2c_write_from(&self, address: u16, buf: &[u8]) -> Result<usize, I2CBusError> {
+        I2CAddressType::Bits7.check(address)?;
+        self.bus
+            .borrow_mut()
+            .write(address as u8, buf)
+            .map_err(|e| i2c_error(e, address))?;
+        Ok(buf.len())
+    }
+
+    fn is_i2c_init(&self) -> bool {
+        self.init.load(Ordering::Acquire)
+    }
+
+    fn set_uninit(&self) {
+        self.init.store(false, Ordering::SeqCst)
+    }
+
+    // 外设已经由厂商 HAL 初始化
+    fn call_i2c_init(&self) -> Result<(), I2CBusError> {
+        self.init.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    fn inc_open_num(&self) {
+        self.num.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn dec_open_num(&self) -> u32 {
+        let old = self
+            .num
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                Some(n.saturating_sub(1))
+            })
+            .unwrap_or(0);
+        old.saturating_sub(1)
+    }
+
+    fn call_i2c_uninit(&self) -> Result<(), I2CBusError> {
+        Ok(())
+    }
+}
+
+/* GPIO */
+
+// 接在 embedded-hal 输出引脚上的 LED
+pub struct HalPin<T> {
+    pin: RefCell<T>,
+    // 低电平点亮
+    active_low: bool,
+    on: AtomicBool,
+}
+
+impl<T: OutputPin> HalPin<T> {
+    pub fn new(pin: T, active_low: bool) -> HalPin<T> {
+        HalPin {
+            pin: RefCell::new(pin),
+            active_low,
+            on: AtomicBool::new(false),
+        }
+    }
+
+    fn light(&self, on: bool, err: LedError) -> Result<(), LedError> {
+        let mut pin = self.pin.borrow_mut();
+        let ret = if on != self.active_low {
+            pin.set_high()
+        } else {
+            pin.set_low()
+        };
+        ret.map_err(|_| err)?;
+        self.on.store(on, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<T: OutputPin> DeviceLed for HalPin<T> {
+    fn init(&self) -> Result<(), LedError> {
+        self.light(false, LedError::InitError)
+    }
+
+    fn on(&self) -> Result<(), LedError> {
+        self.light(true, LedError::OnOFFError)
+    }
+
+    fn off(&self) -> Result<(), LedError> {
+        self.light(false, LedError::OnOFFError)
+    }
+
+    fn is_on(&self) -> bool {
+        self.on.load(Ordering::Acquire)
+    }
+
+    fn uninit(&self) -> Result<(), LedError> {
+        self.light(false, LedError::UninitError)
+    }
+}
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use super::*;
+    use crate::device::register_device;
+    use crate::device::serial::Serial;
+    use crate::typed::find_serial;
+    use core::convert::Infallible;
+    use std::sync::Mutex as StdMutex;
+
+    // 忙时读写都返回 WouldBlock 的串口
+    #[derive(Clone, Default)]
+    struct Uart(Arc<StdMutex<(bool, Vec<u8>)>>);
+
+    impl serial::ErrorType for Uart {
+        type Error = Infallible;
+    }
+
+    impl serial::Read for Uart {
+        fn read(&mut self) -> nb::Result<u8, Infallible> {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    impl serial::Write for Uart {
+        fn write(&mut self, w: u8) -> nb::Result<(), Infallible> {
+            let mut s = self.0.lock().unwrap();
+            if s.0 {
+                return Err(nb::Error::WouldBlock);
+            }
+            s.1.push(w);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> nb::Result<(), Infallible> {
+            if self.0.lock().unwrap().0 {
+                return Err(nb::Error::WouldBlock);
+            }
+            Ok(())
+        }
+    }
+
+    impl Uart {
+        fn busy(&self, b: bool) {
+            self.0.lock().unwrap().0 = b;
+        }
+
+        fn tx(&self) -> Vec<u8> {
+            self.0.lock().unwrap().1.clone()
+        }
+    }
+
+    #[test]
+    fn write_char_busy() {
+        let u = Uart::default();
+        let s = HalSerial::new(u.clone());
+        u.busy(true);
+        assert!(!s.write_able());
+        assert!(matches!(s.write_char(b'x'), Err(SerialError::WriteError)));
+        assert!(matches!(
+            s.dma_write(b"x".as_ptr(), 1),
+            Err(SerialError::WriteError)
+        ));
+        u.busy(false);
+        assert!(s.write_able());
+        s.write_char(b'y').unwrap();
+        assert_eq!(u.tx(), b"y");
+    }
+
+    #[test]
+    fn tx_one_byte_per_irq() {
+        let u = Uart::default();
+        let s = HalSerial::new(u.clone());
+        let irq = s.irq_handle();
+        register_device(Serial::new(s), "test/adapter/tx").unwrap();
+        let dev = find_serial("test/adapter/tx").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        // 打开发送中断时先发送一个字节，其余的由 on_irq 发送
+        g.write_bytes(b"ab").unwrap();
+        assert_eq!(u.tx(), b"a");
+        irq.on_irq();
+        assert_eq!(u.tx(), b"ab");
+        // 外设忙时直接返回，不在关中断时等待
+        u.busy(true);
+        g.write_bytes(b"c").unwrap();
+        irq.on_irq();
+        assert_eq!(u.tx(), b"ab");
+        u.busy(false);
+        irq.on_irq();
+        assert_eq!(u.tx(), b"abc");
+    }
+
+    struct Bus(Vec<u8>);
+
+    impl i2c::ErrorType for Bus {
+        type Error = i2c::ErrorKind;
+    }
+
+    impl I2c for Bus {
+        fn transaction(
+            &mut self,
+            address: u8,
+            _ops: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            self.0.push(address);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn i2c_address() {
+        let b = HalI2cBus::new(Bus(Vec::new()));
+        let mut ten = I2CMsg::read(0x20, 1);
+        ten.address_type = I2CAddressType::Bits10;
+        let mut msgs = [I2CMsg::write(0x20, alloc::vec![1]), ten];
+        assert!(matches!(
+            b.i2c_trans(&mut msgs),
+            Err(I2CBusError::Address(0x20))
+        ));
+        let mut msgs = [I2CMsg::read(0x120, 1)];
+        assert!(matches!(
+            b.i2c_trans(&mut msgs),
+            Err(I2CBusError::Address(0x120))
+        ));
+        assert!(matches!(
+            b.i2c_read_into(0x80, &mut [0]),
+            Err(I2CBusError::Address(0x80))
+        ));
+        assert!(matches!(
+            b.i2c_write_from(0x3FF, &[0]),
+            Err(I2CBusError::Address(0x3FF))
+        ));
+        // 地址错误时不访问总线
+        assert!(b.bus.borrow().0.is_empty());
+        b.i2c_write_from(0x7F, &[0]).unwrap();
+        assert_eq!(b.bus.borrow().0, [0x7F]);
+    }
+}
@@ -1,19 +1,29 @@
-use crate::alloc::string::ToString;
+use crate::alloc::borrow::Cow;
+use crate::alloc::string::{String, ToString};
 use crate::alloc::sync::Arc;
+use crate::alloc::vec::{IntoIter, Vec};
 use crate::data::OpenFlag;
-use crate::device::register_fast_device;
+use crate::device::{check_name, register_fast_device, DeviceClass};
 use crate::driver::Driver;
 use crate::error::IOError;
 use crate::fast_dev::FastDev;
-use crate::guard::DriverGuard;
+use crate::guard::{DriverGuard, OwnedDriverGuard};
+use crate::os::{Os, OsApi, OsSemaphore};
+use crate::trace::{self, TraceData, TraceOp};
 use crate::Mutex;
+use crate::ALIAS_LIST;
 use crate::DEVICE_LIST;
 use crate::FAST_DEVICE_LIST;
+use core::fmt::{Display, Formatter};
+use core::ops::Bound;
 
+// 设备名可以是路径形式，如 tty/uart1、bus/i2c1/bmp280
+// 也可以是别名，别名会先被解析为设备名
 pub fn find(name: &str) -> Result<Arc<Mutex<Driver>>, IOError> {
+    let name = resolve(name);
     let list = DEVICE_LIST.lock().unwrap();
 
-    let dev = list.get(name);
+    let dev = list.get(name.as_str());
 
     return match dev {
         Some(dev) => Ok(dev.clone()),
@@ -23,6 +33,10 @@ pub fn find(name: &str) -> Result<Arc<Mutex<Driver>>, IOError> {
 
 pub trait DevOpen {
     fn open(&self, f: &OpenFlag) -> Result<DriverGuard, IOError>;
+    // 设备被占用时等待，直到占用者释放或者超时
+    fn open_wait(&self, f: &OpenFlag, timeout: Option<u32>) -> Result<DriverGuard, IOError>;
+    // 得到持有设备所有权的 guard，不受设备句柄生命周期的限制
+    fn open_owned(&self, f: &OpenFlag) -> Result<OwnedDriverGuard, IOError>;
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -36,16 +50,231 @@ impl DevOpen for Arc<Mutex<Driver>> {
     fn open(&self, f: &OpenFlag) -> Result<DriverGuard, IOError> {
         let ot = raw_open(self, f)?;
         Ok(DriverGuard {
-            raw: self,
+            raw: Cow::Borrowed(self),
             o_type: ot,
         })
     }
+
+    fn open_wait(&self, f: &OpenFlag, timeout: Option<u32>) -> Result<DriverGuard, IOError> {
+        let ot = raw_open_wait(self, f, timeout)?;
+        Ok(DriverGuard {
+            raw: Cow::Borrowed(self),
+            o_type: ot,
+        })
+    }
+
+    fn open_owned(&self, f: &OpenFlag) -> Result<OwnedDriverGuard, IOError> {
+        let guard = open_static(self, f)?;
+        Ok(OwnedDriverGuard(Arc::new(guard)))
+    }
+}
+
+pub(crate) fn open_static(
+    dev: &Arc<Mutex<Driver>>,
+    f: &OpenFlag,
+) -> Result<DriverGuard<'static>, IOError> {
+    let ot = raw_open(dev, f)?;
+    Ok(DriverGuard {
+        raw: Cow::Owned(dev.clone()),
+        o_type: ot,
+    })
+}
+
+// 别名最多允许的嵌套层数
+const ALIAS_DEPTH: usize = 8;
+
+// 将别名解析为设备名，不是别名时原样返回
+pub fn resolve(name: &str) -> String {
+    let alias = ALIAS_LIST.lock().unwrap();
+    let mut name = name;
+    for _ in 0..ALIAS_DEPTH {
+        match alias.get(name) {
+            Some(target) => name = target.as_str(),
+            None => break,
+        }
+    }
+    name.to_string()
+}
+
+// 设置别名，别名已经存在时替换指向的设备
+// 别名不能与设备同名，也不能形成环
+pub fn set_alias(alias: &str, target: &str) -> Result<(), IOError> {
+    if !check_name(alias) || !check_name(target) {
+        return Err(IOError::RegisterError);
+    }
+    let mut list = ALIAS_LIST.lock().unwrap();
+    if DEVICE_LIST.lock().unwrap().contains_key(alias)
+        || FAST_DEVICE_LIST.lock().unwrap().contains_key(alias)
+    {
+        return Err(IOError::RegisterError);
+    }
+    let mut name = target;
+    for _ in 0..ALIAS_DEPTH {
+        if name == alias {
+            return Err(IOError::RegisterError);
+        }
+        match list.get(name) {
+            Some(t) => name = t.as_str(),
+            None => break,
+        }
+    }
+    list.insert(alias.to_string(), target.to_string());
+    Ok(())
+}
+
+pub fn remove_alias(alias: &str) -> Result<(), IOError> {
+    let mut list = ALIAS_LIST.lock().unwrap();
+    list.remove(alias).map(|_| ()).ok_or(IOError::FindError)
+}
+
+// 列出所有别名及其指向的名称
+pub fn list_alias() -> IntoIter<(String, String)> {
+    let list = ALIAS_LIST.lock().unwrap();
+    let ret: Vec<(String, String)> = list.iter().map(|(a, t)| (a.clone(), t.clone())).collect();
+    ret.into_iter()
+}
+
+// 设备列表中的一项
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub class: DeviceClass,
+    pub is_block: bool,
+    pub open_type: Option<OpenType>,
+    pub open_num: u32,
+    // 父设备的名称
+    pub parent: Option<String>,
+}
+
+impl Display for DeviceInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let kind = if self.is_block { "block" } else { "char" };
+        let open = match self.open_type {
+            None => "closed",
+            Some(OpenType::Only) => "only",
+            Some(_) => "shared",
+        };
+        write!(
+            f,
+            "{:<16} {:<14} {:<6} {:<7} {}",
+            self.name,
+            self.class.as_str(),
+            kind,
+            open,
+            self.open_num
+        )
+    }
+}
+
+// 列出所有注册的设备，包括快设备
+// 已经被取走的快设备不在链表中，不会被列出
+// 返回的是调用时刻的快照
+pub fn list_device() -> IntoIter<DeviceInfo> {
+    list_prefix("")
+}
+
+// 列出某一路径下的设备，如 "tty" 会列出 tty/uart1，但不会列出 ttyS1
+// 前缀为空时列出所有设备
+pub fn list_prefix(prefix: &str) -> IntoIter<DeviceInfo> {
+    let prefix = prefix.trim_end_matches('/');
+    let under = |name: &str| {
+        prefix.is_empty()
+            || name == prefix
+            || (name.starts_with(prefix) && name[prefix.len()..].starts_with('/'))
+    };
+    // 先取出设备再逐个查询状态，不在持有链表锁时等待设备锁
+    // 否则一个设备上的阻塞读写会让查找、注册等所有使用链表的操作一起阻塞
+    let devs: Vec<(String, Arc<Mutex<Driver>>)> = {
+        let list = DEVICE_LIST.lock().unwrap();
+        list.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(name, _)| name.starts_with(prefix))
+            .filter(|(name, _)| under(name))
+            .map(|(name, dev)| (name.clone(), dev.clone()))
+            .collect()
+    };
+    let mut ret = Vec::new();
+    for (name, dev) in devs {
+        let dev = dev.lock().unwrap();
+        ret.push(DeviceInfo {
+            name,
+            class: dev.class(),
+            is_block: dev.is_block_dev(),
+            open_type: dev.open_type(),
+            open_num: dev.open_num(),
+            parent: dev.parent(),
+        });
+    }
+    {
+        let list = FAST_DEVICE_LIST.lock().unwrap();
+        for name in list.keys().filter(|n| under(n)) {
+            ret.push(DeviceInfo {
+                name: name.clone(),
+                class: DeviceClass::Fast,
+                is_block: false,
+                open_type: None,
+                open_num: 0,
+                parent: None,
+            });
+        }
+    }
+    ret.into_iter()
+}
+
+// 设备树中的一个节点
+#[derive(Debug, Clone)]
+pub struct DeviceNode {
+    pub info: DeviceInfo,
+    pub children: Vec<DeviceNode>,
+}
+
+impl DeviceNode {
+    fn fmt_level(&self, f: &mut Formatter<'_>, level: usize) -> core::fmt::Result {
+        writeln!(f, "{:width$}{}", "", self.info, width = level * 2)?;
+        for c in self.children.iter() {
+            c.fmt_level(f, level + 1)?;
+        }
+        Ok(())
+    }
+}
+
+// 每个子设备比父设备多缩进两格
+impl Display for DeviceNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.fmt_level(f, 0)
+    }
+}
+
+// 以父子关系组织的设备列表，返回所有没有父设备的设备
+pub fn device_tree() -> Vec<DeviceNode> {
+    let mut all: Vec<DeviceInfo> = list_device().collect();
+    take_children(&mut all, None)
+}
+
+fn take_children(all: &mut Vec<DeviceInfo>, parent: Option<&str>) -> Vec<DeviceNode> {
+    let mut nodes = Vec::new();
+    let mut i = 0;
+    while i < all.len() {
+        if all[i].parent.as_deref() == parent {
+            nodes.push(all.remove(i));
+        } else {
+            i += 1;
+        }
+    }
+    nodes
+        .into_iter()
+        .map(|info| {
+            let children = take_children(all, Some(info.name.as_str()));
+            DeviceNode { info, children }
+        })
+        .collect()
 }
 
 // 快设备：没有任何框架
 // 利用反射，从注册的链表中得到之前注册的设备
-// 操作也仅能使用该类型提供的操作
+// 操作也仅能使用该类型提供的操作，名称可以是别名
 pub fn take_fast_dev<T: Send + 'static>(name: &str) -> Result<FastDev<T>, IOError> {
+    let name = resolve(name);
+    let name = name.as_str();
     let mut list = FAST_DEVICE_LIST.lock().unwrap();
     let dev = list.remove(name);
     return match dev {
@@ -70,48 +299,350 @@ pub fn take_fast_dev<T: Send + 'static>(name: &str) -> Result<FastDev<T>, IOErro
 }
 
 // 释放快设备，将设备归还到全局链表中
-pub fn release_fast_dev<T: Send + 'static>(dev: FastDev<T>) {
+// 同名的设备已经被重新注册时返回 RegisterError
+pub fn release_fast_dev<T: Send + 'static>(dev: FastDev<T>) -> Result<(), IOError> {
     let FastDev {
         dev: raw_dev,
         name: raw_name,
     } = dev;
-    register_fast_device(raw_dev, &raw_name).unwrap();
+    register_fast_device(raw_dev, &raw_name)
 }
 
 pub fn raw_open(dev: &Arc<Mutex<Driver>>, f: &OpenFlag) -> Result<OpenType, IOError> {
     let mut inner_dev = dev.lock().unwrap();
 
+    if inner_dev.removed || inner_dev.unregistered {
+        return Err(IOError::RemovedError);
+    }
+    if !inner_dev.waiters.is_empty() {
+        // 已经有线程在排队等待打开，不能插队
+        return Err(IOError::OpenError);
+    }
+
+    try_open(&mut inner_dev, f)?.ok_or(IOError::OpenError)
+}
+
+// 打开设备并记录统计
+fn try_open(inner_dev: &mut Driver, f: &OpenFlag) -> Result<Option<OpenType>, IOError> {
+    let ret = raw_try_open(inner_dev, f);
+    match &ret {
+        Ok(Some(_)) => {
+            inner_dev.stats.opens = inner_dev.stats.opens.wrapping_add(1);
+            trace::emit(inner_dev, TraceOp::Open, 0, TraceData::None, Ok(()));
+        }
+        Ok(None) => {}
+        Err(e) => {
+            inner_dev.stats.record_error(e);
+            trace::emit(inner_dev, TraceOp::Open, 0, TraceData::None, Err(e));
+        }
+    }
+    ret
+}
+
+// 等待打开：设备被占用时在设备的等待队列中排队，按先来后到的顺序打开
+// timeout 单位为 ms，None 表示一直等待
+pub fn raw_open_wait(
+    dev: &Arc<Mutex<Driver>>,
+    f: &OpenFlag,
+    timeout: Option<u32>,
+) -> Result<OpenType, IOError> {
+    let sem = Arc::new(Os::semaphore().map_err(|_| IOError::OpenError)?);
+    let ticket = {
+        let mut inner_dev = dev.lock().unwrap();
+        if inner_dev.removed || inner_dev.unregistered {
+            return Err(IOError::RemovedError);
+        }
+        let t = inner_dev.next_ticket;
+        inner_dev.next_ticket = t.wrapping_add(1);
+        inner_dev.waiters.push_back((t, sem.clone()));
+        t
+    };
+
+    let start = Os::tick_ms();
+    loop {
+        {
+            let mut inner_dev = dev.lock().unwrap();
+            let ret = if inner_dev.removed || inner_dev.unregistered {
+                Err(IOError::RemovedError)
+            } else if inner_dev.waiters.front().map(|w| w.0) == Some(ticket) {
+                try_open(&mut inner_dev, f)
+            } else {
+                Ok(None)
+            };
+            match ret {
+                Ok(Some(ot)) => {
+                    inner_dev.waiters.pop_front();
+                    // 共享打开时后面的等待者也可能可以打开
+                    inner_dev.wake_open_waiter();
+                    return Ok(ot);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    leave_wait(&mut inner_dev, ticket);
+                    return Err(e);
+                }
+            }
+        }
+
+        // 在设备锁之外睡眠，设备关闭或者前面的等待者离开时被唤醒
+        let woken = match timeout {
+            None => sem.take_wait_forever(),
+            Some(t) => match t.checked_sub(Os::tick_ms().wrapping_sub(start)) {
+                Some(left) if left > 0 => sem.take_wait(left),
+                _ => Err(()),
+            },
+        };
+        if woken.is_err() {
+            leave_wait(&mut dev.lock().unwrap(), ticket);
+            return Err(IOError::Timeout);
+        }
+    }
+}
+
+// 离开等待队列，排在最前面时让下一个等待者尝试打开
+fn leave_wait(inner_dev: &mut Driver, ticket: u32) {
+    let first = inner_dev.waiters.front().map(|w| w.0) == Some(ticket);
+    inner_dev.waiters.retain(|w| w.0 != ticket);
+    if first {
+        inner_dev.wake_open_waiter();
+    }
+}
+
+// 设备被占用时返回 Ok(None)
+fn raw_try_open(inner_dev: &mut Driver, f: &OpenFlag) -> Result<Option<OpenType>, IOError> {
     return if f.get_only() {
         if !(inner_dev.open_able) {
             // 当前已经被独占
-            Err(IOError::OpenError)
+            Ok(None)
         } else if inner_dev.open_num != 0 {
             // 已经被非独占打开
-            Err(IOError::OpenError)
+            Ok(None)
         } else {
             inner_dev.open_able = false;
-            inner_dev.ops.open(f).map_err(|e| {
+            first_open(inner_dev, f).map_err(|e| {
                 inner_dev.open_able = true;
                 e
             })?;
-            Ok(OpenType::Only)
+            Ok(Some(OpenType::Only))
         }
     } else {
         if !(inner_dev.open_able) {
             // 当前已经被独占
-            Err(IOError::OpenError)
+            Ok(None)
         } else if inner_dev.open_num == 0 {
             // 第一次打开
             inner_dev.open_num = 1;
-            inner_dev.ops.open(f).map_err(|e| {
+            first_open(inner_dev, f).map_err(|e| {
                 inner_dev.open_num = 0;
                 e
             })?;
-            Ok(OpenType::Master)
+            Ok(Some(OpenType::Master))
         } else {
             // 已经执行过打开函数，不需要再次执行
             inner_dev.open_num += 1;
-            Ok(OpenType::User)
+            Ok(Some(OpenType::User))
         }
     };
 }
+
+// 执行驱动的打开函数，有父设备时先共享打开父设备
+// 锁的顺序总是先子设备后父设备
+fn first_open(inner_dev: &mut Driver, f: &OpenFlag) -> Result<(), IOError> {
+    let parent = match &inner_dev.parent {
+        Some(p) => Some(open_static(p, &OpenFlag::zero())?),
+        None => None,
+    };
+    // 打开失败时 parent 在此处被释放，父设备随之关闭
+    inner_dev.ops.open(f)?;
+    inner_dev.parent_guard = parent;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use super::*;
+    use crate::bsp::host_sim::testing::count_dev;
+    use crate::driver::DriverOps;
+    use core::sync::atomic::Ordering;
+
+    #[test]
+    fn shared_open_close() {
+        let (opens, closes) = count_dev("test/api/shared");
+        let dev = find("test/api/shared").unwrap();
+        let a = dev.open(&OpenFlag::zero()).unwrap();
+        let b = dev.open(&OpenFlag::zero()).unwrap();
+        assert!(a.is_master());
+        assert!(b.is_user());
+        assert_eq!(dev.lock().unwrap().open_num(), 2);
+        // 独占打开需要等所有共享者关闭
+        assert!(matches!(
+            dev.open(OpenFlag::zero().set_only(true)),
+            Err(IOError::OpenError)
+        ));
+        drop(a);
+        assert_eq!(closes.load(Ordering::SeqCst), 0);
+        drop(b);
+        assert_eq!(opens.load(Ordering::SeqCst), 1);
+        assert_eq!(closes.load(Ordering::SeqCst), 1);
+        assert_eq!(dev.lock().unwrap().open_type(), None);
+    }
+
+    #[test]
+    fn only_open_close() {
+        let (opens, closes) = count_dev("test/api/only");
+        let dev = find("test/api/only").unwrap();
+        let g = dev.open(OpenFlag::zero().set_only(true)).unwrap();
+        assert!(g.is_only());
+        assert!(matches!(
+            dev.open(&OpenFlag::zero()),
+            Err(IOError::OpenError)
+        ));
+        drop(g);
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        assert!(g.is_master());
+        drop(g);
+        assert_eq!(opens.load(Ordering::SeqCst), 2);
+        assert_eq!(closes.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn fast_dev_name_check() {
+        use crate::alloc::boxed::Box;
+        assert!(register_fast_device(Box::new(1u32), "").is_err());
+        assert!(register_fast_device(Box::new(1u32), "test//fast").is_err());
+        count_dev("test/fast/target");
+        set_alias("test/fast/alias", "test/fast/target").unwrap();
+        // 不能与别名同名，别名也不能与快设备同名
+        assert!(register_fast_device(Box::new(1u32), "test/fast/alias").is_err());
+        register_fast_device(Box::new(1u32), "test/fast/dev").unwrap();
+        assert!(set_alias("test/fast/dev", "test/fast/target").is_err());
+    }
+
+    #[test]
+    fn fast_dev_alias() {
+        use crate::alloc::boxed::Box;
+        register_fast_device(Box::new(7u32), "test/fast/real").unwrap();
+        set_alias("test/fast/short", "test/fast/real").unwrap();
+        let dev = take_fast_dev::<u32>("test/fast/short").unwrap();
+        assert_eq!(**dev, 7);
+        assert!(take_fast_dev::<u32>("test/fast/real").is_err());
+        // 归还时使用真实名称
+        release_fast_dev(dev).unwrap();
+        assert!(take_fast_dev::<u32>("test/fast/real").is_ok());
+    }
+
+    #[test]
+    fn find_missing() {
+        assert!(matches!(find("test/api/none"), Err(IOError::FindError)));
+    }
+
+    #[test]
+    fn list_and_open_type() {
+        count_dev("test/list/a");
+        count_dev("test/list/a/b");
+        count_dev("test/lista");
+        let names: Vec<String> = list_prefix("test/list/").map(|d| d.name).collect();
+        assert_eq!(names, ["test/list/a", "test/list/a/b"]);
+
+        let dev = find("test/list/a").unwrap();
+        let info = || list_prefix("test/list/a").next().unwrap();
+        assert_eq!(info().open_type, None);
+        let a = dev.open(&OpenFlag::zero()).unwrap();
+        assert_eq!(info().open_type, Some(OpenType::Master));
+        let b = dev.open(&OpenFlag::zero()).unwrap();
+        assert_eq!(info().open_type, Some(OpenType::User));
+        assert_eq!(info().open_num, 2);
+        drop((a, b));
+        let g = dev.open(OpenFlag::zero().set_only(true)).unwrap();
+        assert_eq!(info().open_type, Some(OpenType::Only));
+        drop(g);
+    }
+
+    // 某个设备的锁被长时间持有时，枚举不能阻塞其他使用设备链表的操作
+    #[test]
+    fn list_does_not_hold_registry() {
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        count_dev("test/busy/dev");
+        let dev = find("test/busy/dev").unwrap();
+        let held = dev.lock().unwrap();
+        let lister = std::thread::spawn(|| list_prefix("test/busy").count());
+        std::thread::sleep(Duration::from_millis(20));
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || tx.send(find("test/busy/dev").is_ok()).unwrap());
+        assert_eq!(rx.recv_timeout(Duration::from_secs(2)), Ok(true));
+        drop(held);
+        assert_eq!(lister.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn open_wait_in_order() {
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        count_dev("test/wait/order");
+        let dev = find("test/wait/order").unwrap();
+        let g = dev.open(OpenFlag::zero().set_only(true)).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let mut waiters = Vec::new();
+        for i in 0..2 {
+            let tx = tx.clone();
+            waiters.push(std::thread::spawn(move || {
+                let dev = find("test/wait/order").unwrap();
+                let g = dev.open_wait(OpenFlag::zero().set_only(true), None);
+                tx.send(i).unwrap();
+                std::thread::sleep(Duration::from_millis(10));
+                g.is_ok()
+            }));
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        // 设备被占用时等待者都在睡眠
+        assert!(rx.try_recv().is_err());
+        // 普通打开不能插队
+        assert!(matches!(
+            dev.open(&OpenFlag::zero()),
+            Err(IOError::OpenError)
+        ));
+        drop(g);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(2)), Ok(0));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(2)), Ok(1));
+        for w in waiters {
+            assert!(w.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn open_wait_timeout() {
+        count_dev("test/wait/tmo");
+        let dev = find("test/wait/tmo").unwrap();
+        let g = dev.open(OpenFlag::zero().set_only(true)).unwrap();
+        assert!(matches!(
+            dev.open_wait(&OpenFlag::zero(), Some(10)),
+            Err(IOError::Timeout)
+        ));
+        // 超时的等待者已经离开队列
+        assert!(dev.lock().unwrap().waiters.is_empty());
+        drop(g);
+        assert!(dev.open(&OpenFlag::zero()).is_ok());
+    }
+
+    #[test]
+    fn open_wait_unregister() {
+        use crate::device::{unregister_device_with, UnregisterMode};
+        use std::time::Duration;
+
+        count_dev("test/wait/unreg");
+        let dev = find("test/wait/unreg").unwrap();
+        let g = dev.open(OpenFlag::zero().set_only(true)).unwrap();
+        let waiter = std::thread::spawn(|| {
+            let dev = find("test/wait/unreg").unwrap();
+            let ret = dev.open_wait(&OpenFlag::zero(), None);
+            matches!(ret, Err(IOError::RemovedError))
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        unregister_device_with("test/wait/unreg", UnregisterMode::Deferred).unwrap();
+        assert!(waiter.join().unwrap());
+        drop(g);
+    }
+}
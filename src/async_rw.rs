@@ -1,40 +1,168 @@
+use crate::alloc::boxed::Box;
+use crate::alloc::sync::Arc;
+use crate::alloc::vec::Vec;
 use crate::data::{StdData, ToMakeStdData};
-use crate::driver::{DriverAsyncHelper, DriverOps};
+use crate::driver::{Driver, DriverAsyncHelper, DriverOps};
 use crate::error::IOError;
-use crate::guard::DriverGuard;
+use crate::guard::{DriverGuard, OwnedDriverGuard};
+use crate::os::{Os, OsApi};
+use crate::Mutex;
 use core::future::Future;
+use core::ops::Deref;
 use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use core::task::{Context, Poll};
-use rtt_rs::embassy_async::executor::device_wake;
+use futures_core::Stream;
+
+// future 持有 guard 的方式
+// 持有 OwnedDriverGuard 时 future 是 'static 的，可以被 spawn
+pub(crate) enum GuardRef<'a, 'c> {
+    Borrowed(&'a DriverGuard<'c>),
+    Owned(OwnedDriverGuard),
+}
+
+impl<'a, 'c> Deref for GuardRef<'a, 'c> {
+    type Target = DriverGuard<'c>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            GuardRef::Borrowed(g) => g,
+            GuardRef::Owned(g) => g,
+        }
+    }
+}
+
+pub(crate) enum DataRef<'b> {
+    Borrowed(&'b (dyn ToMakeStdData + Sync)),
+    Owned(Box<dyn ToMakeStdData + Send + Sync>),
+}
+
+impl DataRef<'_> {
+    fn get(&self) -> &dyn ToMakeStdData {
+        match self {
+            DataRef::Borrowed(d) => *d,
+            DataRef::Owned(d) => d.as_ref(),
+        }
+    }
+}
+
+// 区分同一设备上的不同 future，从 1 开始分配
+static NEXT_WAITER_ID: AtomicUsize = AtomicUsize::new(1);
+
+// future 在设备中的注册状态
+// 重复 poll 时使用同一个 id，设备只保留一个 waker
+// 被丢弃时从设备中移除还没有被通知的 waker
+pub(crate) struct Waiter {
+    id: usize,
+    write: bool,
+    // 注册过 waker 的设备
+    dev: Option<Arc<Mutex<Driver>>>,
+}
+
+impl Waiter {
+    fn new(write: bool) -> Waiter {
+        Waiter {
+            id: NEXT_WAITER_ID.fetch_add(1, Ordering::Relaxed),
+            write,
+            dev: None,
+        }
+    }
+
+    pub(crate) fn read() -> Waiter {
+        Waiter::new(false)
+    }
+
+    pub(crate) fn write() -> Waiter {
+        Waiter::new(true)
+    }
+
+    // 读缓冲区中至少有 need 个字节时被唤醒，写时忽略 need
+    fn register(
+        &mut self,
+        g: &DriverGuard<'_>,
+        need: usize,
+        cx: &Context<'_>,
+    ) -> Result<(), IOError> {
+        let raw: &Arc<Mutex<Driver>> = &g.raw;
+        if self.write {
+            raw.register_write_callback(self.id, Os::device_wake, cx.waker())?;
+        } else {
+            raw.register_read_callback(self.id, need, Os::device_wake, cx.waker())?;
+        }
+        if self.dev.is_none() {
+            self.dev = Some(raw.clone());
+        }
+        Ok(())
+    }
+
+    // 完成时移除可能残留的 waker，如被其他原因唤醒后读到了数据
+    fn clear(&mut self) {
+        if let Some(dev) = self.dev.take() {
+            if self.write {
+                dev.unregister_write_callback(self.id);
+            } else {
+                dev.unregister_read_callback(self.id);
+            }
+        }
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+// 等待数据到来，出错时结束 future
+fn wait<T>(
+    waiter: &mut Waiter,
+    g: &DriverGuard<'_>,
+    need: usize,
+    cx: &Context<'_>,
+) -> Poll<Result<T, IOError>> {
+    match waiter.register(g, need, cx) {
+        Ok(_) => Poll::Pending,
+        Err(e) => finish(waiter, Err(e)),
+    }
+}
+
+fn finish<T>(waiter: &mut Waiter, ret: Result<T, IOError>) -> Poll<Result<T, IOError>> {
+    waiter.clear();
+    Poll::Ready(ret)
+}
+
+// 不等待的读，没有数据时返回 0
+fn try_read(g: &DriverGuard<'_>, address: usize, buf: &mut [u8]) -> Result<usize, IOError> {
+    match g.read_into(address, buf) {
+        Err(IOError::ReadEmpty) => Ok(0),
+        r => r,
+    }
+}
 
 pub struct AsyncReadFuture<'a, 'c>(
-    pub(crate) &'a DriverGuard<'c>,
+    pub(crate) GuardRef<'a, 'c>,
     pub(crate) usize,
     pub(crate) u32,
+    pub(crate) Waiter,
 );
 pub struct AsyncWriteFuture<'a, 'b, 'c>(
-    pub(crate) &'a DriverGuard<'c>,
+    pub(crate) GuardRef<'a, 'c>,
     pub(crate) usize, // address
-    pub(crate) &'b dyn ToMakeStdData,
+    pub(crate) DataRef<'b>,
+    pub(crate) Waiter,
 );
 
 impl<'a, 'c> Future for AsyncReadFuture<'a, 'c> {
     type Output = Result<StdData, IOError>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        return match self.0.read(self.1, self.2) {
-            Ok(a) => match a {
-                StdData::Null => {
-                    self.0
-                        .raw
-                        .register_read_callback(device_wake, cx.waker().clone())
-                        .unwrap();
-                    Poll::Pending
-                }
-                _ => Poll::Ready(Ok(a)),
-            },
-            Err(_) => Poll::Ready(Err(IOError::ReadError)),
-        };
+        let this = self.get_mut();
+        match this.0.read(this.1, this.2) {
+            Ok(StdData::Null) => wait(&mut this.3, &this.0, 1, cx),
+            // 与读到 Null 相同，等待数据到来
+            Err(IOError::ReadEmpty) => wait(&mut this.3, &this.0, 1, cx),
+            ret => finish(&mut this.3, ret),
+        }
     }
 }
 
@@ -42,18 +170,449 @@ impl<'a, 'b, 'c> Future for AsyncWriteFuture<'a, 'b, 'c> {
     type Output = Result<(), IOError>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        return match self.0.write(self.1, self.2) {
-            Ok(_) => Poll::Ready(Ok(())),
-            Err(b) => match b {
-                IOError::WriteBusy => {
-                    self.0
-                        .raw
-                        .register_write_callback(device_wake, cx.waker().clone())
-                        .unwrap();
-                    Poll::Pending
+        let this = self.get_mut();
+        match this.0.write(this.1, this.2.get()) {
+            Err(IOError::WriteBusy) => wait(&mut this.3, &this.0, 0, cx),
+            ret => finish(&mut this.3, ret),
+        }
+    }
+}
+
+// 以下的 future 和 stream 使用 read_into 读取，设备需要以中断读或异步读的方式打开
+// 没有数据时在接收中断中被唤醒
+
+// 读满 len 个字节
+pub struct ReadExactFuture<'a, 'c> {
+    g: GuardRef<'a, 'c>,
+    address: usize,
+    buf: Vec<u8>,
+    got: usize,
+    waiter: Waiter,
+}
+
+impl<'a, 'c> ReadExactFuture<'a, 'c> {
+    pub(crate) fn new(g: GuardRef<'a, 'c>, address: usize, len: usize) -> Self {
+        ReadExactFuture {
+            g,
+            address,
+            buf: alloc::vec![0; len],
+            got: 0,
+            waiter: Waiter::read(),
+        }
+    }
+}
+
+impl Future for ReadExactFuture<'_, '_> {
+    type Output = Result<Vec<u8>, IOError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        while this.got < this.buf.len() {
+            let n = match try_read(&this.g, this.address, &mut this.buf[this.got..]) {
+                Ok(n) => n,
+                Err(e) => return finish(&mut this.waiter, Err(e)),
+            };
+            if n == 0 {
+                // 剩余的数据全部到达后再唤醒
+                let need = this.buf.len() - this.got;
+                return wait(&mut this.waiter, &this.g, need, cx);
+            }
+            this.got += n;
+        }
+        let buf = core::mem::take(&mut this.buf);
+        finish(&mut this.waiter, Ok(buf))
+    }
+}
+
+// 读到 delim 为止，返回的数据包含 delim
+// 读到 max 个字节仍没有 delim 时返回 IOError::DataError，已经读出的数据被丢弃
+pub struct ReadUntilFuture<'a, 'c> {
+    g: GuardRef<'a, 'c>,
+    address: usize,
+    delim: u8,
+    max: usize,
+    buf: Vec<u8>,
+    waiter: Waiter,
+}
+
+impl<'a, 'c> ReadUntilFuture<'a, 'c> {
+    pub(crate) fn new(g: GuardRef<'a, 'c>, address: usize, delim: u8, max: usize) -> Self {
+        ReadUntilFuture {
+            g,
+            address,
+            delim,
+            max,
+            buf: Vec::new(),
+            waiter: Waiter::read(),
+        }
+    }
+}
+
+impl Future for ReadUntilFuture<'_, '_> {
+    type Output = Result<Vec<u8>, IOError>;
+
+    // 逐字节读取，不会多读 delim 之后的数据
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            let mut ch = [0u8];
+            match try_read(&this.g, this.address, &mut ch) {
+                Ok(0) => return wait(&mut this.waiter, &this.g, 1, cx),
+                Ok(_) => {}
+                Err(e) => return finish(&mut this.waiter, Err(e)),
+            }
+            this.buf.push(ch[0]);
+            if ch[0] == this.delim {
+                let buf = core::mem::take(&mut this.buf);
+                return finish(&mut this.waiter, Ok(buf));
+            }
+            if this.buf.len() >= this.max {
+                this.buf = Vec::new();
+                return finish(&mut this.waiter, Err(IOError::DataError));
+            }
+        }
+    }
+}
+
+// 每次从设备读取的字节数
+const STREAM_BUF_SIZE: usize = 16;
+
+// 逐字节的数据流，出错时流结束，错误可以通过 take_error 取得
+pub struct ByteStream<'a, 'c> {
+    g: GuardRef<'a, 'c>,
+    address: usize,
+    buf: [u8; STREAM_BUF_SIZE],
+    pos: usize,
+    len: usize,
+    err: Option<IOError>,
+    done: bool,
+    waiter: Waiter,
+}
+
+impl<'a, 'c> ByteStream<'a, 'c> {
+    pub(crate) fn new(g: GuardRef<'a, 'c>, address: usize) -> Self {
+        ByteStream {
+            g,
+            address,
+            buf: [0; STREAM_BUF_SIZE],
+            pos: 0,
+            len: 0,
+            err: None,
+            done: false,
+            waiter: Waiter::read(),
+        }
+    }
+
+    pub fn take_error(&mut self) -> Option<IOError> {
+        self.err.take()
+    }
+}
+
+impl Stream for ByteStream<'_, '_> {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<u8>> {
+        let this = self.get_mut();
+        if this.pos == this.len {
+            if this.done {
+                return Poll::Ready(None);
+            }
+            let n = match try_read(&this.g, this.address, &mut this.buf) {
+                Ok(0) => match wait::<()>(&mut this.waiter, &this.g, 1, cx) {
+                    Poll::Ready(Err(e)) => {
+                        this.err = Some(e);
+                        0
+                    }
+                    _ => return Poll::Pending,
+                },
+                Ok(n) => n,
+                Err(e) => {
+                    this.err = Some(e);
+                    0
                 }
-                _ => Poll::Ready(Err(b)),
+            };
+            if n == 0 {
+                this.done = true;
+                this.waiter.clear();
+                return Poll::Ready(None);
+            }
+            this.pos = 0;
+            this.len = n;
+        }
+        this.pos += 1;
+        Poll::Ready(Some(this.buf[this.pos - 1]))
+    }
+}
+
+// 每次返回当前已经到达的数据，最多 max 个字节
+// 先读到栈上的缓冲区，有数据时才按读到的长度分配返回的 Vec
+// 出错时流结束，错误可以通过 take_error 取得
+pub struct ChunkStream<'a, 'c> {
+    g: GuardRef<'a, 'c>,
+    address: usize,
+    max: usize,
+    err: Option<IOError>,
+    done: bool,
+    waiter: Waiter,
+}
+
+impl<'a, 'c> ChunkStream<'a, 'c> {
+    pub(crate) fn new(g: GuardRef<'a, 'c>, address: usize, max: usize) -> Self {
+        ChunkStream {
+            g,
+            address,
+            max,
+            err: None,
+            done: false,
+            waiter: Waiter::read(),
+        }
+    }
+
+    pub fn take_error(&mut self) -> Option<IOError> {
+        self.err.take()
+    }
+}
+
+impl Stream for ChunkStream<'_, '_> {
+    type Item = Vec<u8>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        let mut tmp = [0u8; STREAM_BUF_SIZE];
+        let want = this.max.min(STREAM_BUF_SIZE);
+        let n = match try_read(&this.g, this.address, &mut tmp[..want]) {
+            Ok(0) => match wait::<()>(&mut this.waiter, &this.g, 1, cx) {
+                Poll::Ready(Err(e)) => Err(e),
+                _ => return Poll::Pending,
             },
+            r => r,
         };
+        let n = match n {
+            Ok(n) => n,
+            Err(e) => {
+                this.err = Some(e);
+                this.done = true;
+                this.waiter.clear();
+                return Poll::Ready(None);
+            }
+        };
+        // 按实际读到的长度分配，更多的数据到达时再增长
+        let mut buf = tmp[..n].to_vec();
+        while buf.len() < this.max {
+            let want = (this.max - buf.len()).min(STREAM_BUF_SIZE);
+            match try_read(&this.g, this.address, &mut tmp[..want]) {
+                Ok(0) => break,
+                Ok(n) => buf.extend_from_slice(&tmp[..n]),
+                // 先返回已经读到的数据，下一次 poll 时流结束
+                Err(e) => {
+                    this.err = Some(e);
+                    this.done = true;
+                    this.waiter.clear();
+                    break;
+                }
+            }
+        }
+        Poll::Ready(Some(buf))
+    }
+}
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use crate::alloc::vec::Vec;
+    use crate::api::{find, DevOpen};
+    use crate::bsp::host_sim::testing::{poll, sim_uart, CountWaker};
+    use crate::bsp::host_sim::uart::uart_inject_rx;
+    use crate::device::{register_device, DeviceOps};
+    use crate::driver::DriverOps;
+    use crate::{IOError, OpenFlag, StdData, ToMakeStdData};
+    use core::pin::Pin;
+    use core::task::{Context, Poll, Waker};
+    use futures_core::Stream;
+
+    #[test]
+    fn async_read_wakes_on_rx() {
+        let num = sim_uart("test/async/read");
+        let dev = find("test/async/read").unwrap();
+        let g = dev.open(OpenFlag::zero().set_read_async(true)).unwrap();
+        let (c, w) = CountWaker::new();
+        let mut f = g.async_read(0, 1).unwrap();
+        assert!(poll(&mut f, &w).is_pending());
+        assert_eq!(c.count(), 0);
+        uart_inject_rx(num, b"a");
+        assert_eq!(c.count(), 1);
+        match poll(&mut f, &w) {
+            Poll::Ready(Ok(StdData::U32(a))) => assert_eq!(a, b'a' as u32),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn async_read_ready_data() {
+        let num = sim_uart("test/async/ready");
+        let dev = find("test/async/ready").unwrap();
+        let g = dev.open(OpenFlag::zero().set_read_async(true)).unwrap();
+        uart_inject_rx(num, b"b");
+        let (c, w) = CountWaker::new();
+        let mut f = g.async_read(0, 1).unwrap();
+        assert!(matches!(poll(&mut f, &w), Poll::Ready(Ok(StdData::U32(_)))));
+        assert_eq!(c.count(), 0);
+    }
+
+    #[test]
+    fn async_read_exclusive() {
+        sim_uart("test/async/only");
+        let dev = find("test/async/only").unwrap();
+        let g = dev
+            .open(OpenFlag::zero().set_only(true).set_read_async(true))
+            .unwrap();
+        // 独占打开时不能进行异步读写
+        assert!(matches!(g.async_read(0, 1), Err(IOError::ReadError)));
+    }
+
+    #[test]
+    fn dropped_future_unregisters() {
+        let num = sim_uart("test/async/drop");
+        let dev = find("test/async/drop").unwrap();
+        let g = dev.open(OpenFlag::zero().set_read_async(true)).unwrap();
+        let (c1, w1) = CountWaker::new();
+        let mut f = g.async_read(0, 1).unwrap();
+        assert!(poll(&mut f, &w1).is_pending());
+        drop(f);
+        let (c2, w2) = CountWaker::new();
+        let mut f = g.async_read(0, 1).unwrap();
+        assert!(poll(&mut f, &w2).is_pending());
+        uart_inject_rx(num, b"a");
+        // 丢弃的 future 不再被唤醒
+        assert_eq!(c1.count(), 0);
+        assert_eq!(c2.count(), 1);
+    }
+
+    #[test]
+    fn repoll_keeps_one_waker() {
+        let num = sim_uart("test/async/repoll");
+        let dev = find("test/async/repoll").unwrap();
+        let g = dev.open(OpenFlag::zero().set_read_async(true)).unwrap();
+        let (c1, w1) = CountWaker::new();
+        let (c2, w2) = CountWaker::new();
+        let mut f = g.async_read(0, 1).unwrap();
+        for _ in 0..3 {
+            assert!(poll(&mut f, &w1).is_pending());
+        }
+        // 换了 waker 后只唤醒最后一次 poll 的 waker
+        assert!(poll(&mut f, &w2).is_pending());
+        uart_inject_rx(num, b"a");
+        assert_eq!(c1.count(), 0);
+        assert_eq!(c2.count(), 1);
+        assert!(poll(&mut f, &w2).is_ready());
+        uart_inject_rx(num, b"b");
+        assert_eq!(c2.count(), 1);
+    }
+
+    #[test]
+    fn wake_every_ready_waiter() {
+        let num = sim_uart("test/async/all");
+        let dev = find("test/async/all").unwrap();
+        let g = dev.open(OpenFlag::zero().set_read_async(true)).unwrap();
+        let (c1, w1) = CountWaker::new();
+        let (c2, w2) = CountWaker::new();
+        let (c3, w3) = CountWaker::new();
+        let mut f1 = g.async_read(0, 1).unwrap();
+        let mut f2 = g.async_read(0, 1).unwrap();
+        let mut f3 = g.read_exact(0, 3).unwrap();
+        assert!(poll(&mut f1, &w1).is_pending());
+        assert!(poll(&mut f2, &w2).is_pending());
+        assert!(poll(&mut f3, &w3).is_pending());
+        // 一个字节唤醒所有只需要一个字节的等待者，read_exact 等剩余的数据到齐
+        uart_inject_rx(num, b"a");
+        assert_eq!((c1.count(), c2.count(), c3.count()), (1, 1, 0));
+        uart_inject_rx(num, b"bc");
+        assert_eq!(c3.count(), 1);
+        assert!(matches!(poll(&mut f3, &w3), Poll::Ready(Ok(ref v)) if v == b"abc"));
+    }
+
+    // 没有数据但不支持异步通知的设备
+    struct NullDev;
+
+    impl DeviceOps for NullDev {
+        fn open(&self, _flag: &OpenFlag) -> Result<(), IOError> {
+            Ok(())
+        }
+        fn read(&self, _len: u32) -> Result<StdData, IOError> {
+            Ok(StdData::Null)
+        }
+        fn close(&self) -> Result<(), IOError> {
+            Ok(())
+        }
+        fn control(&self, _data: &dyn ToMakeStdData) -> Result<(), IOError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn register_error_ends_future() {
+        register_device(NullDev, "test/async/null").unwrap();
+        let dev = find("test/async/null").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        let (_c, w) = CountWaker::new();
+        let mut f = g.async_read(0, 1).unwrap();
+        assert!(matches!(
+            poll(&mut f, &w),
+            Poll::Ready(Err(IOError::UnsupportedError))
+        ));
+    }
+
+    fn next<S: Stream + Unpin>(s: &mut S, w: &Waker) -> Poll<Option<S::Item>> {
+        Pin::new(s).poll_next(&mut Context::from_waker(w))
+    }
+
+    #[test]
+    fn read_until_max() {
+        let num = sim_uart("test/async/until");
+        let dev = find("test/async/until").unwrap();
+        let g = dev.open(OpenFlag::zero().set_read_async(true)).unwrap();
+        assert!(matches!(g.read_until(0, b'\n', 0), Err(IOError::DataError)));
+        let (_c, w) = CountWaker::new();
+        let mut f = g.read_until(0, b'\n', 4).unwrap();
+        assert!(poll(&mut f, &w).is_pending());
+        // 读满 max 个字节仍没有 delim，之后的数据留在缓冲区中
+        uart_inject_rx(num, b"abcdef\n");
+        assert!(matches!(
+            poll(&mut f, &w),
+            Poll::Ready(Err(IOError::DataError))
+        ));
+        let mut f = g.read_until(0, b'\n', 4).unwrap();
+        assert!(matches!(poll(&mut f, &w), Poll::Ready(Ok(ref v)) if v == b"ef\n"));
+        // delim 正好是第 max 个字节
+        uart_inject_rx(num, b"xyz\n");
+        let mut f = g.read_until(0, b'\n', 4).unwrap();
+        assert!(matches!(poll(&mut f, &w), Poll::Ready(Ok(ref v)) if v == b"xyz\n"));
+    }
+
+    #[test]
+    fn chunks_across_stack_buf() {
+        let num = sim_uart("test/async/chunks");
+        let dev = find("test/async/chunks").unwrap();
+        let g = dev.open(OpenFlag::zero().set_read_async(true)).unwrap();
+        let (c, w) = CountWaker::new();
+        let mut s = g.chunks(0, 40).unwrap();
+        for _ in 0..3 {
+            assert!(next(&mut s, &w).is_pending());
+        }
+        let data: Vec<u8> = (0..50).collect();
+        uart_inject_rx(num, &data);
+        assert_eq!(c.count(), 1);
+        // 超过栈上缓冲区的数据在一次 poll 中读完，最多 max 个字节
+        assert!(matches!(next(&mut s, &w), Poll::Ready(Some(ref v)) if v[..] == data[..40]));
+        assert!(matches!(next(&mut s, &w), Poll::Ready(Some(ref v)) if v[..] == data[40..]));
+        assert!(next(&mut s, &w).is_pending());
+        assert!(s.take_error().is_none());
+        // 只到达一个字节时不按 max 分配
+        uart_inject_rx(num, b"z");
+        assert!(
+            matches!(next(&mut s, &w), Poll::Ready(Some(ref v)) if v == b"z" && v.capacity() < 40)
+        );
     }
 }
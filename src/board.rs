@@ -0,0 +1,512 @@
+//! 板卡描述
+//! 用 board_table! 描述板卡上的外设和引脚，编译时为每个外设生成一项设备表
+//! 生成的设备表与 device_table! 的相同，由 register_tables 配置引脚并注册设备
+//! LED 和软件 I2C 总线只依赖 GPIO，由框架直接生成
+//! 串口、SPI 总线需要操作芯片的寄存器，由 BoardChip 的实现者构造
+//! 增加一个板卡变种只需要写一张新的描述表
+//!
+//! ```ignore
+//! board_table! {
+//!     chip: CHIP;
+//!     pub(crate) static BOARD = [
+//!         uart UartDesc { name: "uart1", num: 1, tx: Pin::new(Port::A, 9), rx: Pin::new(Port::A, 10), af: 7, alias: &["console"] },
+//!         led LedDesc { name: "led0", pin: Pin::new(Port::I, 8), active_low: true, pull: Pull::Up },
+//!     ];
+//! }
+//! ```
+
+use crate::alloc::boxed::Box;
+use crate::alloc::sync::Arc;
+use crate::device::i2c_bus::{I2CBusDev, I2CBusError, SBusI2C, SBusI2CBase, SBusI2CTrans};
+use crate::device::led::{DeviceLed, Led, LedError};
+use crate::device::register_device;
+use crate::error::IOError;
+use crate::{Mutex, OpenFlag};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Port {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pin {
+    pub port: Port,
+    pub num: u8,
+}
+
+impl Pin {
+    pub const fn new(port: Port, num: u8) -> Pin {
+        Pin { port, num }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Speed {
+    Low,
+    Medium,
+    High,
+    VeryHigh,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PinMode {
+    Input(Pull),
+    Output {
+        open_drain: bool,
+        pull: Pull,
+        speed: Speed,
+    },
+    Alternate {
+        af: u8,
+        open_drain: bool,
+        pull: Pull,
+        speed: Speed,
+    },
+}
+
+pub struct UartDesc {
+    pub name: &'static str,
+    // 芯片上的串口编号，如 USART1 为 1
+    pub num: u32,
+    pub tx: Pin,
+    pub rx: Pin,
+    // 引脚的复用功能编号
+    pub af: u8,
+    pub alias: &'static [&'static str],
+}
+
+pub struct LedDesc {
+    pub name: &'static str,
+    pub pin: Pin,
+    // 低电平点亮
+    pub active_low: bool,
+    // 引脚的上下拉，与板上 LED 的接法有关
+    pub pull: Pull,
+}
+
+pub struct SpiDesc {
+    pub name: &'static str,
+    pub num: u32,
+    pub sck: Pin,
+    pub miso: Pin,
+    pub mosi: Pin,
+    pub af: u8,
+    // 片选引脚，低电平有效，空闲时为高电平
+    // 第一个片选可以由总线驱动作为 BusSpi::cs 的默认片选
+    pub cs: &'static [Pin],
+}
+
+// 使用 GPIO 模拟的 I2C 总线
+pub struct I2cDesc {
+    pub name: &'static str,
+    pub scl: Pin,
+    pub sda: Pin,
+    // 半个时钟周期，单位 us
+    pub delay_us: u32,
+}
+
+// 总线先于挂在上面的设备注册
+pub const BUS_ORDER: u32 = 10;
+pub const DEV_ORDER: u32 = 20;
+
+// 芯片相关的操作，由 BSP 实现
+pub trait BoardChip {
+    // 配置引脚，需要同时打开端口的时钟
+    fn setup(&self, pin: Pin, mode: PinMode);
+    fn set(&self, pin: Pin, high: bool);
+    fn get(&self, pin: Pin) -> bool;
+    fn delay_us(&self, us: u32);
+
+    // 构造并注册串口设备，引脚已经配置完成
+    fn register_uart(&'static self, _desc: &'static UartDesc) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+    // 构造并注册 SPI 总线，引脚已经配置完成
+    // 没有 SPI 总线驱动的芯片使用默认实现，描述表中不能有 SPI 总线
+    fn register_spi(&'static self, _desc: &'static SpiDesc) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+}
+
+// 串口和 SPI 的复用引脚
+fn af_pin(af: u8) -> PinMode {
+    PinMode::Alternate {
+        af,
+        open_drain: false,
+        pull: Pull::None,
+        speed: Speed::High,
+    }
+}
+
+const CS_PIN: PinMode = PinMode::Output {
+    open_drain: false,
+    pull: Pull::None,
+    speed: Speed::Low,
+};
+
+const I2C_PIN: PinMode = PinMode::Output {
+    open_drain: true,
+    pull: Pull::Up,
+    speed: Speed::Low,
+};
+
+fn led_pin(pull: Pull) -> PinMode {
+    PinMode::Output {
+        open_drain: false,
+        pull,
+        speed: Speed::Low,
+    }
+}
+
+// 以下函数由 board_table! 生成的代码调用，配置引脚后注册设备
+
+pub fn register_i2c<C: BoardChip + Sync>(
+    chip: &'static C,
+    d: &'static I2cDesc,
+) -> Result<(), IOError> {
+    chip.setup(d.scl, I2C_PIN);
+    chip.setup(d.sda, I2C_PIN);
+    let bus = SBusI2C::new(GpioI2C {
+        chip,
+        desc: d,
+        init: AtomicBool::new(false),
+        num: AtomicU32::new(0),
+    });
+    let bus: Box<dyn SBusI2CTrans + Send> = Box::new(bus);
+    register_device(I2CBusDev::new(Arc::new(Mutex::new(bus).unwrap())), d.name)
+}
+
+pub fn register_spi<C: BoardChip + Sync>(
+    chip: &'static C,
+    d: &'static SpiDesc,
+) -> Result<(), IOError> {
+    chip.setup(d.sck, af_pin(d.af));
+    chip.setup(d.miso, af_pin(d.af));
+    chip.setup(d.mosi, af_pin(d.af));
+    for cs in d.cs {
+        chip.setup(*cs, CS_PIN);
+        chip.set(*cs, true);
+    }
+    chip.register_spi(d)
+}
+
+pub fn register_uart<C: BoardChip + Sync>(
+    chip: &'static C,
+    d: &'static UartDesc,
+) -> Result<(), IOError> {
+    chip.setup(d.tx, af_pin(d.af));
+    chip.setup(d.rx, af_pin(d.af));
+    chip.register_uart(d)
+}
+
+pub fn register_led<C: BoardChip + Sync>(
+    chip: &'static C,
+    d: &'static LedDesc,
+) -> Result<(), IOError> {
+    let led = GpioLed {
+        chip,
+        desc: d,
+        on: AtomicBool::new(false),
+    };
+    register_device(Led::new(led), d.name)
+}
+
+// 声明一张板卡设备表，chip 为实现了 BoardChip 的静态变量
+// 每一项由外设类型（uart、led、spi、i2c）和对应的描述组成，可以用 #[cfg(...)] 按 feature 选择
+// 总线的注册顺序为 BUS_ORDER，其他设备为 DEV_ORDER
+#[macro_export]
+macro_rules! board_table {
+    (
+        chip: $chip:path;
+        $vis:vis static $table:ident = [
+            $(
+                $(#[$m:meta])*
+                $kind:ident $desc:expr
+            ),* $(,)?
+        ];
+    ) => {
+        $vis static $table: &[$crate::init::DeviceEntry] = &[
+            $(
+                $(#[$m])*
+                $crate::board_table!(@$kind $chip, $desc),
+            )*
+        ];
+    };
+    (@uart $chip:path, $desc:expr) => {{
+        const D: $crate::board::UartDesc = $desc;
+        $crate::init::DeviceEntry {
+            name: D.name,
+            order: $crate::board::DEV_ORDER,
+            parent: None,
+            aliases: D.alias,
+            register: |_| $crate::board::register_uart(&$chip, &D),
+        }
+    }};
+    (@led $chip:path, $desc:expr) => {{
+        const D: $crate::board::LedDesc = $desc;
+        $crate::init::DeviceEntry {
+            name: D.name,
+            order: $crate::board::DEV_ORDER,
+            parent: None,
+            aliases: &[],
+            register: |_| $crate::board::register_led(&$chip, &D),
+        }
+    }};
+    (@spi $chip:path, $desc:expr) => {{
+        const D: $crate::board::SpiDesc = $desc;
+        $crate::init::DeviceEntry {
+            name: D.name,
+            order: $crate::board::BUS_ORDER,
+            parent: None,
+            aliases: &[],
+            register: |_| $crate::board::register_spi(&$chip, &D),
+        }
+    }};
+    (@i2c $chip:path, $desc:expr) => {{
+        const D: $crate::board::I2cDesc = $desc;
+        $crate::init::DeviceEntry {
+            name: D.name,
+            order: $crate::board::BUS_ORDER,
+            parent: None,
+            aliases: &[],
+            register: |_| $crate::board::register_i2c(&$chip, &D),
+        }
+    }};
+}
+
+// 接在 GPIO 上的 LED
+pub struct GpioLed<C: BoardChip + 'static> {
+    chip: &'static C,
+    desc: &'static LedDesc,
+    on: AtomicBool,
+}
+
+impl<C: BoardChip> GpioLed<C> {
+    fn light(&self, on: bool) {
+        self.chip.set(self.desc.pin, on != self.desc.active_low);
+        self.on.store(on, Ordering::Release);
+    }
+}
+
+impl<C: BoardChip> DeviceLed for GpioLed<C> {
+    fn init(&self) -> Result<(), LedError> {
+        self.chip.setup(self.desc.pin, led_pin(self.desc.pull));
+        self.light(false);
+        Ok(())
+    }
+
+    fn on(&self) -> Result<(), LedError> {
+        self.light(true);
+        Ok(())
+    }
+
+    fn off(&self) -> Result<(), LedError> {
+        self.light(false);
+        Ok(())
+    }
+
+    fn is_on(&self) -> bool {
+        self.on.load(Ordering::Acquire)
+    }
+
+    // 引脚恢复为浮空输入
+    fn uninit(&self) -> Result<(), LedError> {
+        self.chip.setup(self.desc.pin, PinMode::Input(Pull::None));
+        self.on.store(false, Ordering::Release);
+        Ok(())
+    }
+}
+
+// 用 GPIO 模拟的 I2C 总线
+pub struct GpioI2C<C: BoardChip + 'static> {
+    chip: &'static C,
+    desc: &'static I2cDesc,
+    init: AtomicBool,
+    num: AtomicU32,
+}
+
+impl<C: BoardChip> SBusI2CBase for GpioI2C<C> {
+    // 释放总线，两根线都为高电平
+    fn init(&self, _f: &OpenFlag) -> Result<(), I2CBusError> {
+        self.chip.setup(self.desc.scl, I2C_PIN);
+        self.chip.setup(self.desc.sda, I2C_PIN);
+        self.chip.set(self.desc.sda, true);
+        self.chip.set(self.desc.scl, true);
+        Ok(())
+    }
+
+    fn uninit(&self) -> Result<(), I2CBusError> {
+        self.chip.set(self.desc.sda, true);
+        self.chip.set(self.desc.scl, true);
+        Ok(())
+    }
+
+    fn get_atomic_init_flag(&self) -> &AtomicBool {
+        &self.init
+    }
+
+    fn get_atomic_num(&self) -> &AtomicU32 {
+        &self.num
+    }
+
+    fn scl_set(&self, flag: bool) {
+        self.chip.set(self.desc.scl, flag)
+    }
+
+    fn sda_set(&self, flag: bool) {
+        self.chip.set(self.desc.sda, flag)
+    }
+
+    fn scl_get(&self) -> bool {
+        self.chip.get(self.desc.scl)
+    }
+
+    fn sda_get(&self) -> bool {
+        self.chip.get(self.desc.sda)
+    }
+
+    fn get_delay_time_us(&self) -> u32 {
+        self.desc.delay_us
+    }
+
+    fn delay_us(&self, us: u32) {
+        self.chip.delay_us(us)
+    }
+}
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use super::*;
+    use crate::alloc::vec::Vec;
+    use crate::api::{find, resolve, DevOpen};
+    use crate::device::DeviceClass;
+    use crate::driver::DriverOps;
+    use crate::init::register_tables;
+    use std::sync::Mutex as StdMutex;
+
+    // 记录引脚操作的芯片，只支持 2 号串口
+    struct MockChip(StdMutex<Vec<(Pin, Option<PinMode>, bool)>>);
+
+    impl BoardChip for MockChip {
+        fn setup(&self, pin: Pin, mode: PinMode) {
+            self.0.lock().unwrap().push((pin, Some(mode), false));
+        }
+        fn set(&self, pin: Pin, high: bool) {
+            self.0.lock().unwrap().push((pin, None, high));
+        }
+        fn get(&self, _pin: Pin) -> bool {
+            false
+        }
+        fn delay_us(&self, _us: u32) {}
+
+        fn register_uart(&'static self, desc: &'static UartDesc) -> Result<(), IOError> {
+            if desc.num != 2 {
+                return Err(IOError::UnsupportedError);
+            }
+            crate::bsp::host_sim::testing::count_dev(desc.name);
+            Ok(())
+        }
+    }
+
+    static CHIP: MockChip = MockChip(StdMutex::new(Vec::new()));
+
+    board_table! {
+        chip: CHIP;
+        static BOARD = [
+            led LedDesc { name: "test/board/led", pin: Pin::new(Port::I, 8), active_low: true, pull: Pull::Down },
+            uart UartDesc {
+                name: "test/board/uart2",
+                num: 2,
+                tx: Pin::new(Port::A, 2),
+                rx: Pin::new(Port::A, 3),
+                af: 7,
+                alias: &["test/board/console"],
+            },
+            uart UartDesc {
+                name: "test/board/uart3",
+                num: 3,
+                tx: Pin::new(Port::B, 10),
+                rx: Pin::new(Port::B, 11),
+                af: 7,
+                alias: &["test/board/never"],
+            },
+            spi SpiDesc {
+                name: "test/board/spi",
+                num: 1,
+                sck: Pin::new(Port::A, 5),
+                miso: Pin::new(Port::A, 6),
+                mosi: Pin::new(Port::A, 7),
+                af: 5,
+                cs: &[Pin::new(Port::D, 14)],
+            },
+            #[cfg(feature = "nope")]
+            led LedDesc { name: "test/board/gated", pin: Pin::new(Port::I, 9), active_low: false, pull: Pull::None },
+            i2c I2cDesc { name: "test/board/i2c", scl: Pin::new(Port::B, 6), sda: Pin::new(Port::B, 7), delay_us: 5 },
+        ];
+    }
+
+    #[test]
+    fn board_table() {
+        let r = register_tables(&[BOARD]);
+        let names: Vec<_> = r.iter().map(|r| (r.name, r.result.is_ok())).collect();
+        // 总线先注册，同一顺序内按声明的顺序
+        assert_eq!(
+            names,
+            [
+                ("test/board/spi", false),
+                ("test/board/i2c", true),
+                ("test/board/led", true),
+                ("test/board/uart2", true),
+                ("test/board/uart3", false),
+            ]
+        );
+        // 默认的 register_spi 不支持，但引脚已经配置，片选空闲为高电平
+        assert!(matches!(r[0].result, Err(IOError::UnsupportedError)));
+        assert_eq!(resolve("test/board/console"), "test/board/uart2");
+        assert_eq!(resolve("test/board/never"), "test/board/never");
+        assert!(find("test/board/gated").is_err());
+        assert_eq!(
+            find("test/board/i2c").unwrap().lock().unwrap().class(),
+            DeviceClass::I2CBus
+        );
+        {
+            let log = CHIP.0.lock().unwrap();
+            let cs = Pin::new(Port::D, 14);
+            assert!(log.contains(&(cs, Some(CS_PIN), false)));
+            assert!(log.contains(&(cs, None, true)));
+            assert!(log.contains(&(Pin::new(Port::A, 2), Some(af_pin(7)), false)));
+        }
+
+        let led = find("test/board/led").unwrap();
+        let g = led.open(&OpenFlag::zero()).unwrap();
+        // 使用描述表中的上下拉
+        let mode = Some(led_pin(Pull::Down));
+        assert!(CHIP.0.lock().unwrap().iter().any(|e| e.1 == mode));
+        CHIP.0.lock().unwrap().clear();
+        g.write(0, &1u32).unwrap();
+        // 低电平点亮
+        assert_eq!(
+            CHIP.0.lock().unwrap().last(),
+            Some(&(Pin::new(Port::I, 8), None, false))
+        );
+        assert_eq!(g.read(0, 1).unwrap().take_u32().unwrap(), 1);
+    }
+}
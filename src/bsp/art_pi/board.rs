@@ -0,0 +1,114 @@
+//! ART-Pi 的板卡描述以及 STM32H7 的引脚操作
+
+use super::{hal, uart, DP};
+use crate::board::{BoardChip, LedDesc, Pin, PinMode, Port, Pull, Speed, UartDesc};
+use crate::board_table;
+use crate::device::register_device;
+use crate::device::serial::Serial;
+use crate::error::IOError;
+
+// 还没有 SPI 总线驱动，使用默认的 register_spi，板卡上的 SPI 没有描述
+board_table! {
+    chip: CHIP;
+    pub(crate) static BOARD = [
+        uart UartDesc {
+            name: "uart1",
+            num: 1,
+            tx: Pin::new(Port::A, 9),
+            rx: Pin::new(Port::A, 10),
+            af: 7,
+            alias: &["console"],
+        },
+        led LedDesc {
+            name: "led0",
+            pin: Pin::new(Port::I, 8),
+            active_low: true,
+            pull: Pull::Up,
+        },
+    ];
+}
+
+// 内核主频，用于微秒延时
+const CPU_MHZ: u32 = 480;
+
+pub(crate) struct Stm32H7;
+
+pub(crate) static CHIP: Stm32H7 = Stm32H7;
+
+// 各个 GPIO 端口的寄存器相同，地址间隔 0x400
+fn gpio(port: Port) -> &'static hal::gpioa::RegisterBlock {
+    let addr = hal::GPIOA::ptr() as usize + port as usize * 0x400;
+    unsafe { &*(addr as *const hal::gpioa::RegisterBlock) }
+}
+
+// 修改寄存器中从 shift 开始、宽度为 width 的位
+fn field(old: u32, shift: u32, width: u32, val: u32) -> u32 {
+    let mask = ((1 << width) - 1) << shift;
+    (old & !mask) | ((val << shift) & mask)
+}
+
+impl BoardChip for Stm32H7 {
+    fn setup(&self, pin: Pin, mode: PinMode) {
+        // AHB4ENR 的第 n 位为端口 n 的时钟
+        DP.0.RCC
+            .ahb4enr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << pin.port as u32) });
+
+        let (moder, open_drain, pull, speed, af) = match mode {
+            PinMode::Input(pull) => (0, false, pull, Speed::Low, None),
+            PinMode::Output {
+                open_drain,
+                pull,
+                speed,
+            } => (1, open_drain, pull, speed, None),
+            PinMode::Alternate {
+                af,
+                open_drain,
+                pull,
+                speed,
+            } => (2, open_drain, pull, speed, Some(af)),
+        };
+        let pull = match pull {
+            Pull::None => 0,
+            Pull::Up => 1,
+            Pull::Down => 2,
+        };
+        let n = pin.num as u32;
+        let r = gpio(pin.port);
+        r.moder
+            .modify(|v, w| unsafe { w.bits(field(v.bits(), n * 2, 2, moder)) });
+        r.otyper
+            .modify(|v, w| unsafe { w.bits(field(v.bits(), n, 1, open_drain as u32)) });
+        r.ospeedr
+            .modify(|v, w| unsafe { w.bits(field(v.bits(), n * 2, 2, speed as u32)) });
+        r.pupdr
+            .modify(|v, w| unsafe { w.bits(field(v.bits(), n * 2, 2, pull)) });
+        if let Some(af) = af {
+            if n < 8 {
+                r.afrl
+                    .modify(|v, w| unsafe { w.bits(field(v.bits(), n * 4, 4, af as u32)) });
+            } else {
+                r.afrh
+                    .modify(|v, w| unsafe { w.bits(field(v.bits(), (n - 8) * 4, 4, af as u32)) });
+            }
+        }
+    }
+
+    fn set(&self, pin: Pin, high: bool) {
+        let n = pin.num as u32;
+        let bit = if high { 1 << n } else { 1 << (n + 16) };
+        gpio(pin.port).bsrr.write(|w| unsafe { w.bits(bit) });
+    }
+
+    fn get(&self, pin: Pin) -> bool {
+        gpio(pin.port).idr.read().bits() & (1 << pin.num) != 0
+    }
+
+    fn delay_us(&self, us: u32) {
+        cortex_m::asm::delay(us * CPU_MHZ);
+    }
+
+    fn register_uart(&'static self, desc: &'static UartDesc) -> Result<(), IOError> {
+        register_device(Serial::new(uart::BspUart::new(desc.num)), desc.name)
+    }
+}
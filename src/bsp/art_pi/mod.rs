@@ -1,11 +1,11 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
-mod led;
+mod board;
 mod uart;
 
 use crate::dev_init;
-use crate::device::register_device;
+use crate::init::register_tables;
 use lazy_static::lazy_static;
 use rtt_rs::println;
 pub(crate) use stm32h7::stm32h743v as hal;
@@ -23,10 +23,13 @@ struct PH(hal::Peripherals);
 dev_init!(init);
 pub fn init() {
     println!("Device Init start!!!");
-    use crate::device::led::Led;
-    register_device(Led::new(led::BspLed::new()), "led0").unwrap();
-    println!("Register LED finished");
-    use crate::device::serial::Serial;
-    register_device(Serial::new(uart::BspUart::new(1)), "uart1").unwrap();
-    println!("Register UART1 finished");
+    for r in register_tables(&[board::BOARD]) {
+        match r.result {
+            Ok(()) => println!("Register {} finished", r.name),
+            Err(e) => println!("Register {} failed: {}", r.name, e),
+        }
+        for (alias, e) in r.alias_errors {
+            println!("Alias {} -> {} failed: {}", alias, r.name, e);
+        }
+    }
 }
@@ -5,8 +5,8 @@ use crate::device::serial::{
     bsp, DeviceSerial, SerialBaudRate, SerialBitOrder, SerialDataBits, SerialError, SerialParity,
     SerialStopBits,
 };
-use crate::rtt_rs::raw_api::no_irq;
-use crate::OpenFlag;
+use crate::os::{Os, OsApi};
+use crate::{IOError, OpenFlag};
 use core::cell::{Cell, UnsafeCell};
 use core::task::Waker;
 
@@ -19,6 +19,23 @@ pub struct BspUart {
     reg: Cell<usize>,
 }
 
+// 读取并清除接收错误标志，ORE 不清除时会一直触发中断
+fn take_rx_error(ut: &hal::usart1::RegisterBlock) -> Option<SerialError> {
+    let isr = ut.isr.read();
+    let err = if isr.ore().bit_is_set() {
+        SerialError::Overrun
+    } else if isr.fe().bit_is_set() {
+        SerialError::Framing
+    } else if isr.pe().bit_is_set() {
+        SerialError::Parity
+    } else {
+        return None;
+    };
+    ut.icr
+        .write(|w| w.orecf().set_bit().fecf().set_bit().pecf().set_bit());
+    Some(err)
+}
+
 fn cal_brr(clk: u32, bound: u32) -> u32 {
     (clk * 1000000 + bound / 2) / bound
 }
@@ -53,39 +70,13 @@ impl DeviceSerial for BspUart {
 
         match self.num {
             1 => {
-                let r = &DP.0.GPIOA;
                 let ut = &DP.0.USART1;
                 let baud = cal_brr(100, 115200);
                 self.reg.set(hal::USART1::ptr() as usize);
 
-                rcc.ahb4enr.modify(|_, w| w.gpioaen().set_bit());
+                // 引脚由板卡描述在启动时配置
                 rcc.apb2enr.modify(|_, w| w.usart1en().set_bit());
 
-                r.moder.modify(|_, w| {
-                    w.moder9().alternate();
-                    w.moder10().alternate();
-                    w
-                });
-                r.otyper.modify(|_, w| {
-                    w.ot9().push_pull();
-                    w.ot10().push_pull();
-                    w
-                });
-                r.pupdr.modify(|_, w| {
-                    w.pupdr9().floating();
-                    w.pupdr10().floating();
-                    w
-                });
-                r.ospeedr.modify(|_, w| {
-                    w.ospeedr9().high_speed();
-                    w.ospeedr10().high_speed();
-                    w
-                });
-                r.afrh.modify(|_, w| {
-                    w.afr9().af7();
-                    w.afr10().af7();
-                    w
-                });
                 rcc.apb2rstr.modify(|_, w| w.usart1rst().set_bit());
                 rcc.apb2rstr.modify(|_, w| w.usart1rst().clear_bit());
 
@@ -114,6 +105,33 @@ impl DeviceSerial for BspUart {
         Ok(())
     }
 
+    // 关闭串口及其时钟，寄存器的配置在 STOP 模式下保持
+    fn suspend(&self) -> Result<(), SerialError> {
+        let ut = unsafe {
+            let reg = self.reg.get() as *mut hal::usart1::RegisterBlock;
+            &(*reg)
+        };
+        ut.cr1.modify(|_, w| w.ue().clear_bit());
+        match self.num {
+            1 => DP.0.RCC.apb2enr.modify(|_, w| w.usart1en().clear_bit()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<(), SerialError> {
+        match self.num {
+            1 => DP.0.RCC.apb2enr.modify(|_, w| w.usart1en().set_bit()),
+            _ => {}
+        }
+        let ut = unsafe {
+            let reg = self.reg.get() as *mut hal::usart1::RegisterBlock;
+            &(*reg)
+        };
+        ut.cr1.modify(|_, w| w.ue().set_bit());
+        Ok(())
+    }
+
     fn get_helper(&self) -> &BspSerial {
         &self.hp
     }
@@ -123,7 +141,13 @@ impl DeviceSerial for BspUart {
             let reg = self.reg.get() as *mut hal::usart1::RegisterBlock;
             &(*reg)
         };
-        Ok(ut.rdr.read().bits() as _)
+        let err = take_rx_error(ut);
+        // 出错时也要读出数据寄存器，清除 RXNE
+        let ch = ut.rdr.read().bits() as u8;
+        match err {
+            Some(e) => Err(e),
+            None => Ok(ch),
+        }
     }
 
     fn read_able(&self) -> bool {
@@ -171,32 +195,43 @@ impl DeviceSerial for BspUart {
         ut.cr1.modify(|_, w| w.txeie().bit(f));
     }
 
-    fn dma_write(&self, _ptr: *const u8, _len: usize) {
-        unimplemented!()
+    // 还没有配置 DMA 通道
+    fn dma_write(&self, _ptr: *const u8, _len: usize) -> Result<(), SerialError> {
+        Err(SerialError::WriteError)
+    }
+
+    // 以下配置暂不支持，保持 init 时的配置
+    fn config_baud(&self, _val: SerialBaudRate) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
     }
 
-    fn config_baud(&self, _val: SerialBaudRate) {
-        unimplemented!()
+    fn stop_bots(&self, _val: SerialStopBits) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
     }
 
-    fn stop_bots(&self, _val: SerialStopBits) {
-        todo!()
+    fn data_bits(&self, _val: SerialDataBits) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
     }
 
-    fn data_bits(&self, _val: SerialDataBits) {
-        todo!()
+    fn parity(&self, _val: SerialParity) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
     }
 
-    fn parity(&self, _val: SerialParity) {
-        todo!()
+    fn bit_order(&self, _val: SerialBitOrder) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
     }
 
-    fn bit_order(&self, _val: SerialBitOrder) {
-        todo!()
+    // init 时固定配置为 115200
+    fn get_baud(&self) -> Result<SerialBaudRate, IOError> {
+        Ok(SerialBaudRate::B115200)
     }
 
-    fn update_flags(&self, _f: OpenFlag) -> OpenFlag {
-        todo!()
+    // 中断处理函数根据 UART_FLAG 决定处理方式
+    fn update_flags(&self, f: OpenFlag) -> OpenFlag {
+        Os::no_irq(|| unsafe {
+            UART_FLAG[self.num as usize] = f.clone();
+        });
+        f
     }
 }
 
@@ -207,7 +242,10 @@ pub extern "C" fn USART1_IRQHandler() {
         let ut = &DP.0.USART1;
         let dev = UART_DEV_PTR[1] as *const BspUart as *mut BspUart;
         let flag = UART_FLAG[1];
-        if ut.isr.read().rxne().bit_is_set() {
+        if take_rx_error(ut).is_some() {
+            // 中断中无法返回错误，出错的数据直接丢弃
+            let _ = ut.rdr.read();
+        } else if ut.isr.read().rxne().bit_is_set() {
             let data = ut.rdr.read().bits();
             bsp::irq_receive_char(dev, data as _);
             if flag.get_read_c_type() {
@@ -0,0 +1,238 @@
+//! 模拟软件 I2C 总线
+//! 每次设置 SCL/SDA 都会记录一次线上的电平 (scl, sda)
+//! 读取 SDA 时返回预先注入的电平，没有注入时返回低电平（即从机应答）
+
+use crate::alloc::collections::VecDeque;
+use crate::alloc::vec::Vec;
+use crate::device::i2c_bus::{I2CBusError, SBusI2CBase};
+use crate::{Mutex, OpenFlag};
+use core::sync::atomic::{AtomicBool, AtomicU32};
+use lazy_static::lazy_static;
+
+const I2C_NUM: usize = 8;
+
+pub struct SimI2CHw {
+    pub scl: bool,
+    pub sda: bool,
+    pub trace: Vec<(bool, bool)>,
+    pub sda_in: VecDeque<bool>,
+    // 模拟从机拉低 SCL（时钟延展）
+    pub scl_hold: bool,
+}
+
+lazy_static! {
+    static ref I2C_HW: Vec<Mutex<SimI2CHw>> = (0..I2C_NUM)
+        .map(|_| {
+            Mutex::new(SimI2CHw {
+                scl: true,
+                sda: true,
+                trace: Vec::new(),
+                sda_in: VecDeque::new(),
+                scl_hold: false,
+            })
+            .unwrap()
+        })
+        .collect();
+}
+
+pub struct SimI2CBus {
+    num: usize,
+    init: AtomicBool,
+    num_open: AtomicU32,
+}
+
+impl SimI2CBus {
+    pub fn new(num: usize) -> SimI2CBus {
+        SimI2CBus {
+            num,
+            init: AtomicBool::new(false),
+            num_open: AtomicU32::new(0),
+        }
+    }
+
+    fn hw(&self) -> &Mutex<SimI2CHw> {
+        &I2C_HW[self.num]
+    }
+}
+
+impl SBusI2CBase for SimI2CBus {
+    fn init(&self, _f: &OpenFlag) -> Result<(), I2CBusError> {
+        if self.num >= I2C_NUM {
+            return Err(I2CBusError::InitError);
+        }
+        self.sda_set(true);
+        self.scl_set(true);
+        Ok(())
+    }
+
+    fn uninit(&self) -> Result<(), I2CBusError> {
+        self.sda_set(true);
+        self.scl_set(true);
+        Ok(())
+    }
+
+    fn get_atomic_init_flag(&self) -> &AtomicBool {
+        &self.init
+    }
+
+    fn get_atomic_num(&self) -> &AtomicU32 {
+        &self.num_open
+    }
+
+    fn scl_set(&self, flag: bool) {
+        let mut hw = self.hw().lock().unwrap();
+        hw.scl = flag;
+        let line = (hw.scl, hw.sda);
+        hw.trace.push(line);
+    }
+
+    fn sda_set(&self, flag: bool) {
+        let mut hw = self.hw().lock().unwrap();
+        hw.sda = flag;
+        let line = (hw.scl, hw.sda);
+        hw.trace.push(line);
+    }
+
+    fn scl_get(&self) -> bool {
+        let hw = self.hw().lock().unwrap();
+        hw.scl && !hw.scl_hold
+    }
+
+    fn sda_get(&self) -> bool {
+        self.hw()
+            .lock()
+            .unwrap()
+            .sda_in
+            .pop_front()
+            .unwrap_or(false)
+    }
+
+    fn get_delay_time_us(&self) -> u32 {
+        0
+    }
+
+    fn delay_us(&self, _us: u32) {}
+
+    fn get_timeout_ms(&self) -> Option<u32> {
+        Some(1)
+    }
+}
+
+// 注入从机驱动的 SDA 电平，按读取顺序排列
+pub fn i2c_inject_sda(num: usize, bits: &[bool]) {
+    I2C_HW[num]
+        .lock()
+        .unwrap()
+        .sda_in
+        .extend(bits.iter().cloned());
+}
+
+// 取出目前为止线上的电平变化记录
+pub fn i2c_take_trace(num: usize) -> Vec<(bool, bool)> {
+    let mut hw = I2C_HW[num].lock().unwrap();
+    core::mem::take(&mut hw.trace)
+}
+
+pub fn i2c_hold_scl(num: usize, hold: bool) {
+    I2C_HW[num].lock().unwrap().scl_hold = hold;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::i2c_bus::{I2CAddressType, I2CMsg, SBusI2C, SBusI2CTrans};
+
+    fn bits(b: u8) -> Vec<bool> {
+        (0..8).rev().map(|i| (b >> i) & 1 == 1).collect()
+    }
+
+    #[test]
+    fn read_write() {
+        let bus = SBusI2C::new(SimI2CBus::new(2));
+        // 没有注入时从机总是应答
+        assert_eq!(bus.i2c_write_from(0x50, &[1, 2]).unwrap(), 2);
+        let trace = i2c_take_trace(2);
+        assert!(!trace.is_empty());
+        assert_eq!(trace.last(), Some(&(true, true)));
+
+        // 地址应答后读出两个字节
+        let mut sda = vec![false];
+        sda.extend(bits(0xA5));
+        sda.extend(bits(0x3C));
+        i2c_inject_sda(2, &sda);
+        let mut buf = [0u8; 2];
+        assert_eq!(bus.i2c_read_into(0x50, &mut buf).unwrap(), 2);
+        assert_eq!(buf, [0xA5, 0x3C]);
+
+        // 写地址、写数据、读地址各应答一次
+        let mut sda = vec![false, false, false];
+        sda.extend(bits(0x42));
+        i2c_inject_sda(2, &sda);
+        let mut msgs = [I2CMsg::write(0x50, vec![0x10]), I2CMsg::read(0x50, 1)];
+        bus.i2c_trans(&mut msgs).unwrap();
+        assert_eq!(msgs[1].buf(), [0x42]);
+    }
+
+    #[test]
+    fn nack() {
+        let bus = SBusI2C::new(SimI2CBus::new(3));
+        // 地址发送两次都没有应答
+        i2c_inject_sda(3, &[true, true]);
+        assert!(matches!(
+            bus.i2c_write_from(0x21, &[0]),
+            Err(I2CBusError::Nack(0x21))
+        ));
+    }
+
+    #[test]
+    fn clock_stretch_timeout() {
+        let bus = SBusI2C::new(SimI2CBus::new(4));
+        i2c_take_trace(4);
+        i2c_hold_scl(4, true);
+        assert!(matches!(
+            bus.i2c_write_from(0x21, &[0]),
+            Err(I2CBusError::Timeout)
+        ));
+        // 第一次超时就中止，只剩下起始条件、第一个时钟和停止条件
+        assert!(i2c_take_trace(4).len() < 12);
+        i2c_hold_scl(4, false);
+        assert!(bus.i2c_write_from(0x21, &[0]).is_ok());
+    }
+
+    // 从电平记录中取出 SCL 上升沿时的 SDA，每 9 位为一个字节加应答位
+    fn sent_bytes(trace: &[(bool, bool)]) -> Vec<u8> {
+        let edges: Vec<bool> = trace
+            .windows(2)
+            .filter(|w| !w[0].0 && w[1].0)
+            .map(|w| w[1].1)
+            .collect();
+        edges
+            .chunks_exact(9)
+            .map(|c| c[..8].iter().fold(0, |b, bit| (b << 1) | *bit as u8))
+            .collect()
+    }
+
+    #[test]
+    fn ten_bit_address() {
+        let bus = SBusI2C::new(SimI2CBus::new(5));
+        i2c_take_trace(5);
+        let mut msg = I2CMsg::write(0x2A5, vec![0x11]);
+        msg.address_type = I2CAddressType::Bits10;
+        bus.i2c_trans(&mut [msg]).unwrap();
+        let sent = sent_bytes(&i2c_take_trace(5));
+        assert_eq!(&sent[..3], [0xF4, 0xA5, 0x11]);
+
+        // 超出范围的地址不会发送到总线上
+        let mut msg = I2CMsg::write(0x400, vec![0x11]);
+        msg.address_type = I2CAddressType::Bits10;
+        assert!(matches!(
+            bus.i2c_trans(&mut [msg]),
+            Err(I2CBusError::Address(0x400))
+        ));
+        assert!(matches!(
+            bus.i2c_write_from(0x80, &[0]),
+            Err(I2CBusError::Address(0x80))
+        ));
+        assert!(i2c_take_trace(5).is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+//! 模拟 LED，由 VirtualBspLed 修改而来
+//! 状态保存在全局表中，测试可以通过 led_is_on 查看
+
+use crate::device::led::{DeviceLed, LedError};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const LED_NUM: usize = 8;
+
+// 记录当前LED的状态
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SimLedState {
+    Uninit = 0,
+    On,
+    Off,
+}
+
+// 初始状态都为 SimLedState::Uninit
+static LED_STATE: [AtomicU8; LED_NUM] = [
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+];
+
+pub struct SimLed {
+    num: usize,
+}
+
+impl SimLed {
+    pub fn new(num: usize) -> Self {
+        SimLed { num }
+    }
+
+    fn set_state(&self, s: SimLedState) {
+        LED_STATE[self.num].store(s as u8, Ordering::SeqCst);
+    }
+}
+
+impl DeviceLed for SimLed {
+    fn init(&self) -> Result<(), LedError> {
+        if self.num >= LED_NUM {
+            return Err(LedError::InitError);
+        }
+        self.set_state(SimLedState::Off);
+        Ok(())
+    }
+
+    fn on(&self) -> Result<(), LedError> {
+        self.set_state(SimLedState::On);
+        Ok(())
+    }
+
+    fn off(&self) -> Result<(), LedError> {
+        self.set_state(SimLedState::Off);
+        Ok(())
+    }
+
+    fn is_on(&self) -> bool {
+        led_state(self.num) == SimLedState::On
+    }
+
+    fn uninit(&self) -> Result<(), LedError> {
+        self.set_state(SimLedState::Uninit);
+        Ok(())
+    }
+}
+
+pub fn led_state(num: usize) -> SimLedState {
+    match LED_STATE[num].load(Ordering::SeqCst) {
+        1 => SimLedState::On,
+        2 => SimLedState::Off,
+        _ => SimLedState::Uninit,
+    }
+}
+
+pub fn led_is_on(num: usize) -> bool {
+    led_state(num) == SimLedState::On
+}
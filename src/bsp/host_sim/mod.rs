@@ -0,0 +1,52 @@
+//! 主机上的模拟板卡
+//! 所有外设都在内存中模拟，不需要真实的寄存器
+//! 注册的设备名称与真实板卡保持一致：uart1、led0、spi1、i2c1，console 指向 uart1
+//! 测试代码可以通过各个子模块提供的函数注入接收数据、检查发送数据、触发中断
+
+pub mod i2c_bus;
+pub mod led;
+pub mod spi_bus;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod uart;
+
+use crate::alloc::boxed::Box;
+use crate::device::i2c_bus::SBusI2C;
+use crate::device::led::Led;
+use crate::device::register_fast_device;
+use crate::device::serial::Serial;
+use crate::device::spi_bus::BusSpiHandler;
+use crate::init::register_tables;
+use crate::{dev_init, device_table};
+use std::sync::Once;
+
+static INIT: Once = Once::new();
+
+device_table! {
+    static DEVICES = [
+        { name: "led0", order: 10, dev: Led::new(led::SimLed::new(0)) },
+        { name: "uart1", order: 10, dev: Serial::new(uart::SimUart::new(1)), alias: ["console"] },
+    ];
+}
+
+dev_init!(init);
+// 可以被多次调用，只有第一次会注册设备
+pub fn init() {
+    INIT.call_once(|| {
+        for r in register_tables(&[DEVICES]) {
+            if let Err(e) = r.result {
+                std::eprintln!("register {} failed: {}", r.name, e);
+            }
+            for (alias, e) in r.alias_errors {
+                std::eprintln!("alias {} -> {} failed: {}", alias, r.name, e);
+            }
+        }
+        // 总线没有设备框架，作为快设备注册
+        register_fast_device(
+            Box::new(BusSpiHandler::new(spi_bus::SimSpiBus::new(1))),
+            "spi1",
+        )
+        .unwrap();
+        register_fast_device(Box::new(SBusI2C::new(i2c_bus::SimI2CBus::new(1))), "i2c1").unwrap();
+    });
+}
@@ -0,0 +1,171 @@
+//! 模拟 SPI 总线
+//! MOSI 上发送的数据被记录下来，MISO 返回预先注入的数据，没有数据时返回 0xFF
+
+use crate::alloc::collections::VecDeque;
+use crate::alloc::vec::Vec;
+use crate::device::base::DynCycleQueue;
+use crate::device::spi_bus::bsp::BspBusSpi;
+use crate::device::spi_bus::BusSpi;
+use crate::device::spi_device::{SpiConfig, SpiError};
+use crate::{Mutex, OpenFlag};
+use core::cell::UnsafeCell;
+use lazy_static::lazy_static;
+
+const SPI_NUM: usize = 6;
+
+pub struct SimSpiHw {
+    pub mosi: Vec<u8>,
+    pub miso: VecDeque<u8>,
+    pub cs: bool,
+    pub init: bool,
+    // (CPOL, CPHA)
+    pub mode: (bool, bool),
+}
+
+lazy_static! {
+    static ref SPI_HW: Vec<Mutex<SimSpiHw>> = (0..SPI_NUM)
+        .map(|_| {
+            Mutex::new(SimSpiHw {
+                mosi: Vec::new(),
+                miso: VecDeque::new(),
+                cs: false,
+                init: false,
+                mode: (true, true),
+            })
+            .unwrap()
+        })
+        .collect();
+}
+
+pub struct SimSpiBus {
+    num: usize,
+    hp: BspBusSpi,
+}
+
+impl SimSpiBus {
+    pub fn new(num: usize) -> Self {
+        SimSpiBus {
+            num,
+            hp: BspBusSpi {
+                r_buffer: UnsafeCell::new(DynCycleQueue::new(32)),
+                w_buffer: UnsafeCell::new(DynCycleQueue::new(32)),
+            },
+        }
+    }
+
+    fn hw(&self) -> &Mutex<SimSpiHw> {
+        &SPI_HW[self.num]
+    }
+}
+
+impl BusSpi for SimSpiBus {
+    fn init(&self, _f: &OpenFlag, cfg: &SpiConfig) -> Result<(), SpiError> {
+        self.np_init()?;
+        self.hw().lock().unwrap().mode = cfg.s_type().cpol_cpha();
+        Ok(())
+    }
+
+    fn np_init(&self) -> Result<(), SpiError> {
+        if self.num >= SPI_NUM {
+            return Err(SpiError::InitError);
+        }
+        self.hw().lock().unwrap().init = true;
+        Ok(())
+    }
+
+    fn cs(&self, f: bool) {
+        self.hw().lock().unwrap().cs = f;
+    }
+
+    fn uninit(&self) -> Result<(), SpiError> {
+        self.hw().lock().unwrap().init = false;
+        Ok(())
+    }
+
+    fn trans_bit(&self, data: u8) -> Result<u8, SpiError> {
+        let mut hw = self.hw().lock().unwrap();
+        if !hw.init {
+            return Err(SpiError::UninitError);
+        }
+        hw.mosi.push(data);
+        Ok(hw.miso.pop_front().unwrap_or(0xFF))
+    }
+
+    fn trans_bits_dma(&self, ptr: *const u8, len: usize) -> Result<(), SpiError> {
+        let data = unsafe { core::slice::from_raw_parts(ptr, len) };
+        let mut hw = self.hw().lock().unwrap();
+        if !hw.init {
+            return Err(SpiError::UninitError);
+        }
+        hw.mosi.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn get_helper(&self) -> &BspBusSpi {
+        &self.hp
+    }
+}
+
+// 注入从机将要返回的数据
+pub fn spi_inject_miso(num: usize, data: &[u8]) {
+    SPI_HW[num]
+        .lock()
+        .unwrap()
+        .miso
+        .extend(data.iter().cloned());
+}
+
+// 取出目前为止主机发送的数据
+pub fn spi_take_mosi(num: usize) -> Vec<u8> {
+    let mut hw = SPI_HW[num].lock().unwrap();
+    core::mem::take(&mut hw.mosi)
+}
+
+pub fn spi_mode(num: usize) -> (bool, bool) {
+    SPI_HW[num].lock().unwrap().mode
+}
+
+pub fn spi_cs(num: usize) -> bool {
+    SPI_HW[num].lock().unwrap().cs
+}
+
+pub fn spi_is_init(num: usize) -> bool {
+    SPI_HW[num].lock().unwrap().init
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::spi_bus::{BusSpiHandler, BusSpiOps};
+    use crate::device::spi_device::SpiType;
+
+    #[test]
+    fn miso_mosi() {
+        let bus = BusSpiHandler::new(SimSpiBus::new(2));
+        spi_inject_miso(2, &[0xEF, 0x40]);
+        assert_eq!(bus.trans_bit(0x9F).unwrap(), 0xEF);
+        assert_eq!(
+            bus.trans_bits_dam(alloc::vec![0, 0, 0]).unwrap(),
+            [0x40, 0xFF, 0xFF]
+        );
+        assert_eq!(spi_take_mosi(2), [0x9F, 0, 0, 0]);
+    }
+
+    #[test]
+    fn uninit_bus() {
+        let bus = SimSpiBus::new(3);
+        assert!(matches!(bus.trans_bit(0), Err(SpiError::UninitError)));
+        assert!(SimSpiBus::new(SPI_NUM).np_init().is_err());
+    }
+
+    #[test]
+    fn init_mode() {
+        let bus = SimSpiBus::new(4);
+        bus.init(&OpenFlag::zero(), &SpiConfig::new(SpiType::TypeB))
+            .unwrap();
+        assert_eq!(spi_mode(4), (false, true));
+        bus.init(&OpenFlag::zero(), &SpiConfig::new(SpiType::TypeD))
+            .unwrap();
+        assert_eq!(spi_mode(4), (true, true));
+    }
+}
@@ -0,0 +1,120 @@
+//! 单元测试用的辅助函数
+//! 测试并行运行，每个测试注册自己的模拟串口，不与 host_sim::init 注册的 uart1 共用
+
+use super::spi_bus::SimSpiBus;
+use super::uart::SimUart;
+use crate::alloc::boxed::Box;
+use crate::alloc::sync::Arc;
+use crate::device::serial::Serial;
+use crate::device::spi_bus::{BusSpiHandler, SpiBusRef};
+use crate::device::spi_device::bsp::BspSpiDev;
+use crate::device::spi_device::{DeviceSpi, SpiConfig, SpiError};
+use crate::device::{register_child_device, register_device, DeviceOps};
+use crate::Mutex;
+use crate::{IOError, OpenFlag, ToMakeStdData};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use std::task::Wake;
+
+// 0 和 1 留给 host_sim::init
+static NEXT_UART: AtomicU32 = AtomicU32::new(2);
+
+// 注册一个新的模拟串口，返回串口编号
+pub(crate) fn sim_uart(name: &str) -> u32 {
+    let num = NEXT_UART.fetch_add(1, Ordering::Relaxed);
+    register_device(Serial::new(SimUart::new(num)), name).unwrap();
+    num
+}
+
+// 记录被唤醒的次数
+pub(crate) struct CountWaker(AtomicUsize);
+
+impl CountWaker {
+    pub(crate) fn new() -> (Arc<CountWaker>, Waker) {
+        let c = Arc::new(CountWaker(AtomicUsize::new(0)));
+        (c.clone(), Waker::from(c))
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl Wake for CountWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+pub(crate) fn poll<F: Future + Unpin>(f: &mut F, w: &Waker) -> Poll<F::Output> {
+    Pin::new(f).poll(&mut Context::from_waker(w))
+}
+
+// 记录驱动打开、关闭次数的设备
+pub(crate) struct CountDev(Arc<AtomicU32>, Arc<AtomicU32>);
+
+impl DeviceOps for CountDev {
+    fn open(&self, _flag: &OpenFlag) -> Result<(), IOError> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+    fn close(&self) -> Result<(), IOError> {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+    fn control(&self, _data: &dyn ToMakeStdData) -> Result<(), IOError> {
+        Ok(())
+    }
+}
+
+// 注册一个 CountDev，返回打开、关闭的计数
+pub(crate) fn count_dev(name: &str) -> (Arc<AtomicU32>, Arc<AtomicU32>) {
+    let opens = Arc::new(AtomicU32::new(0));
+    let closes = Arc::new(AtomicU32::new(0));
+    register_device(CountDev(opens.clone(), closes.clone()), name).unwrap();
+    (opens, closes)
+}
+
+// 在 parent 下注册一个 CountDev
+pub(crate) fn count_child(name: &str, parent: &str) -> (Arc<AtomicU32>, Arc<AtomicU32>) {
+    let opens = Arc::new(AtomicU32::new(0));
+    let closes = Arc::new(AtomicU32::new(0));
+    register_child_device(CountDev(opens.clone(), closes.clone()), name, parent).unwrap();
+    (opens, closes)
+}
+
+// 使用指定编号的模拟 SPI 总线
+pub(crate) fn sim_spi_bus(num: usize) -> SpiBusRef {
+    Arc::new(Mutex::new(Box::new(BusSpiHandler::new(SimSpiBus::new(num))) as Box<_>).unwrap())
+}
+
+// 挂在模拟总线上的 SPI 设备，片选的状态记录在设备中
+pub(crate) struct SimSpiDev(SpiBusRef, AtomicBool);
+
+impl SimSpiDev {
+    pub(crate) fn new(bus: SpiBusRef) -> SimSpiDev {
+        SimSpiDev(bus, AtomicBool::new(false))
+    }
+}
+
+impl DeviceSpi for SimSpiDev {
+    fn cs(&self, f: bool) {
+        self.1.store(f, Ordering::SeqCst);
+    }
+    fn np_init(&self) -> Result<(), SpiError> {
+        Ok(())
+    }
+    fn init(&self, _f: &OpenFlag, _cfg: &SpiConfig) -> Result<(), SpiError> {
+        Ok(())
+    }
+    fn uninit(&self) -> Result<(), SpiError> {
+        Ok(())
+    }
+    fn get_helper(&self) -> BspSpiDev {
+        BspSpiDev {
+            bus: self.0.clone(),
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! 模拟串口
+//! 接收寄存器用一个 FIFO 模拟，发送的数据记录在 tx 里
+//! 中断使能时，注入数据或者打开发送中断会立即执行模拟的中断处理函数
+
+use crate::alloc::collections::VecDeque;
+use crate::alloc::vec::Vec;
+use crate::device::base::DynCycleQueue;
+use crate::device::serial::bsp::BspSerial;
+use crate::device::serial::{
+    bsp, DeviceSerial, SerialBaudRate, SerialBitOrder, SerialDataBits, SerialError, SerialParity,
+    SerialStopBits,
+};
+use crate::os::{Os, OsApi};
+use crate::{IOError, Mutex, OpenFlag};
+use core::cell::UnsafeCell;
+use lazy_static::lazy_static;
+
+const UART_NUM: usize = 32;
+
+static mut UART_DEV_PTR: [usize; UART_NUM] = [0 as _; UART_NUM];
+static mut UART_FLAG: [OpenFlag; UART_NUM] = [OpenFlag::zero(); UART_NUM];
+
+// 模拟的串口硬件状态
+pub struct SimUartHw {
+    pub rx: VecDeque<u8>,
+    pub tx: Vec<u8>,
+    pub rx_irq: bool,
+    pub tx_irq: bool,
+    pub init: bool,
+    pub baud: u32,
+    // 时钟被关闭
+    pub suspended: bool,
+    // 发送的数据达到该长度后不再可写，模拟对端不接收
+    pub tx_limit: Option<usize>,
+    // 下一个接收的字节出错，如溢出、帧错误、校验错误
+    pub rx_error: Option<SerialError>,
+}
+
+lazy_static! {
+    static ref UART_HW: Vec<Mutex<SimUartHw>> = (0..UART_NUM)
+        .map(|_| {
+            Mutex::new(SimUartHw {
+                rx: VecDeque::new(),
+                tx: Vec::new(),
+                rx_irq: false,
+                tx_irq: false,
+                init: false,
+                baud: SerialBaudRate::B115200 as u32,
+                suspended: false,
+                tx_limit: None,
+                rx_error: None,
+            })
+            .unwrap()
+        })
+        .collect();
+}
+
+pub struct SimUart {
+    num: u32,
+    hp: BspSerial,
+}
+
+impl SimUart {
+    pub fn new(num: u32) -> Self {
+        SimUart {
+            num,
+            hp: BspSerial {
+                read_async_helper: UnsafeCell::new(None),
+                r_buffer: UnsafeCell::new(DynCycleQueue::new(256)),
+                w_buffer: UnsafeCell::new(DynCycleQueue::new(256)),
+                rx_indicate: UnsafeCell::new(None),
+            },
+        }
+    }
+
+    fn hw(&self) -> &Mutex<SimUartHw> {
+        &UART_HW[self.num as usize]
+    }
+}
+
+impl DeviceSerial for SimUart {
+    fn init(&self, f: &OpenFlag) -> Result<(), SerialError> {
+        if self.num as usize >= UART_NUM {
+            return Err(SerialError::InitError);
+        }
+        Os::no_irq(|| unsafe {
+            UART_DEV_PTR[self.num as usize] = self as *const _ as usize;
+            UART_FLAG[self.num as usize] = *f;
+        });
+        let mut hw = self.hw().lock().unwrap();
+        hw.init = true;
+        hw.tx.clear();
+        Ok(())
+    }
+
+    fn uninit(&self) -> Result<(), SerialError> {
+        Os::no_irq(|| unsafe {
+            UART_DEV_PTR[self.num as usize] = 0;
+        });
+        let mut hw = self.hw().lock().unwrap();
+        hw.init = false;
+        hw.rx_irq = false;
+        hw.tx_irq = false;
+        Ok(())
+    }
+
+    fn get_helper(&self) -> &BspSerial {
+        &self.hp
+    }
+
+    fn read_char(&self) -> Result<u8, SerialError> {
+        let mut hw = self.hw().lock().unwrap();
+        let ch = hw.rx.pop_front().ok_or(SerialError::ReadError)?;
+        match hw.rx_error.take() {
+            Some(e) => Err(e),
+            None => Ok(ch),
+        }
+    }
+
+    fn read_able(&self) -> bool {
+        !self.hw().lock().unwrap().rx.is_empty()
+    }
+
+    fn write_char(&self, val: u8) -> Result<(), SerialError> {
+        let mut hw = self.hw().lock().unwrap();
+        if !hw.init || hw.suspended {
+            return Err(SerialError::UninitError);
+        }
+        hw.tx.push(val);
+        Ok(())
+    }
+
+    fn write_able(&self) -> bool {
+        let hw = self.hw().lock().unwrap();
+        match hw.tx_limit {
+            Some(l) => hw.tx.len() < l,
+            None => true,
+        }
+    }
+
+    fn write_finish(&self) -> bool {
+        true
+    }
+
+    // 接收寄存器中已有数据时，打开中断后立即进入中断
+    fn rx_irq_en(&self, f: bool) {
+        self.hw().lock().unwrap().rx_irq = f;
+        if f {
+            uart_irq(self.num);
+        }
+    }
+
+    // 发送寄存器总是空的，打开发送中断后立即进入中断
+    fn tx_irq_en(&self, f: bool) {
+        self.hw().lock().unwrap().tx_irq = f;
+        if f {
+            uart_irq(self.num);
+        }
+    }
+
+    fn dma_write(&self, ptr: *const u8, len: usize) -> Result<(), SerialError> {
+        let data = unsafe { core::slice::from_raw_parts(ptr, len) };
+        self.hw().lock().unwrap().tx.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn config_baud(&self, val: SerialBaudRate) -> Result<(), IOError> {
+        self.hw().lock().unwrap().baud = val as u32;
+        Ok(())
+    }
+
+    // 模拟的线路没有帧格式，接受 8 位数据、1 位停止位、无校验、低位在前
+    fn stop_bots(&self, val: SerialStopBits) -> Result<(), IOError> {
+        match val {
+            SerialStopBits::B1 => Ok(()),
+            _ => Err(IOError::UnsupportedError),
+        }
+    }
+
+    fn data_bits(&self, val: SerialDataBits) -> Result<(), IOError> {
+        match val {
+            SerialDataBits::B8 => Ok(()),
+            _ => Err(IOError::UnsupportedError),
+        }
+    }
+
+    fn parity(&self, val: SerialParity) -> Result<(), IOError> {
+        match val {
+            SerialParity::NONE => Ok(()),
+            _ => Err(IOError::UnsupportedError),
+        }
+    }
+
+    fn bit_order(&self, val: SerialBitOrder) -> Result<(), IOError> {
+        match val {
+            SerialBitOrder::LSB => Ok(()),
+            _ => Err(IOError::UnsupportedError),
+        }
+    }
+
+    fn get_baud(&self) -> Result<SerialBaudRate, IOError> {
+        let baud = self.hw().lock().unwrap().baud;
+        SerialBaudRate::from_u32(baud).ok_or(IOError::DataError)
+    }
+
+    fn update_flags(&self, f: OpenFlag) -> OpenFlag {
+        Os::no_irq(|| unsafe {
+            UART_FLAG[self.num as usize] = f;
+        });
+        f
+    }
+
+    // 模拟关闭时钟，波特率寄存器的内容丢失
+    fn suspend(&self) -> Result<(), SerialError> {
+        let mut hw = self.hw().lock().unwrap();
+        hw.suspended = true;
+        hw.baud = 0;
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<(), SerialError> {
+        self.hw().lock().unwrap().suspended = false;
+        Ok(())
+    }
+}
+
+// 模拟的中断处理函数，与真实板卡的 USARTx_IRQHandler 流程相同
+pub fn uart_irq(num: u32) {
+    Os::no_irq(|| unsafe {
+        let dev = UART_DEV_PTR[num as usize] as *mut SimUart;
+        if dev.is_null() {
+            return;
+        }
+        let flag = UART_FLAG[num as usize];
+        loop {
+            let ch = {
+                let mut hw = UART_HW[num as usize].lock().unwrap();
+                if hw.rx_irq {
+                    hw.rx.pop_front()
+                } else {
+                    None
+                }
+            };
+            match ch {
+                None => break,
+                Some(ch) => {
+                    bsp::irq_receive_char(dev, ch);
+                    if flag.get_read_c_type() {
+                        bsp::call_rx_indicate(dev);
+                    } else if flag.get_read_async() {
+                        bsp::notify_form_irq(dev);
+                    }
+                }
+            }
+        }
+        // 与真实硬件相同，发送寄存器可写时才进入发送中断
+        loop {
+            let tx_irq = UART_HW[num as usize].lock().unwrap().tx_irq;
+            if !tx_irq || !(*dev).write_able() {
+                break;
+            }
+            bsp::irq_send_char(dev);
+        }
+    });
+}
+
+// 模拟对端发送数据
+pub fn uart_inject_rx(num: u32, data: &[u8]) {
+    UART_HW[num as usize]
+        .lock()
+        .unwrap()
+        .rx
+        .extend(data.iter().cloned());
+    uart_irq(num);
+}
+
+// 取出目前为止发送出去的数据
+pub fn uart_take_tx(num: u32) -> Vec<u8> {
+    let mut hw = UART_HW[num as usize].lock().unwrap();
+    core::mem::take(&mut hw.tx)
+}
+
+// 下一个读出的字节带有错误标志
+pub fn uart_inject_rx_error(num: u32, e: SerialError) {
+    UART_HW[num as usize].lock().unwrap().rx_error = Some(e);
+}
+
+pub fn uart_limit_tx(num: u32, limit: Option<usize>) {
+    UART_HW[num as usize].lock().unwrap().tx_limit = limit;
+}
+
+pub fn uart_baud(num: u32) -> u32 {
+    UART_HW[num as usize].lock().unwrap().baud
+}
+
+pub fn uart_suspended(num: u32) -> bool {
+    UART_HW[num as usize].lock().unwrap().suspended
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{find, DevOpen};
+    use crate::bsp::host_sim::testing::sim_uart;
+    use crate::driver::DriverOps;
+
+    #[test]
+    fn irq_round_trip() {
+        let num = sim_uart("test/uart/irq");
+        let dev = find("test/uart/irq").unwrap();
+        let g = dev
+            .open(OpenFlag::zero().set_read_int(true).set_write_int(true))
+            .unwrap();
+        g.write(0, &"hello").unwrap();
+        assert_eq!(uart_take_tx(num), b"hello");
+        uart_inject_rx(num, b"abc");
+        let mut buf = [0u8; 8];
+        assert_eq!(g.read_into(0, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+        // 没有数据时返回 0
+        assert_eq!(g.read_into(0, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn block_round_trip() {
+        let num = sim_uart("test/uart/block");
+        let dev = find("test/uart/block").unwrap();
+        let g = dev
+            .open(OpenFlag::zero().set_read_block(true).set_write_block(true))
+            .unwrap();
+        assert_eq!(g.write_from(0, b"xy").unwrap(), 2);
+        assert_eq!(uart_take_tx(num), b"xy");
+        uart_inject_rx(num, b"z");
+        assert_eq!(g.read(0, 1).unwrap().take_u32().unwrap(), b'z' as u32);
+    }
+
+    #[test]
+    fn closed_uart_ignores_irq() {
+        let num = sim_uart("test/uart/closed");
+        // 没有打开时中断不会取走数据
+        uart_inject_rx(num, b"q");
+        assert_eq!(UART_HW[num as usize].lock().unwrap().rx.len(), 1);
+        let dev = find("test/uart/closed").unwrap();
+        let g = dev.open(OpenFlag::zero().set_read_int(true)).unwrap();
+        assert_eq!(g.read(0, 1).unwrap().take_u32().unwrap(), b'q' as u32);
+    }
+}
@@ -3,3 +3,6 @@ mod stm32f746zg_nucleo;
 
 #[cfg(feature = "art_pi")]
 mod art_pi;
+
+#[cfg(feature = "host_sim")]
+pub mod host_sim;
@@ -0,0 +1,143 @@
+//! NUCLEO-F746ZG 的板卡描述以及 STM32F7 的引脚操作
+
+use super::spi_bus::Stm32f746SPIBus;
+use super::uart::Stm32f746Uart;
+use super::{hal, DP};
+use crate::alloc::boxed::Box;
+use crate::alloc::sync::Arc;
+use crate::board::{BoardChip, LedDesc, Pin, PinMode, Port, Pull, Speed, SpiDesc, UartDesc};
+use crate::board_table;
+use crate::device::register_device;
+use crate::device::serial::Serial;
+use crate::device::spi_bus::{BusSpiHandler, BusSpiOps, SpiBusDev, SpiBusRef};
+use crate::error::IOError;
+use crate::Mutex;
+
+board_table! {
+    chip: CHIP;
+    pub(crate) static BOARD = [
+        uart UartDesc {
+            name: "uart6",
+            num: 6,
+            tx: Pin::new(Port::G, 14),
+            rx: Pin::new(Port::G, 9),
+            af: 8,
+            alias: &["console"],
+        },
+        led LedDesc {
+            name: "led0",
+            pin: Pin::new(Port::C, 6),
+            active_low: false,
+            pull: Pull::Down,
+        },
+        spi SpiDesc {
+            name: "spi1",
+            num: 1,
+            sck: Pin::new(Port::A, 5),
+            miso: Pin::new(Port::A, 6),
+            mosi: Pin::new(Port::A, 7),
+            af: 5,
+            cs: &[Pin::new(Port::D, 14)],
+        },
+    ];
+}
+
+// 内核主频，用于微秒延时
+const CPU_MHZ: u32 = 216;
+
+pub(crate) struct Stm32F7;
+
+pub(crate) static CHIP: Stm32F7 = Stm32F7;
+
+// 各个 GPIO 端口的寄存器相同，地址间隔 0x400
+fn gpio(port: Port) -> &'static hal::gpioa::RegisterBlock {
+    let addr = hal::GPIOA::ptr() as usize + port as usize * 0x400;
+    unsafe { &*(addr as *const hal::gpioa::RegisterBlock) }
+}
+
+// 修改寄存器中从 shift 开始、宽度为 width 的位
+fn field(old: u32, shift: u32, width: u32, val: u32) -> u32 {
+    let mask = ((1 << width) - 1) << shift;
+    (old & !mask) | ((val << shift) & mask)
+}
+
+impl BoardChip for Stm32F7 {
+    fn setup(&self, pin: Pin, mode: PinMode) {
+        // AHB1ENR 的第 n 位为端口 n 的时钟
+        DP.0.RCC
+            .ahb1enr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << pin.port as u32) });
+
+        let (moder, open_drain, pull, speed, af) = match mode {
+            PinMode::Input(pull) => (0, false, pull, Speed::Low, None),
+            PinMode::Output {
+                open_drain,
+                pull,
+                speed,
+            } => (1, open_drain, pull, speed, None),
+            PinMode::Alternate {
+                af,
+                open_drain,
+                pull,
+                speed,
+            } => (2, open_drain, pull, speed, Some(af)),
+        };
+        let pull = match pull {
+            Pull::None => 0,
+            Pull::Up => 1,
+            Pull::Down => 2,
+        };
+        let n = pin.num as u32;
+        let r = gpio(pin.port);
+        r.moder
+            .modify(|v, w| unsafe { w.bits(field(v.bits(), n * 2, 2, moder)) });
+        r.otyper
+            .modify(|v, w| unsafe { w.bits(field(v.bits(), n, 1, open_drain as u32)) });
+        r.ospeedr
+            .modify(|v, w| unsafe { w.bits(field(v.bits(), n * 2, 2, speed as u32)) });
+        r.pupdr
+            .modify(|v, w| unsafe { w.bits(field(v.bits(), n * 2, 2, pull)) });
+        if let Some(af) = af {
+            if n < 8 {
+                r.afrl
+                    .modify(|v, w| unsafe { w.bits(field(v.bits(), n * 4, 4, af as u32)) });
+            } else {
+                r.afrh
+                    .modify(|v, w| unsafe { w.bits(field(v.bits(), (n - 8) * 4, 4, af as u32)) });
+            }
+        }
+    }
+
+    fn set(&self, pin: Pin, high: bool) {
+        let n = pin.num as u32;
+        let bit = if high { 1 << n } else { 1 << (n + 16) };
+        gpio(pin.port).bsrr.write(|w| unsafe { w.bits(bit) });
+    }
+
+    fn get(&self, pin: Pin) -> bool {
+        gpio(pin.port).idr.read().bits() & (1 << pin.num) != 0
+    }
+
+    fn delay_us(&self, us: u32) {
+        cortex_m::asm::delay(us * CPU_MHZ);
+    }
+
+    // 串口驱动只实现了 USART6
+    fn register_uart(&'static self, desc: &'static UartDesc) -> Result<(), IOError> {
+        if desc.num != 6 {
+            return Err(IOError::UnsupportedError);
+        }
+        register_device(Serial::new(Stm32f746Uart::new(desc.num)), desc.name)
+    }
+
+    // SPI 总线驱动只实现了 SPI1，描述表中的第一个片选作为总线默认的片选
+    fn register_spi(&'static self, desc: &'static SpiDesc) -> Result<(), IOError> {
+        if desc.num != 1 {
+            return Err(IOError::UnsupportedError);
+        }
+        let spi = Stm32f746SPIBus::new(desc.cs.first().copied());
+        let bus: Box<dyn BusSpiOps + Send> = Box::new(BusSpiHandler::new(spi));
+        let bus: SpiBusRef = Arc::new(Mutex::new(bus).unwrap());
+        register_device(SpiBusDev::new(bus), desc.name)
+    }
+}
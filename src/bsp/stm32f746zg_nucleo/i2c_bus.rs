@@ -2,6 +2,7 @@
 
 use super::DP;
 use crate::device::i2c_bus::{I2CBusError, SBusI2CBase};
+use crate::os::{Os, OsApi};
 use crate::OpenFlag;
 use core::sync::atomic::{AtomicBool, AtomicU32};
 use rtt_rs::print;
@@ -29,7 +30,7 @@ fn inner_delay() -> i32 {
 }
 
 fn inner_delay_ms() {
-    rtt_rs::thread::Thread::delay(1);
+    Os::delay(1);
 }
 
 impl SBusI2CBase for Stm32f746I2CBus {
@@ -45,16 +46,16 @@ impl SBusI2CBase for Stm32f746I2CBus {
         // 设置 8.9 号引脚为输出模式, 默认推挽
         rc.moder.modify(|_, w| w.moder6().output());
         rb.moder.modify(|_, w| w.moder15().output());
-        rtt_rs::thread::Thread::delay(1);
+        Os::delay(1);
         // 设置输出模式
         // TODO: 由于没有真正的I2C总线，目前使用的是逻辑分析仪采集信号，所以设置为输出上拉
         rc.ospeedr.modify(|_, w| w.ospeedr6().low_speed());
         rb.ospeedr.modify(|_, w| w.ospeedr15().low_speed());
-        rtt_rs::thread::Thread::delay(1);
+        Os::delay(1);
         // 设置为上拉
         rc.pupdr.modify(|_, w| w.pupdr6().pull_up());
         rb.pupdr.modify(|_, w| w.pupdr15().pull_up());
-        rtt_rs::thread::Thread::delay(1);
+        Os::delay(1);
 
         rc.bsrr.write(|w| w.bs6().set_bit());
         rb.bsrr.write(|w| w.bs15().set_bit());
@@ -1,31 +1,55 @@
 #![allow(dead_code)]
 
+use super::board::CHIP;
 use super::DP;
+use crate::board::{BoardChip, Pin};
+use crate::device::base::DynCycleQueue;
 use crate::device::spi_bus::bsp::BspBusSpi;
-use crate::device::spi_bus::BusSpi;
+use crate::device::spi_bus::{BusSpi, TRANS_TIMEOUT_MS};
 use crate::device::spi_device::{SpiConfig, SpiError};
+use crate::os::spin_until;
 use crate::OpenFlag;
+use core::cell::UnsafeCell;
 
-pub struct Stm32f746SPIBus {}
+pub struct Stm32f746SPIBus {
+    hp: BspBusSpi,
+    // 总线默认的片选引脚，低电平选中
+    cs: Option<Pin>,
+}
 
 impl Stm32f746SPIBus {
-    pub fn new() -> Self {
-        Stm32f746SPIBus {}
+    pub fn new(cs: Option<Pin>) -> Self {
+        Stm32f746SPIBus {
+            cs,
+            hp: BspBusSpi {
+                r_buffer: UnsafeCell::new(DynCycleQueue::new(32)),
+                w_buffer: UnsafeCell::new(DynCycleQueue::new(32)),
+            },
+        }
     }
 }
 
 impl BusSpi for Stm32f746SPIBus {
-    fn init(&self, _f: &OpenFlag, _cfg: &SpiConfig) -> Result<(), SpiError> {
-        todo!()
+    // 在默认配置的基础上修改时钟极性和相位
+    fn init(&self, _f: &OpenFlag, cfg: &SpiConfig) -> Result<(), SpiError> {
+        self.np_init()?;
+        let (cpol, cpha) = cfg.s_type().cpol_cpha();
+        let spi = &DP.0.SPI1;
+        // 修改 CR1 前需要关闭 SPI
+        spi.cr1.modify(|_, w| w.spe().clear_bit());
+        spi.cr1.modify(|_, w| {
+            w.cpol().bit(cpol);
+            w.cpha().bit(cpha);
+            w
+        });
+        spi.cr1.modify(|_, w| w.spe().set_bit());
+        Ok(())
     }
 
     /// 无参数初始化
     /// 默认SPI1
     fn np_init(&self) -> Result<(), SpiError> {
-        use super::spi_gpio::gpio_config;
-        use super::spi_gpio::SpiN;
-
-        gpio_config(SpiN::Spi1);
+        // 引脚由板卡描述表配置
         let rcc = &DP.0.RCC;
         rcc.apb2enr.modify(|_, w| w.spi1en().set_bit());
         rcc.apb2rstr.modify(|_, w| w.spi1rst().set_bit());
@@ -55,42 +79,40 @@ impl BusSpi for Stm32f746SPIBus {
     }
 
     fn cs(&self, f: bool) {
-        use super::spi_gpio;
-        if f {
-            spi_gpio::gpio_1_cs_on()
-        } else {
-            spi_gpio::gpio_1_cs_off()
+        if let Some(pin) = self.cs {
+            CHIP.set(pin, !f);
         }
     }
 
+    // 最后一个设备关闭后调用，关闭 SPI 和它的时钟
     fn uninit(&self) -> Result<(), SpiError> {
-        todo!()
+        DP.0.SPI1.cr1.modify(|_, w| w.spe().clear_bit());
+        DP.0.RCC.apb2enr.modify(|_, w| w.spi1en().clear_bit());
+        Ok(())
     }
 
     fn trans_bit(&self, data: u8) -> Result<u8, SpiError> {
         let spi = &DP.0.SPI1;
-        loop {
-            if spi.sr.read().txe().bit_is_set() {
-                break;
-            }
-            rtt_rs::thread::Thread::_yield();
-        }
+        // 一个字节只需要几微秒，忙等标志位而不是每次睡眠 1ms
+        spin_until(|| spi.sr.read().txe().bit_is_set(), TRANS_TIMEOUT_MS)
+            .map_err(|_| SpiError::Timeout)?;
         spi.dr.write(|w| unsafe { w.bits(data as _) });
-        loop {
-            if spi.sr.read().rxne().bit_is_set() {
-                break;
-            }
-            rtt_rs::thread::Thread::_yield();
-        }
+        spin_until(|| spi.sr.read().rxne().bit_is_set(), TRANS_TIMEOUT_MS)
+            .map_err(|_| SpiError::Timeout)?;
         let ret = spi.dr.read().bits() as u8;
         Ok(ret)
     }
 
-    fn trans_bits_dma(&self, _ptr: *const u8, _len: usize) {
-        todo!()
+    // 还没有配置 DMA 通道，逐字节阻塞发送
+    fn trans_bits_dma(&self, ptr: *const u8, len: usize) -> Result<(), SpiError> {
+        let data = unsafe { core::slice::from_raw_parts(ptr, len) };
+        for b in data {
+            self.trans_bit(*b)?;
+        }
+        Ok(())
     }
 
     fn get_helper(&self) -> &BspBusSpi {
-        todo!()
+        &self.hp
     }
 }
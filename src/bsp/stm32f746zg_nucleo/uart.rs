@@ -1,21 +1,42 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
-use super::DP;
+use super::{hal, DP};
 use crate::device::base::DynCycleQueue;
 use crate::device::serial::bsp::BspSerial;
-use crate::device::serial::DeviceSerial;
-use crate::device::serial::{bsp, SerialBaudRate, SerialError};
-use crate::OpenFlag;
+use crate::device::serial::{
+    bsp, DeviceSerial, SerialBaudRate, SerialBitOrder, SerialDataBits, SerialError, SerialParity,
+    SerialStopBits,
+};
+use crate::os::{Os, OsApi};
+use crate::{IOError, OpenFlag};
 use core::cell::UnsafeCell;
 
 // 保存了设备的指针，由于都是被Pin住的设备，没有风险
 static mut UART_DEV_PTR: [usize; 8] = [0 as _; 8];
+static mut UART_FLAG: [OpenFlag; 8] = [OpenFlag::zero(); 8];
 
 pub struct Stm32f746Uart {
     num: u32,
     hp: bsp::BspSerial,
 }
 
+// 读取并清除接收错误标志，ORE 不清除时会一直触发中断
+fn take_rx_error(ut: &hal::usart1::RegisterBlock) -> Option<SerialError> {
+    let isr = ut.isr.read();
+    let err = if isr.ore().bit_is_set() {
+        SerialError::Overrun
+    } else if isr.fe().bit_is_set() {
+        SerialError::Framing
+    } else if isr.pe().bit_is_set() {
+        SerialError::Parity
+    } else {
+        return None;
+    };
+    ut.icr
+        .write(|w| w.orecf().set_bit().fecf().set_bit().pecf().set_bit());
+    Some(err)
+}
+
 fn cal_brr(pclk2: u32, bound: u32) -> u32 {
     (pclk2 * 1000000 + bound / 2) / bound
 }
@@ -29,7 +50,6 @@ impl Stm32f746Uart {
                 r_buffer: UnsafeCell::new(DynCycleQueue::new(32)),
                 w_buffer: UnsafeCell::new(DynCycleQueue::new(32)),
                 rx_indicate: UnsafeCell::new(None),
-                ch: 0,
             },
         }
     }
@@ -45,32 +65,15 @@ impl DeviceSerial for Stm32f746Uart {
             // 将地址传递到全局变量供中断使用
             unsafe {
                 UART_DEV_PTR[self.num as usize] = self as *const _ as usize;
+                UART_FLAG[self.num as usize] = f.clone();
             }
         }
 
-        // TX: PG14
-        // RX: PG9
-
-        // 时钟的配置
+        // 引脚（TX: PG14，RX: PG9）由板卡描述表配置
         let rcc = &DP.0.RCC;
-        // 使能IO引脚的时钟
-        rcc.ahb1enr.modify(|_, w| w.gpiogen().set_bit());
         // 使能串口6的时钟
         rcc.apb2enr.modify(|_, w| w.usart6en().set_bit());
 
-        // 设置串口引脚
-        let rg = &DP.0.GPIOG;
-        rg.moder.modify(|_, w| w.moder14().alternate());
-        rg.moder.modify(|_, w| w.moder9().alternate());
-        rg.otyper.modify(|_, w| w.ot14().push_pull());
-        rg.otyper.modify(|_, w| w.ot9().push_pull());
-        rg.pupdr.modify(|_, w| w.pupdr14().floating());
-        rg.pupdr.modify(|_, w| w.pupdr9().floating());
-        rg.ospeedr.modify(|_, w| w.ospeedr14().high_speed());
-        rg.ospeedr.modify(|_, w| w.ospeedr9().high_speed());
-        rg.afrh.modify(|_, w| w.afrh14().af8());
-        rg.afrh.modify(|_, w| w.afrh9().af8());
-
         // 串口复位
         rcc.apb2rstr.modify(|_, w| w.usart6rst().set_bit());
         rcc.apb2rstr.modify(|_, w| w.usart6rst().clear_bit());
@@ -97,19 +100,38 @@ impl DeviceSerial for Stm32f746Uart {
         Ok(())
     }
 
+    // 关闭串口和串口6的时钟，寄存器的配置在 STOP 模式下保持
+    fn suspend(&self) -> Result<(), SerialError> {
+        let ut = &DP.0.USART6;
+        ut.cr1.modify(|_, w| w.ue().clear_bit());
+        DP.0.RCC.apb2enr.modify(|_, w| w.usart6en().clear_bit());
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<(), SerialError> {
+        DP.0.RCC.apb2enr.modify(|_, w| w.usart6en().set_bit());
+        let ut = &DP.0.USART6;
+        ut.cr1.modify(|_, w| w.ue().set_bit());
+        Ok(())
+    }
+
     fn get_helper(&self) -> &BspSerial {
         &self.hp
     }
 
     fn read_char(&self) -> Result<u8, SerialError> {
-        // 从寄存器读取一个值
-        let r = Ok(self.hp.ch);
-        r
+        let ut = &DP.0.USART6;
+        let err = take_rx_error(ut);
+        // 从寄存器读取一个值，出错时也要读出以清除 RXNE
+        let ch = ut.rdr.read().bits() as u8;
+        match err {
+            Some(e) => Err(e),
+            None => Ok(ch),
+        }
     }
 
     fn read_able(&self) -> bool {
-        // TODO: 判断当前是否有数据可读
-        true
+        DP.0.USART6.isr.read().rxne().bit_is_set()
     }
 
     fn write_char(&self, val: u8) -> Result<(), SerialError> {
@@ -131,16 +153,54 @@ impl DeviceSerial for Stm32f746Uart {
     }
 
     fn write_finish(&self) -> bool {
-        // TODO: 判断当前是否写完成
-        true
+        self.write_able()
     }
 
-    fn dma_write(&self, ptr: *const u8, len: usize) {
-        // TODO: 调用dma来写数据
+    fn rx_irq_en(&self, f: bool) {
+        DP.0.USART6.cr1.modify(|_, w| w.rxneie().bit(f));
     }
 
-    fn config_baud(&self, val: SerialBaudRate) {
-        // TODO: 控制波特率
+    fn tx_irq_en(&self, f: bool) {
+        DP.0.USART6.cr1.modify(|_, w| w.txeie().bit(f));
+    }
+
+    // 还没有配置 DMA 通道
+    fn dma_write(&self, ptr: *const u8, len: usize) -> Result<(), SerialError> {
+        Err(SerialError::WriteError)
+    }
+
+    // 以下配置暂不支持，保持 init 时的配置
+    fn config_baud(&self, val: SerialBaudRate) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn stop_bots(&self, val: SerialStopBits) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn data_bits(&self, val: SerialDataBits) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn parity(&self, val: SerialParity) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn bit_order(&self, val: SerialBitOrder) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    // init 时固定配置为 115200
+    fn get_baud(&self) -> Result<SerialBaudRate, IOError> {
+        Ok(SerialBaudRate::B115200)
+    }
+
+    // 中断处理函数根据 UART_FLAG 决定处理方式
+    fn update_flags(&self, f: OpenFlag) -> OpenFlag {
+        Os::no_irq(|| unsafe {
+            UART_FLAG[self.num as usize] = f.clone();
+        });
+        f
     }
 }
 
@@ -150,19 +210,25 @@ pub extern "C" fn USART6_IRQHandler() {
         crate::rt_interrupt_enter();
     }
     let ut = &DP.0.USART6;
-    let dev;
     unsafe {
-        dev = UART_DEV_PTR[6] as *const Stm32f746Uart as *mut Stm32f746Uart;
-        if ut.isr.read().rxne().bit_is_set() {
+        let dev = UART_DEV_PTR[6] as *const Stm32f746Uart as *mut Stm32f746Uart;
+        let flag = UART_FLAG[6];
+        if take_rx_error(ut).is_some() {
+            // 中断中无法返回错误，出错的数据直接丢弃
+            let _ = ut.rdr.read();
+        } else if ut.isr.read().rxne().bit_is_set() {
+            // 数据放入接收缓冲区，中断读从缓冲区取走
             let data = ut.rdr.read().bits();
-            (*dev).hp.ch = data as u8;
-            match *((*dev).hp.rx_indicate.get_mut()) {
-                None => {}
-                Some(func) => {
-                    func();
-                }
+            bsp::irq_receive_char(dev, data as _);
+            if flag.get_read_c_type() {
+                bsp::call_rx_indicate(dev);
+            } else if flag.get_read_async() {
+                bsp::notify_form_irq(dev);
             }
         }
+        if ut.isr.read().txe().bit_is_set() && flag.get_write_int() {
+            bsp::irq_send_char(dev);
+        }
     }
     unsafe {
         crate::rt_interrupt_leave();
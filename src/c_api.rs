@@ -8,17 +8,26 @@ use crate::alloc::boxed::Box;
 use crate::api::find;
 use crate::BTreeMap;
 use crate::Driver;
-use crate::{Arc, OpenFlag, StdData};
+use crate::Mutex;
+use crate::{Arc, IOError, OpenFlag, StdData};
 use alloc::string::String;
 use lazy_static::lazy_static;
 use rtt_rs::base::{CStr, CVoid};
-use rtt_rs::mutex::Mutex;
 
 // 通过设备指针寻找guard指针
 lazy_static! {
     pub static ref DEVMAP: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new()).unwrap();
 }
 
+extern "C" {
+    fn rt_set_errno(no: isize);
+}
+
+// 出错时记录 rt-thread 的错误码，C 侧通过 rt_get_errno 取得
+fn set_errno(e: &IOError) {
+    unsafe { rt_set_errno(e.to_errno()) }
+}
+
 // 返回查找设备标号
 pub extern "C" fn rt_device_find(name: *const u8) -> usize {
     let name = CStr::new(name);
@@ -30,32 +39,37 @@ pub extern "C" fn rt_device_find(name: *const u8) -> usize {
             let dev = Box::new(dev);
             Box::into_raw(dev) as usize
         }
-        Err(_) => 0,
+        Err(e) => {
+            set_errno(&e);
+            0
+        }
     };
 }
 
 use crate::api::DevOpen;
 use crate::driver::DriverOps;
-use crate::guard::DriverGuard;
+use crate::guard::OwnedDriverGuard;
 use core::mem;
 
 pub extern "C" fn rt_device_open(device: *mut CVoid, _flag: u16) -> usize {
-    let dev;
-    unsafe {
-        dev = Box::from_raw(device as *mut Arc<Mutex<Driver>>);
+    let dev = unsafe { &*(device as *const Arc<Mutex<Driver>>) };
+    match dev.open_owned(&OpenFlag::zero()) {
+        Ok(dev_guard) => Box::into_raw(Box::new(dev_guard)) as usize,
+        Err(e) => {
+            set_errno(&e);
+            0
+        }
     }
-    let dev_ref = Box::leak(dev);
-    let dev_guard = dev_ref.open(&OpenFlag::zero());
-    let dev_guard = Box::new(dev_guard);
-    Box::into_raw(dev_guard) as usize
 }
 
 pub extern "C" fn rt_device_read(device: *mut CVoid) -> usize {
     let dev;
     unsafe {
-        dev = Box::from_raw(device as *mut DriverGuard);
+        dev = Box::from_raw(device as *mut OwnedDriverGuard);
+    }
+    if let Err(e) = dev.read(0, 0) {
+        set_errno(&e);
     }
-    dev.read(0, 0);
     mem::forget(dev);
     0
 }
@@ -63,9 +77,20 @@ pub extern "C" fn rt_device_read(device: *mut CVoid) -> usize {
 pub extern "C" fn rt_device_write(device: *mut CVoid) -> usize {
     let dev;
     unsafe {
-        dev = Box::from_raw(device as *mut DriverGuard);
+        dev = Box::from_raw(device as *mut OwnedDriverGuard);
     }
     //dev.write(0, &StdData::U32(0));
     mem::forget(dev);
     0
 }
+
+// 释放 rt_device_open 得到的 guard，关闭设备
+pub extern "C" fn rt_device_close(device: *mut CVoid) -> usize {
+    if device.is_null() {
+        return 0;
+    }
+    unsafe {
+        drop(Box::from_raw(device as *mut OwnedDriverGuard));
+    }
+    0
+}
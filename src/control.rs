@@ -0,0 +1,36 @@
+//! 类型化的控制命令
+//! 每一类设备定义自己的命令枚举和对应的应答类型
+//! 命令在经过 DeviceOps 时被擦除类型，驱动再把它还原
+//! 驱动不认识的命令统一返回 IOError::UnsupportedError
+
+use crate::alloc::boxed::Box;
+use crate::error::IOError;
+use core::any::Any;
+
+pub trait ControlCmd: Send + 'static {
+    type Reply: Send + 'static;
+}
+
+// 擦除类型后的命令与应答
+pub type RawCmd = Box<dyn Any + Send>;
+pub type RawReply = Box<dyn Any + Send>;
+
+// 驱动使用：还原命令，类型不符时返回不支持
+pub fn take_cmd<C: ControlCmd>(cmd: RawCmd) -> Result<C, IOError> {
+    cmd.downcast::<C>()
+        .map(|c| *c)
+        .map_err(|_| IOError::UnsupportedError)
+}
+
+// 驱动使用：打包应答
+pub fn make_reply<C: ControlCmd>(reply: C::Reply) -> RawReply {
+    Box::new(reply)
+}
+
+// 框架使用：还原应答
+pub(crate) fn take_reply<C: ControlCmd>(reply: RawReply) -> Result<C::Reply, IOError> {
+    reply
+        .downcast::<C::Reply>()
+        .map(|r| *r)
+        .map_err(|_| IOError::DataError)
+}
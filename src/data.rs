@@ -51,22 +51,45 @@ impl StdData {
             false
         }
     }
+
+    // 数据的长度，用于统计读写的字节数
+    // 值数据类型(如串口读到的一个字符)按 1 计算
+    pub fn len(&self) -> usize {
+        match self {
+            StdData::Bytes(a) => a.len(),
+            StdData::U32(_) | StdData::U8(_) => 1,
+            _ => 0,
+        }
+    }
 }
 
 pub trait ToMakeStdData {
     fn make_data(&self) -> StdData;
+
+    // 数据的长度，默认会构造一次数据，长数据类型应该重新实现
+    fn data_len(&self) -> usize {
+        self.make_data().len()
+    }
 }
 
 impl ToMakeStdData for u32 {
     fn make_data(&self) -> StdData {
         StdData::U32(self.clone())
     }
+
+    fn data_len(&self) -> usize {
+        1
+    }
 }
 
 impl ToMakeStdData for u8 {
     fn make_data(&self) -> StdData {
         StdData::U8(self.clone())
     }
+
+    fn data_len(&self) -> usize {
+        1
+    }
 }
 
 impl ToMakeStdData for OpenFlag {
@@ -83,6 +106,30 @@ impl ToMakeStdData for &str {
         }
         StdData::Bytes(a)
     }
+
+    fn data_len(&self) -> usize {
+        self.len()
+    }
+}
+
+impl ToMakeStdData for &[u8] {
+    fn make_data(&self) -> StdData {
+        StdData::Bytes(Vec::from(*self))
+    }
+
+    fn data_len(&self) -> usize {
+        self.len()
+    }
+}
+
+impl ToMakeStdData for Vec<u8> {
+    fn make_data(&self) -> StdData {
+        StdData::Bytes(self.clone())
+    }
+
+    fn data_len(&self) -> usize {
+        self.len()
+    }
 }
 
 use crate::alloc::boxed::Box;
@@ -90,18 +137,49 @@ use alloc::fmt::{Debug, Display, Formatter};
 use core::any::Any;
 use paste::paste;
 
+// 0: 标志位
+// 1: 阻塞读超时时间，单位 ms，0 表示一直等待
+// 2: 阻塞写超时时间，单位 ms，0 表示一直等待
 #[derive(Copy, Clone)]
-pub struct OpenFlag(u32);
+pub struct OpenFlag(u32, u32, u32);
 
 impl Debug for OpenFlag {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> alloc::fmt::Result {
-        todo!()
+    fn fmt(&self, f: &mut Formatter<'_>) -> alloc::fmt::Result {
+        f.debug_struct("OpenFlag")
+            .field("bits", &format_args!("{:#06x}", self.0))
+            .field("read_timeout", &self.get_read_timeout())
+            .field("write_timeout", &self.get_write_timeout())
+            .finish()
     }
 }
 
+// 按 "only|read_int|read_block rt=10 wt=0" 的格式显示
 impl Display for OpenFlag {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> alloc::fmt::Result {
-        todo!()
+    fn fmt(&self, f: &mut Formatter<'_>) -> alloc::fmt::Result {
+        let names = [
+            (self.get_only(), "only"),
+            (self.get_read_int(), "read_int"),
+            (self.get_write_int(), "write_int"),
+            (self.get_read_block(), "read_block"),
+            (self.get_write_block(), "write_block"),
+            (self.get_read_c_type(), "read_c_type"),
+            (self.get_read_async(), "read_async"),
+            (self.get_write_async(), "write_async"),
+        ];
+        let mut first = true;
+        for (set, name) in names.iter() {
+            if *set {
+                if !first {
+                    write!(f, "|")?;
+                }
+                write!(f, "{}", name)?;
+                first = false;
+            }
+        }
+        if first {
+            write!(f, "none")?;
+        }
+        write!(f, " rt={} wt={}", self.1, self.2)
     }
 }
 
@@ -127,7 +205,33 @@ macro_rules! flag {
 //  打开标志的设置函数
 impl OpenFlag {
     pub const fn zero() -> Self {
-        OpenFlag(0)
+        OpenFlag(0, 0, 0)
+    }
+
+    pub fn set_read_timeout(&mut self, ms: u32) -> &mut Self {
+        self.1 = ms;
+        self
+    }
+
+    pub fn get_read_timeout(&self) -> Option<u32> {
+        if self.1 == 0 {
+            None
+        } else {
+            Some(self.1)
+        }
+    }
+
+    pub fn set_write_timeout(&mut self, ms: u32) -> &mut Self {
+        self.2 = ms;
+        self
+    }
+
+    pub fn get_write_timeout(&self) -> Option<u32> {
+        if self.2 == 0 {
+            None
+        } else {
+            Some(self.2)
+        }
     }
 
     // 普通的读操作是尝试读，可能返回读失败
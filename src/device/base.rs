@@ -1,6 +1,7 @@
 //! 提供了基础的数据结构
 //! CycleQueue 是一个静态不可扩容的环形队列
 //! 该队列支持强行推入操作，即抛弃掉最先进入的值，推入想要的值
+//! 强行推入时被抛弃的值的数量会被记录下来，用于统计
 
 #![allow(dead_code)]
 
@@ -11,6 +12,7 @@ pub struct CycleQueue<T, N: ArrayLength<Option<T>>> {
     data: GenericArray<Option<T>, N>,
     head: usize,
     tail: usize,
+    dropped: usize,
 }
 
 impl<T, N: ArrayLength<Option<T>>> CycleQueue<T, N> {
@@ -19,6 +21,7 @@ impl<T, N: ArrayLength<Option<T>>> CycleQueue<T, N> {
             data: GenericArray::default(),
             head: 0,
             tail: 0,
+            dropped: 0,
         }
     }
 
@@ -28,7 +31,7 @@ impl<T, N: ArrayLength<Option<T>>> CycleQueue<T, N> {
     }
 
     pub fn length(&self) -> usize {
-        (self.tail - self.head + N::to_usize()) / N::to_usize()
+        (self.tail + N::to_usize() - self.head) % N::to_usize()
     }
 
     pub fn free_len(&self) -> usize {
@@ -71,10 +74,16 @@ impl<T, N: ArrayLength<Option<T>>> CycleQueue<T, N> {
         }
     }
 
+    // 强行推入时抛弃的值的总数
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
     pub fn force_push(&mut self, val: T) -> Option<T> {
         let pop;
         if self.full() {
             pop = self.pop().unwrap();
+            self.dropped = self.dropped.wrapping_add(1);
             let _ = self.push(val);
             Some(pop)
         } else {
@@ -89,6 +98,7 @@ pub struct DynCycleQueue<T> {
     capacity: usize,
     head: usize,
     tail: usize,
+    dropped: usize,
 }
 
 impl<T: Clone> DynCycleQueue<T> {
@@ -101,6 +111,7 @@ impl<T: Clone> DynCycleQueue<T> {
             capacity,
             head: 0,
             tail: 0,
+            dropped: 0,
         }
     }
 
@@ -117,7 +128,7 @@ impl<T: Clone> DynCycleQueue<T> {
     }
 
     pub fn length(&self) -> usize {
-        (self.tail - self.head + self.capacity) / self.capacity
+        (self.tail + self.capacity - self.head) % self.capacity
     }
 
     pub fn free_len(&self) -> usize {
@@ -160,10 +171,16 @@ impl<T: Clone> DynCycleQueue<T> {
         }
     }
 
+    // 强行推入时抛弃的值的总数
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
     pub fn force_push(&mut self, val: T) -> Option<T> {
         let pop;
         if self.full() {
             pop = self.pop().unwrap();
+            self.dropped = self.dropped.wrapping_add(1);
             let _ = self.push(val);
             Some(pop)
         } else {
@@ -1,11 +1,42 @@
-use crate::OpenFlag;
+use crate::alloc::boxed::Box;
+use crate::alloc::sync::Arc;
+use crate::device::{DeviceClass, DeviceOps};
+use crate::error::DeviceError;
+use crate::os::block_until;
+use crate::{IOError, Mutex, OpenFlag, ToMakeStdData};
 use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
 use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
-// TODO: 相关的错误处理还没有做
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum I2CBusError {
     InitError,
+    // 等待从机释放 SCL 超时
+    Timeout,
+    // 从机没有应答，参数为从机地址
+    Nack(u16),
+    // 硬件 I2C 报告的总线错误或仲裁失败
+    Bus,
+    // 地址超出地址类型的范围，或者地址类型没有设置
+    Address(u16),
+}
+
+impl From<I2CBusError> for IOError {
+    fn from(e: I2CBusError) -> Self {
+        IOError::Device(DeviceError::I2CBus(e))
+    }
+}
+
+impl Display for I2CBusError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            I2CBusError::InitError => write!(f, "init failed"),
+            I2CBusError::Timeout => write!(f, "clock stretching timed out"),
+            I2CBusError::Nack(a) => write!(f, "no ack from address {:#04x}", a),
+            I2CBusError::Bus => write!(f, "bus error"),
+            I2CBusError::Address(a) => write!(f, "invalid address {:#05x}", a),
+        }
+    }
 }
 
 pub trait SBusI2CBase {
@@ -23,6 +54,12 @@ pub trait SBusI2CBase {
 
     fn get_delay_time_us(&self) -> u32;
     fn delay_us(&self, us: u32);
+
+    // 时钟延展的超时时间，单位 ms
+    // 返回 None 时不检查从机是否释放 SCL
+    fn get_timeout_ms(&self) -> Option<u32> {
+        None
+    }
 }
 
 pub struct SBusI2C<T: SBusI2CBase> {
@@ -32,8 +69,7 @@ pub struct SBusI2C<T: SBusI2CBase> {
 #[allow(dead_code)]
 impl<T: SBusI2CBase> SBusI2C<T> {
     pub(crate) fn new(bus_raw: T) -> SBusI2C<T> {
-        let ret = SBusI2C { bus_raw };
-        ret
+        SBusI2C { bus_raw }
     }
 }
 
@@ -50,7 +86,7 @@ pub enum I2CAddressType {
     Uninit,
 }
 
-/// 目前没有支持 ack 相关的操作和 10BITS 的地址
+/// 目前没有支持 ack 相关的操作
 #[allow(dead_code)]
 pub struct I2CMsg {
     pub(crate) address: u16,
@@ -62,9 +98,60 @@ pub struct I2CMsg {
     pub(crate) buf: Vec<u8>,
 }
 
+impl I2CMsg {
+    // 7 位地址的读消息，读到的数据通过 buf 取得
+    pub fn read(address: u16, len: u16) -> I2CMsg {
+        I2CMsg {
+            address,
+            address_type: I2CAddressType::Bits7,
+            send_ack: false,
+            ignore_ack: false,
+            rw: I2CRW::Read,
+            len,
+            buf: alloc::vec![0; len as usize],
+        }
+    }
+
+    // 7 位地址的写消息
+    pub fn write(address: u16, buf: Vec<u8>) -> I2CMsg {
+        I2CMsg {
+            address,
+            address_type: I2CAddressType::Bits7,
+            send_ack: false,
+            ignore_ack: false,
+            rw: I2CRW::Write,
+            len: buf.len() as u16,
+            buf,
+        }
+    }
+
+    pub fn buf(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl I2CAddressType {
+    // 检查地址是否在地址类型的范围内
+    pub fn check(self, address: u16) -> Result<(), I2CBusError> {
+        let max = match self {
+            I2CAddressType::Bits7 => 0x7F,
+            I2CAddressType::Bits10 => 0x3FF,
+            I2CAddressType::Uninit => return Err(I2CBusError::Address(address)),
+        };
+        if address > max {
+            return Err(I2CBusError::Address(address));
+        }
+        Ok(())
+    }
+}
+
 /// 设备需要负责总线的初始化
 pub trait SBusI2CTrans {
-    fn i2c_trans(&self, msgs: &mut [I2CMsg]);
+    fn i2c_trans(&self, msgs: &mut [I2CMsg]) -> Result<(), I2CBusError>;
+    // 基于缓冲区的读写，不需要构造 I2CMsg
+    // 只支持 7 位地址，10 位地址使用 i2c_trans
+    fn i2c_read_into(&self, address: u16, buf: &mut [u8]) -> Result<usize, I2CBusError>;
+    fn i2c_write_from(&self, address: u16, buf: &[u8]) -> Result<usize, I2CBusError>;
 
     /* 以下函数提供了自动初始化与反初始化操作，需要设备进行调用来完成 */
     fn is_i2c_init(&self) -> bool;
@@ -76,26 +163,77 @@ pub trait SBusI2CTrans {
 }
 
 impl<T: SBusI2CBase> SBusI2CTrans for SBusI2C<T> {
-    fn i2c_trans(&self, msgs: &mut [I2CMsg]) {
+    fn i2c_trans(&self, msgs: &mut [I2CMsg]) -> Result<(), I2CBusError> {
+        for msg in msgs.iter() {
+            msg.address_type.check(msg.address)?;
+        }
         let mut first = true;
         for msg in msgs {
-            if first {
+            let ret = if first {
                 first = false;
                 self.i2c_start();
+                Ok(())
             } else {
-                self.i2c_restart();
-            }
-            if let I2CRW::Read = msg.rw {
-                let add = ((msg.address as u8) << 1) | 0x01;
-                self.i2c_send_address(add, 1);
-                self.i2c_rec_bytes(msg).unwrap();
-            } else {
-                let add = (msg.address as u8) << 1;
-                self.i2c_send_address(add, 1);
-                self.i2c_send_bytes(msg).unwrap();
+                self.i2c_restart()
+            };
+            let ret = ret
+                .and_then(|_| self.i2c_send_msg_address(msg))
+                .and_then(|_| {
+                    if let I2CRW::Read = msg.rw {
+                        self.i2c_rec_bytes(msg)
+                    } else {
+                        self.i2c_send_bytes(msg)
+                    }
+                });
+            if let Err(e) = ret {
+                let _ = self.i2c_stop();
+                return Err(e);
             }
         }
-        self.i2c_stop();
+        self.i2c_stop()
+    }
+
+    fn i2c_read_into(&self, address: u16, buf: &mut [u8]) -> Result<usize, I2CBusError> {
+        I2CAddressType::Bits7.check(address)?;
+        self.i2c_start();
+        let len = buf.len();
+        let ret = self
+            .i2c_send_address(((address as u8) << 1) | 0x01, 1, false)
+            .and_then(|_| {
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = self.i2c_read_byte()?;
+                    // 最后一个字节回复 NACK，通知从机停止发送
+                    self.i2c_send_ack(i + 1 != len)?;
+                }
+                Ok(())
+            });
+        if let Err(e) = ret {
+            let _ = self.i2c_stop();
+            return Err(e);
+        }
+        self.i2c_stop()?;
+        Ok(len)
+    }
+
+    fn i2c_write_from(&self, address: u16, buf: &[u8]) -> Result<usize, I2CBusError> {
+        I2CAddressType::Bits7.check(address)?;
+        self.i2c_start();
+        let ret = self
+            .i2c_send_address((address as u8) << 1, 1, false)
+            .and_then(|_| {
+                for b in buf {
+                    if !self.i2c_write_byte(*b)? {
+                        return Err(I2CBusError::Nack(address));
+                    }
+                }
+                Ok(())
+            });
+        if let Err(e) = ret {
+            let _ = self.i2c_stop();
+            return Err(e);
+        }
+        self.i2c_stop()?;
+        Ok(buf.len())
     }
 
     fn is_i2c_init(&self) -> bool {
@@ -125,7 +263,13 @@ impl<T: SBusI2CBase> SBusI2CTrans for SBusI2C<T> {
 
     fn dec_open_num(&self) -> u32 {
         let num = self.bus_raw.get_atomic_num();
-        num.fetch_sub(1, Ordering::SeqCst) - 1
+        // 计数已经为 0 时保持为 0
+        let old = num
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                Some(n.saturating_sub(1))
+            })
+            .unwrap_or(0);
+        old.saturating_sub(1)
     }
 
     fn call_i2c_uninit(&self) -> Result<(), I2CBusError> {
@@ -143,9 +287,15 @@ impl<T: SBusI2CBase> SBusI2C<T> {
         self.bus_raw.delay_us(self.bus_raw.get_delay_time_us());
     }
 
-    fn scl_high(&self) {
+    // 从机一直不释放 SCL 时返回超时，传输随之中止
+    fn scl_high(&self) -> Result<(), I2CBusError> {
         self.bus_raw.scl_set(true);
+        if let Some(t) = self.bus_raw.get_timeout_ms() {
+            // 等待从机释放 SCL（时钟延展）
+            block_until(|| self.bus_raw.scl_get(), Some(t)).map_err(|_| I2CBusError::Timeout)?;
+        }
         self.i2c_delay();
+        Ok(())
     }
 
     fn sda_high(&self) {
@@ -177,41 +327,44 @@ impl<T: SBusI2CBase> SBusI2C<T> {
         self.scl_low();
     }
 
-    fn i2c_restart(&self) {
+    fn i2c_restart(&self) -> Result<(), I2CBusError> {
         self.sda_high();
-        self.scl_high();
+        self.scl_high()?;
         self.i2c_delay();
         self.sda_low();
         self.i2c_delay();
         self.scl_low();
+        Ok(())
     }
 
-    fn i2c_stop(&self) {
+    // 超时时仍然释放 SDA，不让总线停留在低电平
+    fn i2c_stop(&self) -> Result<(), I2CBusError> {
         self.sda_low();
         self.i2c_delay();
-        self.scl_high();
+        let ret = self.scl_high();
         self.i2c_delay();
         self.sda_high();
         self.i2c_delay();
+        ret
     }
 
-    fn i2c_wait_ack(&self) -> bool {
+    fn i2c_wait_ack(&self) -> Result<bool, I2CBusError> {
         self.sda_high();
         self.i2c_delay();
-        self.scl_high();
+        self.scl_high()?;
         let ack = !self.get_sda();
         self.scl_low();
-        ack
+        Ok(ack)
     }
 
-    fn i2c_write_byte(&self, data: u8) -> bool {
+    fn i2c_write_byte(&self, data: u8) -> Result<bool, I2CBusError> {
         let mut i = 7;
         while i >= 0 {
             self.scl_low();
             let bits = (data >> i) & 1;
             self.bus_raw.sda_set(bits == 1);
             self.i2c_delay();
-            self.scl_high();
+            self.scl_high()?;
             i -= 1;
         }
         self.scl_low();
@@ -220,7 +373,17 @@ impl<T: SBusI2CBase> SBusI2C<T> {
         self.i2c_wait_ack()
     }
 
-    fn i2c_read_byte(&self) -> u8 {
+    fn i2c_send_ack(&self, ack: bool) -> Result<(), I2CBusError> {
+        if ack {
+            self.bus_raw.sda_set(false);
+        }
+        self.i2c_delay();
+        self.scl_high()?;
+        self.scl_low();
+        Ok(())
+    }
+
+    fn i2c_read_byte(&self) -> Result<u8, I2CBusError> {
         let mut data = 0u8;
         let mut i = 0;
         self.sda_high();
@@ -228,6 +391,7 @@ impl<T: SBusI2CBase> SBusI2C<T> {
 
         while i < 8 {
             data <<= 1;
+            self.scl_high()?;
             if self.get_sda() {
                 data |= 1;
             }
@@ -235,16 +399,20 @@ impl<T: SBusI2CBase> SBusI2C<T> {
             self.i2c_delay2();
             i += 1;
         }
-        data
+        Ok(data)
     }
 
     fn i2c_send_bytes(&self, msg: &I2CMsg) -> Result<i32, I2CBusError> {
-        let mut cnt = msg.len;
+        // 长度超过缓冲区时只发送缓冲区中的数据
+        let mut cnt = msg.len.min(msg.buf.len() as u16);
         let mut bytes = 0;
         let mut index = 0;
 
         while cnt > 0 {
-            let ret = self.i2c_write_byte(msg.buf[index]);
+            let ack = self.i2c_write_byte(msg.buf[index])?;
+            if !ack && !msg.ignore_ack {
+                return Err(I2CBusError::Nack(msg.address));
+            }
             cnt -= 1;
             index += 1;
             bytes += 1;
@@ -258,27 +426,135 @@ impl<T: SBusI2CBase> SBusI2C<T> {
         let mut bytes = 0;
         let mut index = 0;
 
+        if msg.buf.len() < cnt as usize {
+            msg.buf.resize(cnt as usize, 0);
+        }
+
         while cnt > 0 {
-            let ret = self.i2c_read_byte();
+            let ret = self.i2c_read_byte()?;
+
+            msg.buf[index] = ret;
 
             cnt -= 1;
             index += 1;
             bytes += 1;
 
-            msg.buf[index] = ret;
-
-            // TODO ACK
+            // 最后一个字节回复 NACK
+            self.i2c_send_ack(cnt > 0)?;
         }
 
         Ok(bytes)
     }
 
-    fn i2c_send_address(&self, addr: u8, retries: u32) {
-        for i in 0..retries {
-            self.i2c_write_byte(addr);
-            self.i2c_stop();
+    // 发送消息的地址，地址已经检查过
+    // 10 位地址先发送 11110 + 高两位 + 写，再发送低八位
+    // 读消息在此之后重复起始，再发送一次带读标志的高位
+    fn i2c_send_msg_address(&self, msg: &I2CMsg) -> Result<(), I2CBusError> {
+        let read = if let I2CRW::Read = msg.rw { 1 } else { 0 };
+        match msg.address_type {
+            I2CAddressType::Bits10 => {
+                let high = 0xF0 | ((msg.address >> 7) as u8 & 0x06);
+                // 没有应答时报告完整的地址
+                let nack = |e| match e {
+                    I2CBusError::Nack(_) => I2CBusError::Nack(msg.address),
+                    e => e,
+                };
+                self.i2c_send_address(high, 1, msg.ignore_ack)
+                    .map_err(nack)?;
+                if !self.i2c_write_byte(msg.address as u8)? && !msg.ignore_ack {
+                    return Err(I2CBusError::Nack(msg.address));
+                }
+                if read == 1 {
+                    self.i2c_restart()?;
+                    self.i2c_send_address(high | 0x01, 1, msg.ignore_ack)
+                        .map_err(nack)?;
+                }
+                Ok(())
+            }
+            _ => {
+                let add = ((msg.address as u8) << 1) | read;
+                self.i2c_send_address(add, 1, msg.ignore_ack)
+            }
+        }
+    }
+
+    // 发送地址，没有应答时重新开始传输并重试
+    fn i2c_send_address(
+        &self,
+        addr: u8,
+        retries: u32,
+        ignore_ack: bool,
+    ) -> Result<(), I2CBusError> {
+        for i in 0..=retries {
+            if self.i2c_write_byte(addr)? || ignore_ack {
+                return Ok(());
+            }
+            if i == retries {
+                break;
+            }
+            self.i2c_stop()?;
             self.i2c_delay2();
             self.i2c_start();
         }
+        Err(I2CBusError::Nack((addr >> 1) as u16))
+    }
+}
+
+// 设备共享的总线
+pub type I2CBusRef = Arc<Mutex<Box<dyn SBusI2CTrans + Send>>>;
+
+// 增加总线的打开计数，第一次使用时初始化总线
+pub(crate) fn acquire_bus(bus: &I2CBusRef) -> Result<(), IOError> {
+    let bus_lock = bus.lock().unwrap();
+    if !bus_lock.is_i2c_init() {
+        bus_lock.call_i2c_init()?;
+    }
+    bus_lock.inc_open_num();
+    Ok(())
+}
+
+// 减少总线的打开计数，最后一个使用者释放时反初始化总线
+pub(crate) fn release_bus(bus: &I2CBusRef) -> Result<(), IOError> {
+    let bus_lock = bus.lock().unwrap();
+    if bus_lock.dec_open_num() == 0 {
+        let ret = bus_lock.call_i2c_uninit();
+        bus_lock.set_uninit();
+        ret?;
+    }
+    Ok(())
+}
+
+// 将总线注册到设备链表中，作为 I2C 设备的父设备
+// 子设备打开时由注册表打开总线，总线的打开计数只在这里增减
+pub struct I2CBusDev {
+    bus: I2CBusRef,
+}
+
+impl I2CBusDev {
+    pub fn new(bus: I2CBusRef) -> I2CBusDev {
+        I2CBusDev { bus }
+    }
+
+    pub fn bus(&self) -> I2CBusRef {
+        self.bus.clone()
+    }
+}
+
+impl DeviceOps for I2CBusDev {
+    fn open(&self, _flag: &OpenFlag) -> Result<(), IOError> {
+        acquire_bus(&self.bus)
+    }
+
+    fn close(&self) -> Result<(), IOError> {
+        release_bus(&self.bus)
+    }
+
+    // 总线的传输由挂在其上的设备完成
+    fn control(&self, _data: &dyn ToMakeStdData) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::I2CBus
     }
 }
@@ -1,11 +1,8 @@
 #![allow(dead_code)]
 
-use crate::alloc::boxed::Box;
-use crate::alloc::sync::Arc;
-use crate::device::i2c_bus::SBusI2CTrans;
-use rtt_rs::mutex::Mutex;
+use crate::device::i2c_bus::I2CBusRef;
 
-pub type SBusInDev = Arc<Mutex<Box<dyn SBusI2CTrans>>>;
+pub type SBusInDev = I2CBusRef;
 
 pub struct BspI2CDev {
     pub(crate) bus: SBusInDev,
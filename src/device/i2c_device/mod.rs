@@ -5,17 +5,21 @@
 
 #![allow(dead_code)]
 
+use crate::control::{make_reply, take_cmd, ControlCmd, RawCmd, RawReply};
 use crate::device::i2c_bus::{I2CAddressType, I2CMsg, I2CRW};
-use crate::device::DeviceOps;
+use crate::device::{DeviceClass, DeviceOps};
+use crate::error::DeviceError;
 use crate::IOError::ControlError;
 use crate::{IOError, OpenFlag, StdData, ToMakeStdData};
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 use bsp::SBusInDev;
 use core::cell::Cell;
+use core::fmt::{Display, Formatter};
 
 mod bsp;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum I2CDeviceError {
     InitError,
     UninitError,
@@ -23,9 +27,15 @@ pub enum I2CDeviceError {
 
 impl From<I2CDeviceError> for IOError {
     fn from(e: I2CDeviceError) -> Self {
-        match e {
-            I2CDeviceError::InitError => IOError::OpenError,
-            I2CDeviceError::UninitError => IOError::CloseError,
+        IOError::Device(DeviceError::I2CDevice(e))
+    }
+}
+
+impl Display for I2CDeviceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            I2CDeviceError::InitError => write!(f, "init failed"),
+            I2CDeviceError::UninitError => write!(f, "uninit failed"),
         }
     }
 }
@@ -42,11 +52,37 @@ pub struct I2CDev<T: DeviceI2C> {
     address_type: Cell<I2CAddressType>,
 }
 
+#[derive(Copy, Clone)]
 pub struct I2CDevConfig {
     pub address: u32,
     pub address_type: I2CAddressType,
 }
 
+impl ToMakeStdData for I2CDevConfig {
+    fn make_data(&self) -> StdData {
+        StdData::Type(Box::new(self.clone()))
+    }
+}
+
+pub enum I2CCmd {
+    SetAddress(I2CDevConfig),
+    GetAddress,
+    // 在一次总线占用中依次传输多条消息，消息之间使用重复起始条件
+    // 使用消息中的地址，不使用设置的设备地址
+    Transfer(Vec<I2CMsg>),
+}
+
+pub enum I2CReply {
+    Ok,
+    Address(I2CDevConfig),
+    // 传输完成后的消息，读消息的 buf 中为读到的数据
+    Transfer(Vec<I2CMsg>),
+}
+
+impl ControlCmd for I2CCmd {
+    type Reply = I2CReply;
+}
+
 impl<T: DeviceI2C> I2CDev<T> {
     pub fn new(dev: T) -> I2CDev<T> {
         I2CDev {
@@ -55,118 +91,249 @@ impl<T: DeviceI2C> I2CDev<T> {
             address_type: Cell::new(I2CAddressType::Uninit),
         }
     }
+
+    // 没有设置地址时返回 err
+    fn checked_address(&self, err: IOError) -> Result<(u16, I2CAddressType), IOError> {
+        let address = self.address.get();
+        let address_type = self.address_type.get();
+        if address == 0 || address > u16::MAX as u32 {
+            return Err(err);
+        }
+        if let I2CAddressType::Uninit = address_type {
+            return Err(err);
+        }
+        address_type.check(address as u16)?;
+        Ok((address as u16, address_type))
+    }
+
+    fn trans_one(&self, msg: I2CMsg) -> Result<I2CMsg, IOError> {
+        let mut msgs = [msg];
+        {
+            let bus = self.dev.get_bus();
+            let locked_bus = bus.lock().unwrap();
+
+            locked_bus.i2c_trans(&mut msgs[..])?;
+        }
+        let [msg] = msgs;
+        Ok(msg)
+    }
 }
 
 impl<T: DeviceI2C> DeviceOps for I2CDev<T> {
+    // 总线的打开计数由父设备 I2CBusDev 维护，这里不增加计数
+    // 没有注册在总线下时只保证总线已经初始化
     fn open(&self, _flag: &OpenFlag) -> Result<(), IOError> {
-        let bus = self.dev.get_bus();
         {
-            let bus_lock = bus.lock().unwrap();
-            if !bus_lock.is_i2c_init() {
-                bus_lock.call_i2c_init().unwrap();
+            let bus = self.dev.get_bus();
+            let locked_bus = bus.lock().unwrap();
+            if !locked_bus.is_i2c_init() {
+                locked_bus.call_i2c_init()?;
             }
-            bus_lock.inc_open_num();
         }
         self.dev.init()?;
         Ok(())
     }
 
     fn read(&self, p_len: u32) -> Result<StdData, IOError> {
-        if self.address.get() == 0 {
-            return Err(IOError::ReadError);
-        }
-        if let I2CAddressType::Uninit = self.address_type.get() {
-            return Err(IOError::ReadError);
-        }
+        let (address, address_type) = self.checked_address(IOError::ReadError)?;
 
-        let mut msgs = [I2CMsg {
-            address: self.address.get() as u16,
-            address_type: self.address_type.get(),
+        let msg = I2CMsg {
+            address,
+            address_type,
             send_ack: false,
             ignore_ack: false,
             rw: I2CRW::Read,
             len: p_len as _,
-            buf: Vec::new(),
-        }];
+            buf: alloc::vec![0; p_len as usize],
+        };
 
-        {
-            let bus = self.dev.get_bus();
-            let locked_bus = bus.lock().unwrap();
-
-            locked_bus.i2c_trans(&mut msgs[..]);
-        }
-
-        let mut only_msg = None;
-        for i in msgs {
-            only_msg = Some(i);
-        }
-        let I2CMsg { buf, .. } = only_msg.unwrap();
+        let I2CMsg { buf, .. } = self.trans_one(msg)?;
         Ok(StdData::Bytes(buf))
     }
 
     fn write(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
-        let mut msgs = [I2CMsg {
-            address: self.address.get() as u16,
-            address_type: I2CAddressType::Bits7,
+        let (address, address_type) = self.checked_address(IOError::WriteError)?;
+        let mut msg = I2CMsg {
+            address,
+            address_type,
             send_ack: false,
             ignore_ack: false,
             rw: I2CRW::Write,
             len: 0,
             buf: Vec::new(),
-        }];
+        };
         match data.make_data() {
             StdData::Bytes(a) => {
-                msgs[0].len = a.len() as _;
-                msgs[0].buf = a;
+                msg.len = a.len() as _;
+                msg.buf = a;
             }
             StdData::U32(a) => {
-                msgs[0].len = 1;
-                msgs[0].buf.push(a as _);
+                msg.len = 1;
+                msg.buf.push(a as _);
             }
             StdData::U8(a) => {
-                msgs[0].len = 1;
-                msgs[0].buf.push(a as _);
+                msg.len = 1;
+                msg.buf.push(a as _);
             }
             _ => {
                 return Err(IOError::DataError);
             }
         }
 
-        {
-            let bus = self.dev.get_bus();
-            let locked_bus = bus.lock().unwrap();
+        self.trans_one(msg)?;
+        Ok(())
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::I2CDevice
+    }
+
+    fn read_into(&self, buf: &mut [u8]) -> Result<usize, IOError> {
+        let (address, address_type) = self.checked_address(IOError::ReadError)?;
+        // 总线基于缓冲区的读写只支持 7 位地址，10 位地址通过消息传输
+        if let I2CAddressType::Bits10 = address_type {
+            let msg = self.trans_one(I2CMsg {
+                address,
+                address_type,
+                send_ack: false,
+                ignore_ack: false,
+                rw: I2CRW::Read,
+                len: buf.len() as _,
+                buf: alloc::vec![0; buf.len()],
+            })?;
+            buf.copy_from_slice(&msg.buf[..buf.len()]);
+            return Ok(buf.len());
+        }
+        let bus = self.dev.get_bus();
+        let locked_bus = bus.lock().unwrap();
+        Ok(locked_bus.i2c_read_into(address, buf)?)
+    }
 
-            locked_bus.i2c_trans(&mut msgs[..]);
+    fn write_from(&self, buf: &[u8]) -> Result<usize, IOError> {
+        let (address, address_type) = self.checked_address(IOError::WriteError)?;
+        if let I2CAddressType::Bits10 = address_type {
+            self.trans_one(I2CMsg {
+                address,
+                address_type,
+                send_ack: false,
+                ignore_ack: false,
+                rw: I2CRW::Write,
+                len: buf.len() as _,
+                buf: buf.to_vec(),
+            })?;
+            return Ok(buf.len());
         }
+        let bus = self.dev.get_bus();
+        let locked_bus = bus.lock().unwrap();
+        Ok(locked_bus.i2c_write_from(address, buf)?)
+    }
 
-        Ok(())
+    // 传输在读写调用返回前已经完成，没有需要等待写出的数据
+    // 等待其他设备释放总线无法设置超时，所以不支持
+    fn sync(&self, _timeout: Option<u32>) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
     }
 
+    // 总线由父设备关闭
     fn close(&self) -> Result<(), IOError> {
-        let _ = self.dev.uninit();
-        let bus = self.dev.get_bus();
-        {
-            let bus_lock = bus.lock().unwrap();
-            if bus_lock.dec_open_num() == 0 {
-                bus_lock.call_i2c_uninit().unwrap();
-                bus_lock.set_uninit();
-            }
-        }
+        self.dev.uninit()?;
         Ok(())
     }
 
     // 设置地址和地址类型
     fn control(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
-        let a = data.make_data().take_type().unwrap();
-        let b;
-        if a.is::<I2CDevConfig>() {
-            b = a.downcast::<I2CDevConfig>().unwrap();
-        } else {
-            return Err(ControlError);
-        }
+        let a = data
+            .make_data()
+            .take_type()
+            .map_err(|_| IOError::DataError)?;
+        let b = match a.downcast::<I2CDevConfig>() {
+            Ok(b) => b,
+            Err(_) => return Err(ControlError),
+        };
 
         self.address_type.set(b.address_type);
         self.address.set(b.address);
         Ok(())
     }
+
+    fn command(&self, cmd: RawCmd) -> Result<RawReply, IOError> {
+        let reply = match take_cmd::<I2CCmd>(cmd)? {
+            I2CCmd::SetAddress(cfg) => {
+                self.address_type.set(cfg.address_type);
+                self.address.set(cfg.address);
+                I2CReply::Ok
+            }
+            I2CCmd::GetAddress => I2CReply::Address(I2CDevConfig {
+                address: self.address.get(),
+                address_type: self.address_type.get(),
+            }),
+            I2CCmd::Transfer(mut msgs) => {
+                let bus = self.dev.get_bus();
+                let locked_bus = bus.lock().unwrap();
+                locked_bus.i2c_trans(&mut msgs[..])?;
+                I2CReply::Transfer(msgs)
+            }
+        };
+        Ok(make_reply::<I2CCmd>(reply))
+    }
+}
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use super::*;
+    use crate::alloc::sync::Arc;
+    use crate::api::{find, DevOpen};
+    use crate::bsp::host_sim::i2c_bus::SimI2CBus;
+    use crate::device::i2c_bus::{I2CBusDev, I2CBusRef, SBusI2C, SBusI2CTrans};
+    use crate::device::{register_child_device, register_device};
+    use crate::Mutex;
+
+    struct SimDev(I2CBusRef);
+
+    impl DeviceI2C for SimDev {
+        fn init(&self) -> Result<(), I2CDeviceError> {
+            Ok(())
+        }
+        fn uninit(&self) -> Result<(), I2CDeviceError> {
+            Ok(())
+        }
+        fn get_bus(&self) -> SBusInDev {
+            self.0.clone()
+        }
+    }
+
+    #[test]
+    fn child_counts_bus_once() {
+        let bus: Box<dyn SBusI2CTrans + Send> = Box::new(SBusI2C::new(SimI2CBus::new(6)));
+        let bus: I2CBusRef = Arc::new(Mutex::new(bus).unwrap());
+        register_device(I2CBusDev::new(bus.clone()), "test/i2c/bus").unwrap();
+        register_child_device(
+            I2CDev::new(SimDev(bus.clone())),
+            "test/i2c/bus/a",
+            "test/i2c/bus",
+        )
+        .unwrap();
+        register_child_device(
+            I2CDev::new(SimDev(bus.clone())),
+            "test/i2c/bus/b",
+            "test/i2c/bus",
+        )
+        .unwrap();
+
+        let a = find("test/i2c/bus/a").unwrap();
+        let b = find("test/i2c/bus/b").unwrap();
+        let ga = a.open(&OpenFlag::zero()).unwrap();
+        let gb = b.open(&OpenFlag::zero()).unwrap();
+        assert!(bus.lock().unwrap().is_i2c_init());
+        {
+            // 两个子设备只让父设备打开一次总线
+            let locked = bus.lock().unwrap();
+            assert_eq!(locked.dec_open_num(), 0);
+            locked.inc_open_num();
+        }
+        drop(ga);
+        assert!(bus.lock().unwrap().is_i2c_init());
+        drop(gb);
+        assert!(!bus.lock().unwrap().is_i2c_init());
+    }
 }
@@ -1,17 +1,36 @@
+use crate::control::{make_reply, take_cmd, ControlCmd, RawCmd, RawReply};
 use crate::data::{OpenFlag, StdData, ToMakeStdData};
-use crate::device::DeviceOps;
-use crate::error::IOError;
+use crate::device::{DeviceClass, DeviceOps};
+use crate::error::{DeviceError, IOError};
+use core::cell::Cell;
+use core::fmt::{Display, Formatter};
 use core::ops::Deref;
 use core::task::Waker;
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum LedError {
     InitError,
     OnOFFError,
     UninitError,
 }
 
+impl From<LedError> for IOError {
+    fn from(e: LedError) -> Self {
+        IOError::Device(DeviceError::Led(e))
+    }
+}
+
+impl Display for LedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LedError::InitError => write!(f, "init failed"),
+            LedError::OnOFFError => write!(f, "switch failed"),
+            LedError::UninitError => write!(f, "uninit failed"),
+        }
+    }
+}
+
 pub enum LedState {
     On,
     Off,
@@ -26,25 +45,33 @@ impl ToMakeStdData for LedState {
     }
 }
 
+pub enum LedCmd {
+    On,
+    Off,
+    Toggle,
+    GetState,
+}
+
+pub enum LedReply {
+    Ok,
+    State(LedState),
+}
+
+impl ControlCmd for LedCmd {
+    type Reply = LedReply;
+}
+
 pub trait ToLedState {
-    fn to_led_state(&self) -> LedState;
+    // 不是 0 或 1 时返回 DataError
+    fn to_led_state(&self) -> Result<LedState, IOError>;
 }
 
 impl ToLedState for StdData {
-    fn to_led_state(&self) -> LedState {
+    fn to_led_state(&self) -> Result<LedState, IOError> {
         match *self {
-            StdData::U32(a) => {
-                if a == 1 {
-                    LedState::On
-                } else if a == 0 {
-                    LedState::Off
-                } else {
-                    panic!()
-                }
-            }
-            _ => {
-                panic!()
-            }
+            StdData::U32(1) | StdData::U8(1) => Ok(LedState::On),
+            StdData::U32(0) | StdData::U8(0) => Ok(LedState::Off),
+            _ => Err(IOError::DataError),
         }
     }
 }
@@ -55,15 +82,27 @@ pub trait DeviceLed {
     fn off(&self) -> Result<(), LedError>;
     fn is_on(&self) -> bool;
     fn uninit(&self) -> Result<(), LedError>;
+    // 挂起时 LED 已经被熄灭，BSP 可以在这里关闭时钟
+    fn suspend(&self) -> Result<(), LedError> {
+        Ok(())
+    }
+    fn resume(&self) -> Result<(), LedError> {
+        Ok(())
+    }
 }
 
 pub struct Led<T: DeviceLed> {
     pub dev: T,
+    // 挂起前 LED 是否点亮
+    was_on: Cell<bool>,
 }
 
 impl<T: DeviceLed> Led<T> {
     pub fn new(dev: T) -> Led<T> {
-        Led { dev }
+        Led {
+            dev,
+            was_on: Cell::new(false),
+        }
     }
 }
 
@@ -96,21 +135,21 @@ where
     }
 
     fn write(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
-        let d = data.make_data();
-        match d {
-            StdData::U32(a) => {
-                if a == 0 {
-                    self.off().unwrap();
-                    Ok(())
-                } else {
-                    self.on().unwrap();
-                    Ok(())
-                }
-            }
-            _ => {
-                panic!()
-            }
+        let on = match data.make_data() {
+            StdData::U32(a) => a != 0,
+            StdData::U8(a) => a != 0,
+            _ => return Err(IOError::DataError),
+        };
+        if on {
+            self.on()?;
+        } else {
+            self.off()?;
         }
+        Ok(())
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Led
     }
 
     fn close(&self) -> Result<(), IOError> {
@@ -119,17 +158,68 @@ where
 
     #[allow(unused_variables)]
     fn control(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
-        unimplemented!()
+        Err(IOError::UnsupportedError)
+    }
+
+    fn command(&self, cmd: RawCmd) -> Result<RawReply, IOError> {
+        let ret = match take_cmd::<LedCmd>(cmd)? {
+            LedCmd::On => self.on(),
+            LedCmd::Off => self.off(),
+            LedCmd::Toggle => {
+                if self.is_on() {
+                    self.off()
+                } else {
+                    self.on()
+                }
+            }
+            LedCmd::GetState => {
+                let state = if self.is_on() {
+                    LedState::On
+                } else {
+                    LedState::Off
+                };
+                return Ok(make_reply::<LedCmd>(LedReply::State(state)));
+            }
+        };
+        ret.map_err(|_| IOError::ControlError)?;
+        Ok(make_reply::<LedCmd>(LedReply::Ok))
+    }
+
+    // 挂起时熄灭 LED，恢复时回到挂起前的状态
+    fn suspend(&self) -> Result<(), IOError> {
+        self.was_on.set(self.is_on());
+        self.off()?;
+        self.dev.suspend()?;
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<(), IOError> {
+        self.dev.resume()?;
+        if self.was_on.get() {
+            self.on()?;
+        }
+        Ok(())
     }
 
     // LED设备不支持异步读取\写入
     #[allow(unused_variables)]
-    fn register_read_callback(&self, func: fn(Waker), cx: Waker) -> Result<(), IOError> {
-        unimplemented!()
+    fn register_read_callback(
+        &self,
+        id: usize,
+        need: usize,
+        func: fn(Waker),
+        cx: &Waker,
+    ) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
     }
 
     #[allow(unused_variables)]
-    fn register_write_callback(&self, func: fn(Waker), cx: Waker) -> Result<(), IOError> {
-        unimplemented!()
+    fn register_write_callback(
+        &self,
+        id: usize,
+        func: fn(Waker),
+        cx: &Waker,
+    ) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
     }
 }
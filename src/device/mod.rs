@@ -1,12 +1,17 @@
 #![allow(unused_variables)]
 
 use crate::alloc::boxed::Box;
+use crate::alloc::collections::VecDeque;
 use crate::alloc::string::String;
 use crate::alloc::sync::Arc;
+use crate::api::resolve;
+use crate::control::{RawCmd, RawReply};
 use crate::data::{OpenFlag, StdData, ToMakeStdData};
 use crate::driver::Driver;
 use crate::error::IOError;
+use crate::stats::DeviceStats;
 use crate::Mutex;
+use crate::ALIAS_LIST;
 use crate::DEVICE_LIST;
 use crate::FAST_DEVICE_LIST;
 use core::any::Any;
@@ -23,89 +28,377 @@ pub mod spi_flash;
 // 提供基础的数据结构
 pub(crate) mod base;
 
+// 设备的类别，用于设备列表的显示
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceClass {
+    Serial,
+    Led,
+    I2CBus,
+    I2CDevice,
+    SpiBus,
+    SpiDevice,
+    SpiFlash,
+    // 快设备没有框架，无法得知类别
+    Fast,
+    Unknown,
+}
+
+impl DeviceClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceClass::Serial => "Serial Device",
+            DeviceClass::Led => "LED Device",
+            DeviceClass::I2CBus => "I2C Bus",
+            DeviceClass::I2CDevice => "I2C Device",
+            DeviceClass::SpiBus => "SPI Bus",
+            DeviceClass::SpiDevice => "SPI Device",
+            DeviceClass::SpiFlash => "SPI Flash",
+            DeviceClass::Fast => "Fast Device",
+            DeviceClass::Unknown => "Unknown",
+        }
+    }
+}
+
 // 该组操作函数用来被具体的驱动继承
 pub trait DeviceOps {
     // 必须实现
     fn open(&self, flag: &OpenFlag) -> Result<(), IOError>;
+    // 块设备需要使用 b_read/b_write
     fn read(&self, len: u32) -> Result<StdData, IOError> {
         if self.is_block_dev() {
-            panic!()
+            Err(IOError::UnsupportedError)
         } else {
             Err(IOError::ReadError)
         }
     }
     fn write(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
         if self.is_block_dev() {
-            panic!()
+            Err(IOError::UnsupportedError)
         } else {
-            Err(IOError::ReadError)
+            Err(IOError::WriteError)
         }
     }
     fn close(&self) -> Result<(), IOError>;
     fn control(&self, data: &dyn ToMakeStdData) -> Result<(), IOError>;
 
+    // 类型化的控制命令，见 control 模块
+    fn command(&self, cmd: RawCmd) -> Result<RawReply, IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
     fn is_block_dev(&self) -> bool {
         false
     }
 
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Unknown
+    }
+
+    // 字符设备不支持按地址读写
     fn b_read(&self, address: usize, len: usize) -> Result<StdData, IOError> {
-        unimplemented!()
+        Err(IOError::UnsupportedError)
     }
     fn b_write(&self, address: usize, data: &dyn ToMakeStdData) -> Result<(), IOError> {
-        unimplemented!()
+        Err(IOError::UnsupportedError)
+    }
+    // 基于缓冲区的读写，不需要分配内存
+    // 默认转换到 read/write 上，驱动可以提供更高效的实现
+    // 返回实际读写的字节数
+    fn read_into(&self, buf: &mut [u8]) -> Result<usize, IOError> {
+        copy_into(self.read(buf.len() as _)?, buf)
+    }
+    fn write_from(&self, buf: &[u8]) -> Result<usize, IOError> {
+        self.write(&buf)?;
+        Ok(buf.len())
+    }
+    fn b_read_into(&self, address: usize, buf: &mut [u8]) -> Result<usize, IOError> {
+        copy_into(self.b_read(address, buf.len())?, buf)
+    }
+    fn b_write_from(&self, address: usize, buf: &[u8]) -> Result<usize, IOError> {
+        self.b_write(address, &buf)?;
+        Ok(buf.len())
+    }
+
+    // 进行同步操作，等待所有数据写出
+    // timeout 单位为 ms，None 表示一直等待
+    // DriverGuard 使用 Some(0) 轮询，此时只检查一次，没有完成时返回超时错误
+    fn sync(&self, timeout: Option<u32>) -> Result<(), IOError> {
+        Ok(())
     }
-    // 进行同步操作
-    fn sync(&self) {}
 
+    // 修改打开标志，参数为 StdData::OpenFlag，返回修改前的标志
     fn chf(&self, _data: StdData) -> Result<StdData, IOError> {
-        todo!()
+        Err(IOError::UnsupportedError)
+    }
+
+    // 环形缓冲区因为满而被抛弃的数据数量，用于统计
+    // 没有缓冲区的设备不需要实现
+    fn buffer_drops(&self) -> usize {
+        0
+    }
+
+    // 进入低功耗模式前后调用，只对已经打开的设备调用，见 pm 模块
+    // 驱动需要自己保存恢复时用到的状态，如串口配置、LED 状态
+    fn suspend(&self) -> Result<(), IOError> {
+        Ok(())
+    }
+    fn resume(&self) -> Result<(), IOError> {
+        Ok(())
     }
 
     // 还有一些支持C接口注册的函数待完成
 
-    // for async, 非必须实现
+    // for async, 非必须实现，不支持时 future 返回 UnsupportedError
     // NOTE: device 实现规范
     // waker 在通知的时候必须转移所有权
-    // "注册一次，通知一次"
-    // 下次无法再通知了
+    // "注册一次，通知一次"，通知后从列表中移除
+    // id 区分不同的 future，同一个 id 重复注册时替换之前的 waker，不能重复加入
+    // 条件满足时唤醒所有满足条件的等待者，而不是只唤醒一个
+    // need：读缓冲区中至少有 need 个字节时唤醒
     // Waker通知之前会先检查线程是否中止
     // 只要线程没被中止，异步运行时即存在，
     // 且对应协程正在被阻塞，即可进行异步的通知
-    fn register_read_callback(&self, func: fn(Waker), cx: Waker) -> Result<(), IOError> {
-        Ok(())
+    fn register_read_callback(
+        &self,
+        id: usize,
+        need: usize,
+        func: fn(Waker),
+        cx: &Waker,
+    ) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
     }
-    fn register_write_callback(&self, func: fn(Waker), cx: Waker) -> Result<(), IOError> {
-        Ok(())
+    fn register_write_callback(
+        &self,
+        id: usize,
+        func: fn(Waker),
+        cx: &Waker,
+    ) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
     }
+    // future 被丢弃时调用，移除还没有被通知的 waker
+    fn unregister_read_callback(&self, id: usize) {}
+    fn unregister_write_callback(&self, id: usize) {}
 
     // for c-type
     fn register_rx_indicate(&self, func: fn()) {}
 }
 
+// 将 read 返回的数据复制到缓冲区中
+fn copy_into(data: StdData, buf: &mut [u8]) -> Result<usize, IOError> {
+    match data {
+        StdData::Bytes(a) => {
+            let len = a.len().min(buf.len());
+            buf[..len].copy_from_slice(&a[..len]);
+            Ok(len)
+        }
+        StdData::U32(a) if !buf.is_empty() => {
+            buf[0] = a as u8;
+            Ok(1)
+        }
+        StdData::U8(a) if !buf.is_empty() => {
+            buf[0] = a;
+            Ok(1)
+        }
+        StdData::Null => Ok(0),
+        _ => Err(IOError::DataError),
+    }
+}
+
+// 设备名由 '/' 分隔的若干段组成，每段不能为空
+pub(crate) fn check_name(name: &str) -> bool {
+    !name.is_empty() && name.split('/').all(|s| !s.is_empty())
+}
+
 pub fn register_device<T: DeviceOps + Send + 'static>(
     raw_dev: T,
     name: &str,
 ) -> Result<(), IOError> {
+    register(raw_dev, name, None)
+}
+
+// 注册挂在父设备（通常是总线）下的设备
+// 打开子设备时会先共享打开父设备，子设备最后一次关闭时父设备随之关闭
+// 父设备还有子设备时不能被注销
+pub fn register_child_device<T: DeviceOps + Send + 'static>(
+    raw_dev: T,
+    name: &str,
+    parent: &str,
+) -> Result<(), IOError> {
+    register(raw_dev, name, Some(parent))
+}
+
+fn register<T: DeviceOps + Send + 'static>(
+    raw_dev: T,
+    name: &str,
+    parent: Option<&str>,
+) -> Result<(), IOError> {
+    if !check_name(name) {
+        return Err(IOError::RegisterError);
+    }
+    let parent = parent.map(resolve);
+    // 与 set_alias 相同，先锁别名表再锁设备表
+    let alias = ALIAS_LIST.lock().unwrap();
     let mut list = DEVICE_LIST.lock().unwrap();
+    if alias.contains_key(name) || list.contains_key(name) {
+        return Err(IOError::RegisterError);
+    }
+    let parent = match parent {
+        Some(p) => match list.get(p.as_str()) {
+            Some(dev) => Some(dev.clone()),
+            None => return Err(IOError::FindError),
+        },
+        None => None,
+    };
 
     let dev = Arc::new(
         Mutex::new(Driver {
+            name: String::from(name),
             open_able: true,
             open_num: 0,
+            removed: false,
+            unregistered: false,
+            waiters: VecDeque::new(),
+            next_ticket: 0,
+            stats: DeviceStats::default(),
+            drops_base: 0,
+            trace: None,
+            parent: parent.clone(),
+            parent_guard: None,
+            children: 0,
+            suspended: false,
             ops: Box::pin(raw_dev),
         })
         .unwrap(),
     );
 
-    return if let None = list.get(name) {
-        list.insert(String::from(name), dev);
-        Ok(())
-    } else {
-        Err(IOError::RegisterError)
+    if let Some(p) = parent {
+        p.lock().unwrap().children += 1;
+    }
+    list.insert(String::from(name), dev);
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnregisterMode {
+    // 设备正在被使用时返回错误
+    Normal,
+    // 立即从链表中移除，已经打开的句柄继续可用
+    // 最后一个句柄释放时关闭设备并释放驱动
+    Deferred,
+    // 立即关闭设备并释放驱动
+    // 已经打开的句柄之后的操作都会返回 RemovedError
+    Force,
+}
+
+// 注销后替换原有驱动的空设备
+struct RemovedDevice;
+
+impl DeviceOps for RemovedDevice {
+    fn open(&self, flag: &OpenFlag) -> Result<(), IOError> {
+        Err(IOError::RemovedError)
+    }
+    fn read(&self, len: u32) -> Result<StdData, IOError> {
+        Err(IOError::RemovedError)
+    }
+    fn write(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
+        Err(IOError::RemovedError)
+    }
+    fn close(&self) -> Result<(), IOError> {
+        Err(IOError::RemovedError)
+    }
+    fn control(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
+        Err(IOError::RemovedError)
+    }
+    fn command(&self, cmd: RawCmd) -> Result<RawReply, IOError> {
+        Err(IOError::RemovedError)
+    }
+    fn sync(&self, timeout: Option<u32>) -> Result<(), IOError> {
+        Err(IOError::RemovedError)
+    }
+    fn chf(&self, _data: StdData) -> Result<StdData, IOError> {
+        Err(IOError::RemovedError)
+    }
+    fn register_read_callback(
+        &self,
+        id: usize,
+        need: usize,
+        func: fn(Waker),
+        cx: &Waker,
+    ) -> Result<(), IOError> {
+        Err(IOError::RemovedError)
+    }
+    fn register_write_callback(
+        &self,
+        id: usize,
+        func: fn(Waker),
+        cx: &Waker,
+    ) -> Result<(), IOError> {
+        Err(IOError::RemovedError)
+    }
+}
+
+// 注销设备，设备正在被使用或者还有子设备时返回 UnregisterError
+pub fn unregister_device(name: &str) -> Result<(), IOError> {
+    unregister_device_with(name, UnregisterMode::Normal)
+}
+
+pub fn unregister_device_with(name: &str, mode: UnregisterMode) -> Result<(), IOError> {
+    let mut list = DEVICE_LIST.lock().unwrap();
+
+    let dev = match list.get(name) {
+        Some(dev) => dev.clone(),
+        None => return Err(IOError::FindError),
     };
+
+    let mut inner_dev = dev.lock().unwrap();
+    if inner_dev.children != 0 {
+        // 任何方式都不能注销还有子设备的设备
+        return Err(IOError::UnregisterError);
+    }
+    let in_use = !inner_dev.open_able || inner_dev.open_num != 0;
+
+    match mode {
+        UnregisterMode::Normal => {
+            if in_use {
+                return Err(IOError::UnregisterError);
+            }
+        }
+        UnregisterMode::Deferred => {}
+        UnregisterMode::Force => {
+            if in_use {
+                let _ = inner_dev.ops.close();
+            }
+            inner_dev.removed = true;
+            inner_dev.open_able = true;
+            inner_dev.open_num = 0;
+            // 原有驱动在此处被释放
+            inner_dev.ops = Box::pin(RemovedDevice);
+            // 子设备已经关闭，释放持有的父设备
+            inner_dev.parent_guard = None;
+        }
+    }
+
+    // 仍然持有设备的人不能再打开设备，正在等待打开的人返回 RemovedError
+    inner_dev.unregistered = true;
+    inner_dev.wake_all_open_waiters();
+    // 延迟注销的设备仍持有父设备，最后一个句柄关闭时才减少父设备的子设备数量
+    if !(in_use && mode == UnregisterMode::Deferred) {
+        inner_dev.detach_parent();
+    }
+    list.remove(name);
+    Ok(())
 }
 
+// 快设备与普通设备使用相同的命名规则，也不能与别名同名
 pub fn register_fast_device(dev: Box<dyn Any + Send>, name: &str) -> Result<(), IOError> {
+    if !check_name(name) {
+        return Err(IOError::RegisterError);
+    }
+    // 先锁别名表再锁快设备表
+    let alias = ALIAS_LIST.lock().unwrap();
+    if alias.contains_key(name) {
+        return Err(IOError::RegisterError);
+    }
     let mut list = FAST_DEVICE_LIST.lock().unwrap();
 
     return if let None = list.get(name) {
@@ -115,3 +408,108 @@ pub fn register_fast_device(dev: Box<dyn Any + Send>, name: &str) -> Result<(),
         Err(IOError::RegisterError)
     };
 }
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use super::*;
+    use crate::api::{find, DevOpen};
+    use crate::bsp::host_sim::testing::{count_child, count_dev};
+    use crate::driver::DriverOps;
+    use core::sync::atomic::Ordering;
+
+    #[test]
+    fn unregister_normal() {
+        count_dev("test/unreg/normal");
+        let dev = find("test/unreg/normal").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        assert!(matches!(
+            unregister_device("test/unreg/normal"),
+            Err(IOError::UnregisterError)
+        ));
+        drop(g);
+        unregister_device("test/unreg/normal").unwrap();
+        assert!(find("test/unreg/normal").is_err());
+        // 仍然持有设备的人不能再打开
+        assert!(matches!(
+            dev.open(&OpenFlag::zero()),
+            Err(IOError::RemovedError)
+        ));
+        assert!(matches!(
+            unregister_device("test/unreg/normal"),
+            Err(IOError::FindError)
+        ));
+    }
+
+    #[test]
+    fn unregister_deferred() {
+        let (_, closes) = count_dev("test/unreg/deferred");
+        let dev = find("test/unreg/deferred").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        unregister_device_with("test/unreg/deferred", UnregisterMode::Deferred).unwrap();
+        assert!(find("test/unreg/deferred").is_err());
+        // 已经打开的句柄继续可用，但不能再打开
+        assert!(g.control(&0u32).is_ok());
+        assert!(matches!(
+            dev.open(&OpenFlag::zero()),
+            Err(IOError::RemovedError)
+        ));
+        assert!(matches!(
+            dev.open_wait(&OpenFlag::zero(), Some(10)),
+            Err(IOError::RemovedError)
+        ));
+        drop(g);
+        assert_eq!(closes.load(Ordering::SeqCst), 1);
+        // 名称可以被重新注册
+        count_dev("test/unreg/deferred");
+    }
+
+    #[test]
+    fn unregister_force() {
+        let (_, closes) = count_dev("test/unreg/force");
+        let dev = find("test/unreg/force").unwrap();
+        let g = dev.open(OpenFlag::zero().set_only(true)).unwrap();
+        unregister_device_with("test/unreg/force", UnregisterMode::Force).unwrap();
+        assert_eq!(closes.load(Ordering::SeqCst), 1);
+        assert!(matches!(g.control(&0u32), Err(IOError::RemovedError)));
+        assert!(matches!(
+            dev.open(&OpenFlag::zero()),
+            Err(IOError::RemovedError)
+        ));
+        // 驱动已经关闭，释放句柄时不会再次关闭
+        drop(g);
+        assert_eq!(closes.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn unregister_parent() {
+        count_dev("test/unreg/bus");
+        register_child_device(RemovedDevice, "test/unreg/bus/dev", "test/unreg/bus").unwrap();
+        assert!(matches!(
+            unregister_device_with("test/unreg/bus", UnregisterMode::Force),
+            Err(IOError::UnregisterError)
+        ));
+        unregister_device("test/unreg/bus/dev").unwrap();
+        unregister_device("test/unreg/bus").unwrap();
+    }
+
+    #[test]
+    fn deferred_child_keeps_parent() {
+        let (_, bus_closes) = count_dev("test/unreg/dbus");
+        let (_, dev_closes) = count_child("test/unreg/dbus/dev", "test/unreg/dbus");
+        let dev = find("test/unreg/dbus/dev").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        unregister_device_with("test/unreg/dbus/dev", UnregisterMode::Deferred).unwrap();
+        // 子设备仍在使用，总线不能被强制注销
+        assert!(matches!(
+            unregister_device_with("test/unreg/dbus", UnregisterMode::Force),
+            Err(IOError::UnregisterError)
+        ));
+        assert_eq!(bus_closes.load(Ordering::SeqCst), 0);
+        assert!(g.control(&0u32).is_ok());
+        drop(g);
+        assert_eq!(dev_closes.load(Ordering::SeqCst), 1);
+        assert_eq!(bus_closes.load(Ordering::SeqCst), 1);
+        unregister_device_with("test/unreg/dbus", UnregisterMode::Force).unwrap();
+        assert_eq!(bus_closes.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,14 +1,40 @@
-use crate::alloc::collections::LinkedList;
+use crate::alloc::vec::Vec;
 use crate::device::base::DynCycleQueue;
-use crate::device::serial::DeviceSerial;
+use crate::device::serial::{DeviceSerial, SerialError};
 use core::cell::UnsafeCell;
 use core::task::Waker;
 
+// 一个等待接收数据的 future
+pub(crate) struct AsyncWaiter {
+    pub(crate) id: usize,
+    // 接收缓冲区中至少有 need 个字节时唤醒
+    pub(crate) need: usize,
+    pub(crate) waker: Waker,
+}
+
 pub struct BspAsyncSerial {
-    pub(crate) async_wakers: LinkedList<Waker>,
+    pub(crate) async_wakers: Vec<AsyncWaiter>,
     pub(crate) async_notify: fn(Waker),
 }
 
+impl BspAsyncSerial {
+    // 唤醒并移除所有条件已经满足的等待者
+    // need 超过缓冲区能存放的字节数时，缓冲区满即唤醒
+    pub(crate) fn wake_ready(&mut self, rb: &DynCycleQueue<u8>) {
+        let len = rb.length();
+        let max = len + rb.free_len();
+        let mut i = 0;
+        while i < self.async_wakers.len() {
+            if self.async_wakers[i].need.min(max) <= len {
+                let w = self.async_wakers.remove(i);
+                (self.async_notify)(w.waker);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
 pub struct BspSerial {
     pub(crate) read_async_helper: UnsafeCell<Option<BspAsyncSerial>>,
     pub(crate) r_buffer: UnsafeCell<DynCycleQueue<u8>>,
@@ -27,16 +53,9 @@ pub(crate) fn irq_receive_char<T: DeviceSerial>(dev: *mut T, ch: u8) {
 
 pub(crate) fn notify_form_irq<T: DeviceSerial>(dev: *mut T) {
     unsafe {
-        let helper = (*dev).get_helper().read_async_helper.get();
-        match *helper {
-            None => {}
-            Some(ref mut hp) => {
-                let waker = hp.async_wakers.pop_front();
-                match waker {
-                    None => {}
-                    Some(a) => (hp.async_notify)(a),
-                }
-            }
+        let hp = (*dev).get_helper();
+        if let Some(ref mut a) = *hp.read_async_helper.get() {
+            a.wake_ready(&*hp.r_buffer.get());
         }
     }
 }
@@ -50,14 +69,18 @@ pub(crate) fn irq_send_char<T: DeviceSerial>(dev: *mut T) {
                 (*dev).tx_irq_en(false);
             }
             Some(a) => {
-                (*dev).write_char(a as u8).unwrap();
+                // 中断中无法返回错误，发送失败的字符被丢弃
+                let _ = (*dev).write_char(a as u8);
             }
         }
     }
 }
 
 #[allow(dead_code)]
-pub(crate) fn dma_write_data<T: DeviceSerial>(sdev: *mut BspSerial, dev: *mut T) {
+pub(crate) fn dma_write_data<T: DeviceSerial>(
+    sdev: *mut BspSerial,
+    dev: *mut T,
+) -> Result<(), SerialError> {
     // TODO： 需要DMA对齐
     // TODO： 需要配置MPU划分写通区域，并将缓冲区放在写通区域里面
     static mut DMA_BUF: [u8; 32] = [0; 32];
@@ -75,7 +98,7 @@ pub(crate) fn dma_write_data<T: DeviceSerial>(sdev: *mut BspSerial, dev: *mut T)
                 }
             }
         }
-        (*dev).dma_write(DMA_BUF.as_ptr(), len);
+        (*dev).dma_write(DMA_BUF.as_ptr(), len)
     }
 }
 
@@ -1,29 +1,51 @@
 pub(crate) mod bsp;
 
-use crate::device::DeviceOps;
+use crate::control::{make_reply, take_cmd, ControlCmd, RawCmd, RawReply};
+use crate::device::{DeviceClass, DeviceOps};
+use crate::error::DeviceError;
+use crate::os::{block_until, wait_until, Os, OsApi};
 use crate::{IOError, IOError::WriteFull};
 use crate::{OpenFlag, StdData, ToMakeStdData};
 use alloc::boxed::Box;
-use alloc::collections::{LinkedList, VecDeque};
 use alloc::vec::Vec;
-use bsp::{BspAsyncSerial, BspSerial};
+use bsp::{AsyncWaiter, BspAsyncSerial, BspSerial};
 use core::cell::Cell;
+use core::fmt::{Display, Formatter};
 use core::task::Waker;
-use rtt_rs::raw_api::no_irq;
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SerialError {
     InitError,
     WriteError,
     ReadError,
     UninitError,
     BufferNull,
+    // 接收溢出，硬件或接收缓冲区来不及处理
+    Overrun,
+    // 帧错误，通常是波特率不匹配
+    Framing,
+    Parity,
 }
 
 impl From<SerialError> for IOError {
-    fn from(_: SerialError) -> Self {
-        IOError::DeviceOpsError
+    fn from(e: SerialError) -> Self {
+        IOError::Device(DeviceError::Serial(e))
+    }
+}
+
+impl Display for SerialError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SerialError::InitError => write!(f, "init failed"),
+            SerialError::WriteError => write!(f, "write failed"),
+            SerialError::ReadError => write!(f, "read failed"),
+            SerialError::UninitError => write!(f, "uninit failed"),
+            SerialError::BufferNull => write!(f, "buffer not allocated"),
+            SerialError::Overrun => write!(f, "receive overrun"),
+            SerialError::Framing => write!(f, "framing error"),
+            SerialError::Parity => write!(f, "parity error"),
+        }
     }
 }
 
@@ -43,6 +65,20 @@ pub enum SerialBaudRate {
     B3000000 = 3000000,
 }
 
+impl SerialBaudRate {
+    // 不是支持的波特率时返回 None
+    pub fn from_u32(val: u32) -> Option<SerialBaudRate> {
+        use SerialBaudRate::*;
+        [
+            B2400, B4800, B9600, B19200, B38400, B57600, B115200, B230400, B460800, B921600,
+            B2000000, B3000000,
+        ]
+        .iter()
+        .copied()
+        .find(|b| *b as u32 == val)
+    }
+}
+
 #[derive(Copy, Clone, PartialOrd, PartialEq)]
 pub enum SerialDataBits {
     B5 = 5,
@@ -90,6 +126,23 @@ impl ToMakeStdData for SerialConfig {
     }
 }
 
+pub enum SerialCmd {
+    Config(SerialConfig),
+    GetBaud,
+    // 当前的打开标志，包含通过 chf 修改的部分
+    GetFlag,
+}
+
+pub enum SerialReply {
+    Ok,
+    Baud(SerialBaudRate),
+    Flag(OpenFlag),
+}
+
+impl ControlCmd for SerialCmd {
+    type Reply = SerialReply;
+}
+
 pub trait DeviceSerial {
     fn init(&self, f: &OpenFlag) -> Result<(), SerialError>;
     fn uninit(&self) -> Result<(), SerialError>;
@@ -101,27 +154,102 @@ pub trait DeviceSerial {
     fn write_finish(&self) -> bool;
     fn rx_irq_en(&self, f: bool);
     fn tx_irq_en(&self, f: bool);
-    fn dma_write(&self, ptr: *const u8, len: usize);
-    fn config_baud(&self, val: SerialBaudRate);
-    fn stop_bots(&self, val: SerialStopBits);
-    fn data_bits(&self, val: SerialDataBits);
-    fn parity(&self, val: SerialParity);
-    fn bit_order(&self, val: SerialBitOrder);
+    fn dma_write(&self, ptr: *const u8, len: usize) -> Result<(), SerialError>;
+    // 硬件不支持的配置返回 UnsupportedError
+    fn config_baud(&self, val: SerialBaudRate) -> Result<(), IOError>;
+    fn stop_bots(&self, val: SerialStopBits) -> Result<(), IOError>;
+    fn data_bits(&self, val: SerialDataBits) -> Result<(), IOError>;
+    fn parity(&self, val: SerialParity) -> Result<(), IOError>;
+    fn bit_order(&self, val: SerialBitOrder) -> Result<(), IOError>;
     fn update_flags(&self, f: OpenFlag) -> OpenFlag;
+    // 硬件当前使用的波特率，没有通过 config_baud 配置过时查询
+    fn get_baud(&self) -> Result<SerialBaudRate, IOError> {
+        Err(IOError::UnsupportedError)
+    }
+    // 挂起时关闭外设时钟，恢复时重新打开，寄存器的配置由硬件保持
+    fn suspend(&self) -> Result<(), SerialError> {
+        Ok(())
+    }
+    fn resume(&self) -> Result<(), SerialError> {
+        Ok(())
+    }
 }
 
+// 挂起前等待发送缓冲区清空的时间，单位 ms
+const SUSPEND_SYNC_MS: u32 = 100;
+
 pub struct Serial<T: DeviceSerial> {
     dev: T,
     flag: Cell<Option<OpenFlag>>,
+    // 通过 config_baud 配置过的波特率，恢复时重新配置
+    baud: Cell<Option<SerialBaudRate>>,
 }
 
 #[allow(dead_code)]
 impl<T: DeviceSerial> Serial<T> {
-    pub(crate) fn new(dev: T) -> Serial<T> {
+    pub fn new(dev: T) -> Serial<T> {
         Serial {
             dev,
             flag: Cell::new(None),
+            baud: Cell::new(None),
+        }
+    }
+
+    fn config(&self, cfg: SerialConfig) -> Result<(), IOError> {
+        match cfg {
+            SerialConfig::Baud(a) => {
+                self.dev.config_baud(a)?;
+                self.baud.set(Some(a));
+            }
+            SerialConfig::DataBits(a) => self.dev.data_bits(a)?,
+            SerialConfig::StopBits(a) => self.dev.stop_bots(a)?,
+            SerialConfig::Parity(a) => self.dev.parity(a)?,
+            SerialConfig::BitOrder(a) => self.dev.bit_order(a)?,
+            SerialConfig::WBufSize(a) => {
+                let hp = &self.dev.get_helper().w_buffer;
+                let wb = hp.get();
+                Os::no_irq(|| unsafe {
+                    (*wb).resize(a as usize);
+                });
+            }
+            SerialConfig::RBufSize(a) => {
+                let hp = &self.dev.get_helper().r_buffer;
+                let wb = hp.get();
+                Os::no_irq(|| unsafe {
+                    (*wb).resize(a as usize);
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn get_baud(&self) -> Result<SerialBaudRate, IOError> {
+        match self.baud.get() {
+            Some(b) => Ok(b),
+            None => self.dev.get_baud(),
+        }
+    }
+
+    // 将数据放入发送缓冲区并打开发送中断，返回放入的字节数
+    fn push_w_buffer(&self, data: &[u8]) -> usize {
+        if data.is_empty() {
+            return 0;
         }
+        Os::no_irq(|| unsafe {
+            let wb = self.dev.get_helper().w_buffer.get();
+            let len = (*wb).free_len().min(data.len());
+            for ch in &data[..len] {
+                (*wb).force_push(*ch);
+            }
+            self.dev.tx_irq_en(true);
+            len
+        })
+    }
+
+    // 阻塞写时等待可写，超时时间由打开标志给出
+    fn wait_write_able(&self) -> Result<(), IOError> {
+        let timeout = self.flag.get().and_then(|f| f.get_write_timeout());
+        block_until(|| self.dev.write_able(), timeout)
     }
 }
 
@@ -139,25 +267,21 @@ where
     }
 
     fn read(&self, len: u32) -> Result<StdData, IOError> {
-        if self.flag.get().unwrap().get_read_int() || self.flag.get().unwrap().get_read_async() {
-            Ok(no_irq(|| unsafe {
+        let flag = self.flag.get().ok_or(IOError::ReadError)?;
+        if flag.get_read_int() || flag.get_read_async() {
+            Ok(Os::no_irq(|| unsafe {
                 let buf = self.dev.get_helper().r_buffer.get();
                 match (*buf).pop() {
                     None => StdData::Null,
                     Some(a) => StdData::U32(a as u32),
                 }
             }))
-        } else if self.flag.get().unwrap().get_read_block() {
-            loop {
-                if self.dev.read_able() {
-                    return self
-                        .dev
-                        .read_char()
-                        .map(|a| StdData::U32(a as u32))
-                        .map_err(|_| IOError::ReadError);
-                }
-                rtt_rs::thread::Thread::_yield();
-            }
+        } else if flag.get_read_block() {
+            block_until(|| self.dev.read_able(), flag.get_read_timeout())?;
+            self.dev
+                .read_char()
+                .map(|a| StdData::U32(a as u32))
+                .map_err(IOError::from)
         } else {
             if !self.dev.read_able() {
                 Err(IOError::ReadEmpty)
@@ -165,24 +289,20 @@ where
                 self.dev
                     .read_char()
                     .map(|a| StdData::U32(a as u32))
-                    .map_err(|_| IOError::ReadError)
+                    .map_err(IOError::from)
             }
         }
     }
 
     // support irq send, block send
     fn write(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
-        if self.flag.get().unwrap().get_write_block() {
+        let flag = self.flag.get().ok_or(IOError::WriteError)?;
+        if flag.get_write_block() {
             match data.make_data() {
                 StdData::Bytes(a) => {
                     let mut ok = true;
                     for ch in a {
-                        loop {
-                            if self.dev.write_able() {
-                                break;
-                            }
-                            rtt_rs::thread::Thread::_yield();
-                        }
+                        self.wait_write_able()?;
                         if let Err(_) = self.dev.write_char(ch) {
                             ok = false;
                         }
@@ -194,12 +314,7 @@ where
                     }
                 }
                 StdData::U32(b) => {
-                    loop {
-                        if self.dev.write_able() {
-                            break;
-                        }
-                        rtt_rs::thread::Thread::_yield();
-                    }
+                    self.wait_write_able()?;
                     self.dev.write_char(b as u8).map_err(|e| e.into())
                 }
                 _ => return Err(IOError::WriteError),
@@ -207,47 +322,94 @@ where
         } else {
             return match data.make_data() {
                 StdData::Bytes(a) => {
-                    let ret;
-                    if a.is_empty() {
-                        ret = Ok(())
+                    let len = self.push_w_buffer(&a);
+                    if len < a.len() {
+                        Err(WriteFull(StdData::Bytes(Vec::from(&a[len..]))))
                     } else {
-                        let mut dq = VecDeque::from(a);
-                        no_irq(|| unsafe {
-                            let wb = self.dev.get_helper().w_buffer.get();
-                            for ch in 0..(*wb).free_len() {
-                                if let Some(ch) = dq.pop_front() {
-                                    (*wb).force_push(ch);
-                                } else {
-                                    break;
-                                }
-                            }
-                            self.dev.tx_irq_en(true);
-                        });
-                        if !dq.is_empty() {
-                            ret = Err(WriteFull(StdData::Bytes(Vec::from(dq))));
-                        } else {
-                            ret = Ok(())
-                        }
+                        Ok(())
+                    }
+                }
+                // 与长数据相同，放入发送缓冲区，不在关中断时等待硬件
+                StdData::U32(b) => {
+                    if self.push_w_buffer(&[b as u8]) == 0 {
+                        Err(WriteFull(StdData::U32(b)))
+                    } else {
+                        Ok(())
+                    }
+                }
+                StdData::U8(b) => {
+                    if self.push_w_buffer(&[b]) == 0 {
+                        Err(WriteFull(StdData::U8(b)))
+                    } else {
+                        Ok(())
                     }
-                    ret
                 }
-                StdData::U32(b) => no_irq(|| {
-                    while !self.dev.read_able() {}
-                    self.dev.write_char(b as _).map_err(|e| e.into())
-                }),
-                StdData::U8(b) => no_irq(|| {
-                    while !self.dev.read_able() {}
-                    self.dev.write_char(b).map_err(|e| e.into())
-                }),
                 _ => Err(IOError::WriteError),
             };
         }
     }
 
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Serial
+    }
+
+    // 中断模式下直接从接收缓冲区取数据，没有数据时返回 0
+    // 阻塞模式下至少等到一个字节
+    fn read_into(&self, buf: &mut [u8]) -> Result<usize, IOError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let flag = self.flag.get().ok_or(IOError::ReadError)?;
+        if flag.get_read_int() || flag.get_read_async() {
+            return Ok(Os::no_irq(|| unsafe {
+                let rb = self.dev.get_helper().r_buffer.get();
+                let mut len = 0;
+                while len < buf.len() {
+                    match (*rb).pop() {
+                        None => break,
+                        Some(a) => buf[len] = a,
+                    }
+                    len += 1;
+                }
+                len
+            }));
+        }
+        if flag.get_read_block() {
+            block_until(|| self.dev.read_able(), flag.get_read_timeout())?;
+        } else if !self.dev.read_able() {
+            return Err(IOError::ReadEmpty);
+        }
+        let mut len = 0;
+        while len < buf.len() && self.dev.read_able() {
+            buf[len] = self.dev.read_char()?;
+            len += 1;
+        }
+        Ok(len)
+    }
+
+    // 中断模式下返回放入发送缓冲区的字节数，缓冲区满时可能小于数据长度
+    fn write_from(&self, buf: &[u8]) -> Result<usize, IOError> {
+        let flag = self.flag.get().ok_or(IOError::WriteError)?;
+        if flag.get_write_block() {
+            // 超时前已经发送的字节数仍然返回，一个都没有发送时返回错误
+            for (n, ch) in buf.iter().enumerate() {
+                let ret = self
+                    .wait_write_able()
+                    .and_then(|_| self.dev.write_char(*ch).map_err(IOError::from));
+                if let Err(e) = ret {
+                    return if n == 0 { Err(e) } else { Ok(n) };
+                }
+            }
+            Ok(buf.len())
+        } else {
+            Ok(self.push_w_buffer(buf))
+        }
+    }
+
     fn close(&self) -> Result<(), IOError> {
         self.dev.uninit()?;
         self.flag.set(None);
-        no_irq(|| unsafe {
+        Os::no_irq(|| unsafe {
             (*self.dev.get_helper().r_buffer.get()).clean();
             (*self.dev.get_helper().w_buffer.get()).clean();
             (*self.dev.get_helper().read_async_helper.get()) = None;
@@ -259,83 +421,220 @@ where
         let cfg = data.make_data();
         match cfg {
             StdData::Type(a) => {
-                if !a.is::<SerialConfig>() {
-                    Err(IOError::ControlError)
-                } else {
-                    let b = a.downcast::<SerialConfig>().unwrap();
-                    match *b {
-                        SerialConfig::Baud(a) => {
-                            self.dev.config_baud(a);
-                        }
-                        SerialConfig::WBufSize(a) => {
-                            let hp = &self.dev.get_helper().w_buffer;
-                            let wb = hp.get();
-                            no_irq(|| unsafe {
-                                (*wb).resize(a as usize);
-                            });
-                        }
-                        SerialConfig::RBufSize(a) => {
-                            let hp = &self.dev.get_helper().r_buffer;
-                            let wb = hp.get();
-                            no_irq(|| unsafe {
-                                (*wb).resize(a as usize);
-                            });
-                        }
-                        _ => {
-                            // TODO
-                        }
-                    }
-                    Ok(())
-                }
+                let b = a
+                    .downcast::<SerialConfig>()
+                    .map_err(|_| IOError::ControlError)?;
+                self.config(*b)
             }
             _ => Err(IOError::ControlError),
         }
     }
 
-    fn sync(&self) {
-        loop {
-            let mut end = false;
-            no_irq(|| unsafe {
-                let bf = self.dev.get_helper().w_buffer.get();
-                if (*bf).empty() {
-                    end = true;
-                }
-            });
-            if end {
-                break;
-            } else {
-                rtt_rs::thread::Thread::delay(1);
+    fn command(&self, cmd: RawCmd) -> Result<RawReply, IOError> {
+        let reply = match take_cmd::<SerialCmd>(cmd)? {
+            SerialCmd::Config(cfg) => {
+                self.config(cfg)?;
+                SerialReply::Ok
+            }
+            SerialCmd::GetBaud => SerialReply::Baud(self.get_baud()?),
+            SerialCmd::GetFlag => SerialReply::Flag(self.flag.get().ok_or(IOError::ControlError)?),
+        };
+        Ok(make_reply::<SerialCmd>(reply))
+    }
+
+    // 等待发送缓冲区清空并且硬件发送完成
+    fn sync(&self, timeout: Option<u32>) -> Result<(), IOError> {
+        wait_until(
+            || {
+                let empty = Os::no_irq(|| unsafe {
+                    let bf = self.dev.get_helper().w_buffer.get();
+                    (*bf).empty()
+                });
+                Ok(empty && self.dev.write_finish())
+            },
+            timeout,
+        )
+    }
+
+    // 发送完缓冲区中的数据后关闭中断，再由 BSP 关闭时钟
+    fn suspend(&self) -> Result<(), IOError> {
+        self.sync(Some(SUSPEND_SYNC_MS))?;
+        self.dev.rx_irq_en(false);
+        self.dev.tx_irq_en(false);
+        self.dev.suspend()?;
+        Ok(())
+    }
+
+    // 打开时钟后恢复波特率和打开时的中断设置
+    fn resume(&self) -> Result<(), IOError> {
+        self.dev.resume()?;
+        if let Some(b) = self.baud.get() {
+            self.dev.config_baud(b)?;
+        }
+        if let Some(flag) = self.flag.get() {
+            if flag.get_read_int() || flag.get_read_async() {
+                self.dev.rx_irq_en(true);
             }
         }
+        Ok(())
+    }
+
+    // 独占标志不能通过 chf 修改
+    fn chf(&self, data: StdData) -> Result<StdData, IOError> {
+        let mut new = match data {
+            StdData::OpenFlag(f) => f,
+            _ => return Err(IOError::DataError),
+        };
+        let old = self.flag.get().ok_or(IOError::ControlError)?;
+        new.set_only(old.get_only());
+        let new = self.dev.update_flags(new);
+        self.dev
+            .rx_irq_en(new.get_read_int() || new.get_read_async());
+        self.flag.set(Some(new));
+        Ok(StdData::OpenFlag(old))
     }
 
-    fn register_read_callback(&self, func: fn(Waker), cx: Waker) -> Result<(), IOError> {
-        no_irq(|| unsafe {
-            let helper = self.dev.get_helper().read_async_helper.get();
-            match *helper {
-                None => {
-                    *helper = Some(BspAsyncSerial {
-                        async_wakers: {
-                            let mut list = LinkedList::new();
-                            list.push_back(cx);
-                            list
-                        },
-                        async_notify: func,
-                    });
+    fn buffer_drops(&self) -> usize {
+        Os::no_irq(|| unsafe {
+            let hp = self.dev.get_helper();
+            (*hp.r_buffer.get()).dropped() + (*hp.w_buffer.get()).dropped()
+        })
+    }
+
+    // 同一个等待者重复注册时只更新 waker 和条件
+    // 注册时条件已经满足则立即唤醒，避免错过注册之前到达的数据
+    fn register_read_callback(
+        &self,
+        id: usize,
+        need: usize,
+        func: fn(Waker),
+        cx: &Waker,
+    ) -> Result<(), IOError> {
+        Os::no_irq(|| unsafe {
+            let hp = self.dev.get_helper();
+            let a = (*hp.read_async_helper.get()).get_or_insert_with(|| BspAsyncSerial {
+                async_wakers: Vec::new(),
+                async_notify: func,
+            });
+            a.async_notify = func;
+            match a.async_wakers.iter_mut().find(|w| w.id == id) {
+                Some(w) => {
+                    w.need = need;
+                    if !w.waker.will_wake(cx) {
+                        w.waker = cx.clone();
+                    }
                 }
-                Some(ref mut a) => a.async_wakers.push_back(cx),
+                None => a.async_wakers.push(AsyncWaiter {
+                    id,
+                    need,
+                    waker: cx.clone(),
+                }),
             }
+            a.wake_ready(&*hp.r_buffer.get());
         });
         Ok(())
     }
 
+    fn unregister_read_callback(&self, id: usize) {
+        Os::no_irq(|| unsafe {
+            if let Some(ref mut a) = *self.dev.get_helper().read_async_helper.get() {
+                a.async_wakers.retain(|w| w.id != id);
+            }
+        });
+    }
+
     fn register_rx_indicate(&self, func: fn()) {
-        if !self.flag.get().unwrap().get_read_c_type() {
-            return;
+        match self.flag.get() {
+            Some(f) if f.get_read_c_type() => {}
+            _ => return,
         }
-        no_irq(|| unsafe {
+        Os::no_irq(|| unsafe {
             let f = self.dev.get_helper().rx_indicate.get();
             (*f) = Some(func);
         });
     }
 }
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use super::{SerialBaudRate, SerialConfig, SerialDataBits, SerialError, SerialParity};
+    use crate::bsp::host_sim::testing::sim_uart;
+    use crate::bsp::host_sim::uart::{
+        uart_baud, uart_inject_rx, uart_inject_rx_error, uart_limit_tx, uart_take_tx,
+    };
+    use crate::driver::DriverOps;
+    use crate::error::DeviceError;
+    use crate::typed::find_serial;
+    use crate::{IOError, OpenFlag};
+
+    #[test]
+    fn baud_from_hardware() {
+        let num = sim_uart("test/serial/baud");
+        let dev = find_serial("test/serial/baud").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        assert!(g.get_baud().unwrap() == SerialBaudRate::B115200);
+        g.set_baud(SerialBaudRate::B9600).unwrap();
+        assert_eq!(uart_baud(num), 9600);
+        assert!(g.get_baud().unwrap() == SerialBaudRate::B9600);
+    }
+
+    #[test]
+    fn config_dispatch() {
+        sim_uart("test/serial/config");
+        let dev = find_serial("test/serial/config").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        assert!(g
+            .raw()
+            .control(&SerialConfig::DataBits(SerialDataBits::B8))
+            .is_ok());
+        // 不支持的配置不会被当作成功
+        assert!(matches!(
+            g.raw().control(&SerialConfig::Parity(SerialParity::ODD)),
+            Err(IOError::UnsupportedError)
+        ));
+    }
+
+    #[test]
+    fn blocking_write_partial() {
+        let num = sim_uart("test/serial/partial");
+        let dev = find_serial("test/serial/partial").unwrap();
+        let g = dev
+            .open(OpenFlag::zero().set_write_block(true).set_write_timeout(5))
+            .unwrap();
+        uart_limit_tx(num, Some(2));
+        // 超时前发送的字节数
+        assert_eq!(g.raw().write_from(0, b"abcd").unwrap(), 2);
+        assert!(matches!(
+            g.raw().write_from(0, b"cd"),
+            Err(IOError::Timeout)
+        ));
+        assert_eq!(uart_take_tx(num), b"ab");
+        uart_limit_tx(num, None);
+    }
+
+    #[test]
+    fn blocking_read_timeout() {
+        let num = sim_uart("test/serial/rtmo");
+        let dev = find_serial("test/serial/rtmo").unwrap();
+        let g = dev
+            .open(OpenFlag::zero().set_read_block(true).set_read_timeout(5))
+            .unwrap();
+        assert!(matches!(g.raw().read(0, 1), Err(IOError::Timeout)));
+        uart_inject_rx(num, b"x");
+        assert!(g.read_byte().unwrap() == Some(b'x'));
+    }
+
+    #[test]
+    fn read_error_flags() {
+        let num = sim_uart("test/serial/ore");
+        let dev = find_serial("test/serial/ore").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        uart_inject_rx_error(num, SerialError::Overrun);
+        uart_inject_rx(num, b"ab");
+        assert!(matches!(
+            g.raw().read(0, 1),
+            Err(IOError::Device(DeviceError::Serial(SerialError::Overrun)))
+        ));
+        assert!(g.read_byte().unwrap() == Some(b'b'));
+    }
+}
@@ -1,7 +1,7 @@
 use crate::alloc::sync::Arc;
 use crate::device::base::DynCycleQueue;
+use crate::os::Semaphore;
 use core::cell::UnsafeCell;
-use rtt_rs::semaphore::Semaphore;
 
 pub struct SerialSimpleHelper {
     pub r_buffer: UnsafeCell<DynCycleQueue<u8>>,
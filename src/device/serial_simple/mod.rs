@@ -1,6 +1,7 @@
-use crate::device::DeviceOps;
+use crate::device::{DeviceClass, DeviceOps};
+use crate::os::{Os, OsApi, OsSemaphore};
 use crate::{IOError, OpenFlag, StdData, ToMakeStdData};
-use rtt_rs::raw_api::no_irq;
+use core::cell::Cell;
 
 mod bsp;
 
@@ -19,20 +20,35 @@ pub trait DeviceSerialSimple {
 
 pub struct SerialSimple<T: DeviceSerialSimple> {
     dev: T,
+    flag: Cell<Option<OpenFlag>>,
+}
+
+impl<T: DeviceSerialSimple> SerialSimple<T> {
+    pub fn new(dev: T) -> SerialSimple<T> {
+        SerialSimple {
+            dev,
+            flag: Cell::new(None),
+        }
+    }
 }
 
 impl<T: DeviceSerialSimple> DeviceOps for SerialSimple<T> {
-    fn open(&self, _flag: &OpenFlag) -> Result<(), IOError> {
+    fn open(&self, flag: &OpenFlag) -> Result<(), IOError> {
         self.dev.init();
+        self.flag.set(Some(flag.clone()));
         Ok(())
     }
 
+    // 等待接收信号量，超时时间由打开标志给出
     fn read(&self, len: u32) -> Result<StdData, IOError> {
         let hp = self.dev.get_helper();
         let sem = hp.rx_sem.clone();
-        sem.take_wait_forever().unwrap();
-        let ch = no_irq(|| unsafe { (*hp.w_buffer.get()).pop().unwrap() });
-        Ok(StdData::U32(ch as _))
+        match self.flag.get().and_then(|f| f.get_read_timeout()) {
+            None => sem.take_wait_forever().map_err(|_| IOError::ReadError)?,
+            Some(t) => sem.take_wait(t).map_err(|_| IOError::Timeout)?,
+        }
+        let ch = Os::no_irq(|| unsafe { (*hp.r_buffer.get()).pop() });
+        ch.map(|a| StdData::U32(a as _)).ok_or(IOError::ReadEmpty)
     }
 
     fn write(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
@@ -41,7 +57,7 @@ impl<T: DeviceSerialSimple> DeviceOps for SerialSimple<T> {
         unsafe {
             // 在无中断的上下文执行
             // buf可能在中断环境中被使用
-            no_irq(|| match data {
+            Os::no_irq(|| match data {
                 StdData::Bytes(a) => {
                     for i in a {
                         (*hp.w_buffer.get()).force_push(i);
@@ -59,12 +75,22 @@ impl<T: DeviceSerialSimple> DeviceOps for SerialSimple<T> {
         Ok(())
     }
 
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Serial
+    }
+
+    fn buffer_drops(&self) -> usize {
+        let hp = self.dev.get_helper();
+        Os::no_irq(|| unsafe { (*hp.r_buffer.get()).dropped() + (*hp.w_buffer.get()).dropped() })
+    }
+
     fn close(&self) -> Result<(), IOError> {
         self.dev.uninit();
+        self.flag.set(None);
         Ok(())
     }
 
     fn control(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
-        unimplemented!()
+        Err(IOError::UnsupportedError)
     }
 }
@@ -6,10 +6,14 @@
 #![allow(dead_code)]
 
 pub mod bsp;
-use crate::alloc::prelude::v1::Vec;
+use crate::alloc::boxed::Box;
+use crate::alloc::sync::Arc;
+use crate::alloc::vec::Vec;
 use crate::device::spi_bus::bsp::BspBusSpi;
 use crate::device::spi_device::{SpiConfig, SpiError};
-use crate::OpenFlag;
+use crate::device::{DeviceClass, DeviceOps};
+use crate::os::{Os, OsApi};
+use crate::{IOError, Mutex, OpenFlag, ToMakeStdData};
 use core::sync::atomic::{AtomicBool, Ordering};
 
 // SPI总线框架支持4种操作方式
@@ -25,28 +29,39 @@ pub trait BusSpi {
     fn init(&self, f: &OpenFlag, cfg: &SpiConfig) -> Result<(), SpiError>;
     // 无参数初始化
     fn np_init(&self) -> Result<(), SpiError>;
-    // 默认的CS引脚
-    fn cs(&self, f: bool) {
-        unimplemented!()
-    }
+    // 默认的CS引脚，没有默认 CS 引脚的总线不需要实现
+    fn cs(&self, f: bool) {}
     // 用来关闭总线设备
     fn uninit(&self) -> Result<(), SpiError>;
     // 使用总线发送数据
     fn trans_bit(&self, data: u8) -> Result<u8, SpiError>;
-    fn trans_bits_dma(&self, ptr: *const u8, len: usize);
+    fn trans_bits_dma(&self, ptr: *const u8, len: usize) -> Result<(), SpiError>;
     // 获取辅助器
     // TODO：总线的辅助器还没有被定义
     fn get_helper(&self) -> &BspBusSpi;
 }
 
+// BSP 等待 SPI 寄存器状态的超时时间，单位 ms
+pub const TRANS_TIMEOUT_MS: u32 = 10;
+
 // 提供给设备使用的API
 pub trait BusSpiOps {
-    fn trans_bit(&self, data: u8) -> Result<u8, ()>;
+    fn trans_bit(&self, data: u8) -> Result<u8, SpiError>;
     // 会产生休眠
-    fn trans_bits_dam(&self, data: Vec<u8>) -> Result<Vec<u8>, ()>;
-    fn sync(&self) -> Result<(), ()>;
+    fn trans_bits_dam(&self, data: Vec<u8>) -> Result<Vec<u8>, SpiError>;
+    fn sync(&self) -> Result<(), SpiError>;
+    // 总线作为父设备被打开、关闭时调用
+    fn bus_init(&self) -> Result<(), SpiError> {
+        Ok(())
+    }
+    fn bus_uninit(&self) -> Result<(), SpiError> {
+        Ok(())
+    }
 }
 
+// 设备共享的总线
+pub type SpiBusRef = Arc<Mutex<Box<dyn BusSpiOps + Send>>>;
+
 pub struct BusSpiHandler<T: BusSpi> {
     pub(crate) dev: T,
     init: AtomicBool,
@@ -63,20 +78,77 @@ impl<T: BusSpi> BusSpiHandler<T> {
 
 // 整合一些传输算法到该实现里面
 impl<T: BusSpi> BusSpiOps for BusSpiHandler<T> {
-    fn trans_bit(&self, data: u8) -> Result<u8, ()> {
+    fn trans_bit(&self, data: u8) -> Result<u8, SpiError> {
         if !self.init.load(Ordering::Acquire) {
-            self.dev.np_init().unwrap();
-            rtt_rs::thread::Thread::delay(1);
+            self.dev.np_init()?;
+            Os::delay(1);
             self.init.store(true, Ordering::Release);
         }
-        Ok(self.dev.trans_bit(data).unwrap())
+        self.dev.trans_bit(data)
     }
 
-    fn trans_bits_dam(&self, data: Vec<u8>) -> Result<Vec<u8>, ()> {
-        todo!()
+    // 逐字节传输，DMA 还没有支持
+    fn trans_bits_dam(&self, data: Vec<u8>) -> Result<Vec<u8>, SpiError> {
+        let mut ret = Vec::with_capacity(data.len());
+        for b in data {
+            ret.push(self.trans_bit(b)?);
+        }
+        Ok(ret)
     }
 
-    fn sync(&self) -> Result<(), ()> {
+    fn sync(&self) -> Result<(), SpiError> {
+        Ok(())
+    }
+
+    fn bus_init(&self) -> Result<(), SpiError> {
+        if !self.init.load(Ordering::Acquire) {
+            self.dev.np_init()?;
+            self.init.store(true, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    fn bus_uninit(&self) -> Result<(), SpiError> {
+        if self.init.swap(false, Ordering::AcqRel) {
+            self.dev.uninit()?;
+        }
         Ok(())
     }
 }
+
+// 将总线注册到设备链表中，作为 SPI 设备的父设备
+// 最后一个子设备关闭时反初始化总线
+pub struct SpiBusDev {
+    bus: SpiBusRef,
+}
+
+impl SpiBusDev {
+    pub fn new(bus: SpiBusRef) -> SpiBusDev {
+        SpiBusDev { bus }
+    }
+
+    pub fn bus(&self) -> SpiBusRef {
+        self.bus.clone()
+    }
+}
+
+impl DeviceOps for SpiBusDev {
+    fn open(&self, _flag: &OpenFlag) -> Result<(), IOError> {
+        self.bus.lock().unwrap().bus_init()?;
+        Ok(())
+    }
+
+    fn close(&self) -> Result<(), IOError> {
+        self.bus.lock().unwrap().bus_uninit()?;
+        Ok(())
+    }
+
+    // 总线的传输由挂在其上的设备完成
+    fn control(&self, _data: &dyn ToMakeStdData) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::SpiBus
+    }
+}
@@ -1,8 +1,5 @@
-use crate::alloc::boxed::Box;
-use crate::alloc::sync::Arc;
-use crate::device::spi_bus::BusSpiOps;
-use crate::Mutex;
+use crate::device::spi_bus::SpiBusRef;
 
 pub struct BspSpiDev {
-    pub(crate) bus: Arc<Mutex<Box<dyn BusSpiOps>>>,
+    pub(crate) bus: SpiBusRef,
 }
@@ -1,21 +1,48 @@
 #![allow(dead_code)]
 
-mod bsp;
+pub mod bsp;
 
-use crate::alloc::prelude::v1::Vec;
+use crate::alloc::vec::Vec;
+use crate::device::spi_bus::BusSpiOps;
 use crate::device::spi_device::bsp::BspSpiDev;
-use crate::OpenFlag;
+use crate::device::{DeviceClass, DeviceOps};
+use crate::error::DeviceError;
+use crate::{IOError, OpenFlag, StdData, ToMakeStdData};
+use core::fmt::{Display, Formatter};
+use core::sync::atomic::{AtomicBool, Ordering};
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SpiError {
     InitError,
     WriteError,
     ReadError,
     UninitError,
     BufferNull,
+    // 等待总线状态超时
+    Timeout,
 }
 
+impl From<SpiError> for IOError {
+    fn from(e: SpiError) -> Self {
+        IOError::Device(DeviceError::Spi(e))
+    }
+}
+
+impl Display for SpiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SpiError::InitError => write!(f, "init failed"),
+            SpiError::WriteError => write!(f, "write failed"),
+            SpiError::ReadError => write!(f, "read failed"),
+            SpiError::UninitError => write!(f, "uninit failed"),
+            SpiError::BufferNull => write!(f, "buffer not allocated"),
+            SpiError::Timeout => write!(f, "bus timed out"),
+        }
+    }
+}
+
+// 对应 SPI 的模式 0~3
 #[derive(Copy, Clone)]
 pub enum SpiType {
     TypeA,
@@ -24,11 +51,33 @@ pub enum SpiType {
     TypeD,
 }
 
+impl SpiType {
+    // 返回 (CPOL, CPHA)
+    pub fn cpol_cpha(self) -> (bool, bool) {
+        match self {
+            SpiType::TypeA => (false, false),
+            SpiType::TypeB => (false, true),
+            SpiType::TypeC => (true, false),
+            SpiType::TypeD => (true, true),
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct SpiConfig {
     pub(crate) s_type: SpiType,
 }
 
+impl SpiConfig {
+    pub fn new(s_type: SpiType) -> SpiConfig {
+        SpiConfig { s_type }
+    }
+
+    pub fn s_type(&self) -> SpiType {
+        self.s_type
+    }
+}
+
 pub type SpiBits = Vec<u8>;
 
 /* 支持使用缓冲区进行传输 */
@@ -46,4 +95,134 @@ pub trait DeviceSpi {
     fn get_helper(&self) -> BspSpiDev;
 }
 
-// 需要实现 DeviceOps
+// 挂在 SPI 总线上的设备，由片选区分
+// 第一次传输时初始化设备
+pub struct SpiDev<T: DeviceSpi> {
+    pub(crate) dev: T,
+    init: AtomicBool,
+}
+
+impl<T: DeviceSpi> SpiDev<T> {
+    pub fn new(dev: T) -> SpiDev<T> {
+        SpiDev {
+            dev,
+            init: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn ensure_init(&self) -> Result<(), SpiError> {
+        if !self.init.load(Ordering::Acquire) {
+            self.dev.np_init()?;
+            self.init.store(true, Ordering::Release);
+        }
+        Ok(())
+    }
+}
+
+impl<T: DeviceSpi> SpiDev<T> {
+    // 整个传输过程中持有总线并保持片选有效
+    pub(crate) fn with_bus<F>(&self, f: F) -> Result<(), SpiError>
+    where
+        F: FnOnce(&dyn BusSpiOps) -> Result<(), SpiError>,
+    {
+        self.ensure_init()?;
+        let hp = self.dev.get_helper();
+        let bus = hp.bus.lock().unwrap();
+        self.dev.cs(true);
+        let ret = f(&**bus).and_then(|_| bus.sync());
+        // 出错时也要释放片选
+        self.dev.cs(false);
+        ret
+    }
+
+    pub(crate) fn release(&self) -> Result<(), SpiError> {
+        if self.init.swap(false, Ordering::AcqRel) {
+            self.dev.uninit()?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: DeviceSpi> DeviceOps for SpiDev<T> {
+    fn open(&self, _flag: &OpenFlag) -> Result<(), IOError> {
+        self.ensure_init()?;
+        Ok(())
+    }
+
+    fn close(&self) -> Result<(), IOError> {
+        self.release()?;
+        Ok(())
+    }
+
+    fn control(&self, _data: &dyn ToMakeStdData) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::SpiDevice
+    }
+
+    fn read(&self, len: u32) -> Result<StdData, IOError> {
+        let mut buf = alloc::vec![0; len as usize];
+        self.read_into(&mut buf)?;
+        Ok(StdData::Bytes(buf))
+    }
+
+    fn write(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
+        let d = data
+            .make_data()
+            .take_bytes()
+            .map_err(|_| IOError::DataError)?;
+        self.write_from(&d)?;
+        Ok(())
+    }
+
+    // 读时发送 0xFF
+    fn read_into(&self, buf: &mut [u8]) -> Result<usize, IOError> {
+        self.with_bus(|bus| {
+            for b in buf.iter_mut() {
+                *b = bus.trans_bit(0xFF)?;
+            }
+            Ok(())
+        })?;
+        Ok(buf.len())
+    }
+
+    // 写时丢弃读到的数据
+    fn write_from(&self, buf: &[u8]) -> Result<usize, IOError> {
+        self.with_bus(|bus| {
+            for b in buf {
+                bus.trans_bit(*b)?;
+            }
+            Ok(())
+        })?;
+        Ok(buf.len())
+    }
+}
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use super::*;
+    use crate::api::{find, list_prefix, DevOpen};
+    use crate::bsp::host_sim::spi_bus::{spi_inject_miso, spi_take_mosi};
+    use crate::bsp::host_sim::testing::{sim_spi_bus, SimSpiDev};
+    use crate::device::register_device;
+    use crate::driver::DriverOps;
+
+    #[test]
+    fn spi_device_rw() {
+        register_device(SpiDev::new(SimSpiDev::new(sim_spi_bus(0))), "test/spi/dev").unwrap();
+        let info = list_prefix("test/spi/dev").next().unwrap();
+        assert_eq!(info.class, DeviceClass::SpiDevice);
+
+        let dev = find("test/spi/dev").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        assert_eq!(g.write_from(0, &[1, 2, 3]).unwrap(), 3);
+        assert_eq!(spi_take_mosi(0), [1, 2, 3]);
+        spi_inject_miso(0, &[0xAA, 0x55]);
+        let mut buf = [0u8; 2];
+        assert_eq!(g.read_into(0, &mut buf).unwrap(), 2);
+        assert_eq!(buf, [0xAA, 0x55]);
+        assert_eq!(spi_take_mosi(0), [0xFF, 0xFF]);
+    }
+}
@@ -1,21 +1,47 @@
 //! 后期需要把spi-flash库的代码直接移动到这里来
 //! 目前参数无法传递
+//! 通过 SpiDev 访问总线，作为 SpiBusDev 的子设备注册时由父设备打开、关闭总线
 
-use crate::alloc::prelude::v1::Vec;
-use crate::device::spi_device::DeviceSpi;
-use crate::device::DeviceOps;
+use crate::alloc::vec::Vec;
+use crate::control::{make_reply, take_cmd, ControlCmd, RawCmd, RawReply};
+use crate::device::spi_device::{DeviceSpi, SpiDev, SpiError};
+use crate::device::{DeviceClass, DeviceOps};
+use crate::error::DeviceError;
+use crate::os::{wait_until, Os, OsApi};
 use crate::{IOError, OpenFlag, StdData, ToMakeStdData};
 use core::cell::UnsafeCell;
+use core::fmt::{Display, Formatter};
 use core::time::Duration;
 use spi_flash::{Flash, FlashAccess};
 
 pub struct SpiFlashAccess<T: DeviceSpi> {
-    dev: T,
+    dev: SpiDev<T>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SpiFlashError {
-    SomeError,
+    // 底层 SPI 总线的错误
+    Bus(SpiError),
+    // 等待写入或擦除完成超时
+    Timeout,
+    // spi-flash 库返回的错误
+    Flash,
+}
+
+impl From<SpiFlashError> for IOError {
+    fn from(e: SpiFlashError) -> Self {
+        IOError::Device(DeviceError::SpiFlash(e))
+    }
+}
+
+impl Display for SpiFlashError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SpiFlashError::Bus(e) => write!(f, "bus: {}", e),
+            SpiFlashError::Timeout => write!(f, "wait for ready timed out"),
+            SpiFlashError::Flash => write!(f, "flash operation failed"),
+        }
+    }
 }
 
 impl From<SpiFlashError> for spi_flash::Error {
@@ -28,31 +54,61 @@ impl<T: DeviceSpi> FlashAccess for SpiFlashAccess<T> {
     type Error = SpiFlashError;
 
     fn access_init(&self) -> Result<(), Self::Error> {
-        self.dev.np_init().unwrap();
-        Ok(())
+        self.dev.ensure_init().map_err(SpiFlashError::Bus)
     }
 
     fn access_uninit(&self) -> Result<(), Self::Error> {
-        self.dev.uninit().unwrap();
-        Ok(())
+        self.dev.release().map_err(SpiFlashError::Bus)
     }
 
     fn exchange(&self, data: &[u8]) -> Result<Vec<u8>, Self::Error> {
-        let hp = self.dev.get_helper();
-        let mut ret = Vec::new();
-        let mx = hp.bus.lock().unwrap();
-        self.dev.cs(true);
-        for i in data {
-            ret.push(mx.trans_bit(*i).unwrap());
-        }
-        mx.sync().unwrap();
-        self.dev.cs(false);
+        let mut ret = Vec::with_capacity(data.len());
+        self.dev
+            .with_bus(|bus| {
+                for i in data {
+                    ret.push(bus.trans_bit(*i)?);
+                }
+                Ok(())
+            })
+            .map_err(SpiFlashError::Bus)?;
         Ok(ret)
     }
 
     fn delay(&self, duration: Duration) {
-        let ms = duration.as_micros();
-        rtt_rs::thread::Thread::delay(ms as _);
+        let ms = duration.as_millis();
+        Os::delay(ms as _);
+    }
+}
+
+pub enum SpiFlashCmd {
+    ReadJedecId,
+    ReadStatus,
+}
+
+pub enum SpiFlashReply {
+    // 厂商 ID、存储类型、容量
+    JedecId([u8; 3]),
+    Status(u8),
+}
+
+impl ControlCmd for SpiFlashCmd {
+    type Reply = SpiFlashReply;
+}
+
+impl<T: DeviceSpi> SpiFlashAccess<T> {
+    // 发送命令后将读到的数据直接写入 buf，不分配内存
+    fn read_after(&self, cmd: &[u8], buf: &mut [u8]) -> Result<(), SpiFlashError> {
+        self.dev
+            .with_bus(|bus| {
+                for i in cmd {
+                    bus.trans_bit(*i)?;
+                }
+                for b in buf.iter_mut() {
+                    *b = bus.trans_bit(0xFF)?;
+                }
+                Ok(())
+            })
+            .map_err(SpiFlashError::Bus)
     }
 }
 
@@ -63,51 +119,144 @@ pub struct SpiFlash<T: DeviceSpi> {
 impl<T: DeviceSpi> SpiFlash<T> {
     pub fn new(dev: T) -> SpiFlash<T> {
         SpiFlash {
-            flash: UnsafeCell::new(spi_flash::Flash::new(SpiFlashAccess { dev })),
+            flash: UnsafeCell::new(spi_flash::Flash::new(SpiFlashAccess {
+                dev: SpiDev::new(dev),
+            })),
         }
     }
 }
 
 impl<T: DeviceSpi> DeviceOps for SpiFlash<T> {
     fn open(&self, flag: &OpenFlag) -> Result<(), IOError> {
-        let flash;
-        unsafe {
-            flash = self.flash.get();
-            (*flash).access.access_init().unwrap();
-        }
+        unsafe { (*self.flash.get()).access.access_init()? };
         Ok(())
     }
 
+    fn class(&self) -> DeviceClass {
+        DeviceClass::SpiFlash
+    }
+
     fn close(&self) -> Result<(), IOError> {
-        let flash;
-        unsafe {
-            flash = self.flash.get();
-            (*flash).access.access_uninit().unwrap();
-        };
+        unsafe { (*self.flash.get()).access.access_uninit()? };
         Ok(())
     }
 
-    fn control(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
-        Ok(())
+    // 没有可以配置的参数，读取状态等操作使用 command
+    fn control(&self, _data: &dyn ToMakeStdData) -> Result<(), IOError> {
+        Err(IOError::UnsupportedError)
+    }
+
+    fn command(&self, cmd: RawCmd) -> Result<RawReply, IOError> {
+        let access = unsafe { &(*self.flash.get()).access };
+        let reply = match take_cmd::<SpiFlashCmd>(cmd)? {
+            SpiFlashCmd::ReadJedecId => {
+                let r = access.exchange(&[0x9F, 0, 0, 0])?;
+                if r.len() < 4 {
+                    return Err(IOError::ControlError);
+                }
+                SpiFlashReply::JedecId([r[1], r[2], r[3]])
+            }
+            SpiFlashCmd::ReadStatus => {
+                let r = access.exchange(&[0x05, 0])?;
+                if r.len() < 2 {
+                    return Err(IOError::ControlError);
+                }
+                SpiFlashReply::Status(r[1])
+            }
+        };
+        Ok(make_reply::<SpiFlashCmd>(reply))
     }
 
     fn b_read(&self, address: usize, len: usize) -> Result<StdData, IOError> {
-        let flash;
-        let ret;
+        let ret = unsafe { (*self.flash.get()).read(address as _, len as _) };
+        ret.map(StdData::Bytes)
+            .map_err(|_| SpiFlashError::Flash.into())
+    }
+
+    // 等待 flash 内部的写入或擦除完成
+    fn sync(&self, timeout: Option<u32>) -> Result<(), IOError> {
+        let access = unsafe { &(*self.flash.get()).access };
+        wait_until(
+            || {
+                let mut status = [0u8];
+                access.read_after(&[0x05], &mut status)?;
+                Ok(status[0] & 0x01 == 0)
+            },
+            timeout,
+        )
+        .map_err(|e| match e {
+            IOError::Timeout => SpiFlashError::Timeout.into(),
+            e => e,
+        })
+    }
+
+    fn b_read_into(&self, address: usize, buf: &mut [u8]) -> Result<usize, IOError> {
+        let access = unsafe { &(*self.flash.get()).access };
+        let cmd = [
+            0x03,
+            (address >> 16) as u8,
+            (address >> 8) as u8,
+            address as u8,
+        ];
+        access.read_after(&cmd, buf)?;
+        Ok(buf.len())
+    }
+
+    fn b_write_from(&self, address: usize, buf: &[u8]) -> Result<usize, IOError> {
         unsafe {
-            flash = self.flash.get();
-            ret = (*flash).read(address as _, len as _).unwrap();
+            (*self.flash.get())
+                .program(address as _, buf, false)
+                .map_err(|_| SpiFlashError::Flash)?;
         }
-        Ok(StdData::Bytes(ret))
+        Ok(buf.len())
     }
 
     fn b_write(&self, address: usize, data: &dyn ToMakeStdData) -> Result<(), IOError> {
-        let flash;
-        let d = data.make_data().take_bytes().unwrap();
+        let d = data
+            .make_data()
+            .take_bytes()
+            .map_err(|_| IOError::DataError)?;
         unsafe {
-            flash = self.flash.get();
-            (*flash).program(address as _, d.as_slice(), false).unwrap();
+            (*self.flash.get())
+                .program(address as _, d.as_slice(), false)
+                .map_err(|_| SpiFlashError::Flash)?;
         }
         Ok(())
     }
 }
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use super::*;
+    use crate::api::{find, DevOpen};
+    use crate::bsp::host_sim::spi_bus::{spi_inject_miso, spi_is_init, spi_take_mosi};
+    use crate::bsp::host_sim::testing::{sim_spi_bus, SimSpiDev};
+    use crate::device::spi_bus::SpiBusDev;
+    use crate::device::{register_child_device, register_device, unregister_device};
+
+    #[test]
+    fn flash_under_bus() {
+        let bus = sim_spi_bus(5);
+        register_device(SpiBusDev::new(bus.clone()), "test/flash/spi").unwrap();
+        register_child_device(
+            SpiFlash::new(SimSpiDev::new(bus)),
+            "test/flash/spi/flash",
+            "test/flash/spi",
+        )
+        .unwrap();
+        assert!(unregister_device("test/flash/spi").is_err());
+
+        let dev = find("test/flash/spi/flash").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        // 打开 flash 时由父设备初始化总线
+        assert!(spi_is_init(5));
+        spi_inject_miso(5, &[0xFF, 0xEF, 0x40, 0x18]);
+        assert!(matches!(
+            g.command(SpiFlashCmd::ReadJedecId),
+            Ok(SpiFlashReply::JedecId([0xEF, 0x40, 0x18]))
+        ));
+        assert_eq!(spi_take_mosi(5), [0x9F, 0, 0, 0]);
+        drop(g);
+        assert!(!spi_is_init(5));
+    }
+}
@@ -2,26 +2,147 @@
 #![allow(unused_variables)]
 
 use crate::alloc::boxed::Box;
+use crate::alloc::collections::VecDeque;
+use crate::alloc::string::{String, ToString};
+use crate::alloc::sync::Arc;
+use crate::api::OpenType;
 use crate::async_rw::{AsyncReadFuture, AsyncWriteFuture};
 use crate::data::{StdData, ToMakeStdData};
-use crate::device::DeviceOps;
+use crate::device::{DeviceClass, DeviceOps};
 use crate::error::IOError;
+use crate::guard::DriverGuard;
+use crate::os::{OsSemaphore, Semaphore};
+use crate::stats::DeviceStats;
+use crate::trace::TraceHook;
+use crate::Mutex;
 use core::pin::Pin;
 use core::task::Waker;
 
 // 设备抽象
 pub struct Driver {
+    pub(crate) name: String,
     pub(crate) open_able: bool,
     pub(crate) open_num: u32,
+    // 设备被强制注销后置位，ops 已经被替换为 RemovedDevice
+    pub(crate) removed: bool,
+    // 设备已经从链表中移除，不能再被打开，已经打开的句柄继续可用
+    pub(crate) unregistered: bool,
+    // 等待打开的队列，保存排队的序号和唤醒排队者的信号量
+    pub(crate) waiters: VecDeque<(u32, Arc<Semaphore>)>,
+    pub(crate) next_ticket: u32,
+    pub(crate) stats: DeviceStats,
+    // 清空统计时驱动记录的缓冲区丢弃数量
+    pub(crate) drops_base: usize,
+    // 设备的跟踪钩子
+    pub(crate) trace: Option<TraceHook>,
+    // 父设备，如 I2C 设备所在的总线
+    pub(crate) parent: Option<Arc<Mutex<Driver>>>,
+    // 设备被打开期间持有的父设备句柄，最后一次关闭时释放
+    pub(crate) parent_guard: Option<DriverGuard<'static>>,
+    // 注册在该设备下的子设备数量
+    pub(crate) children: u32,
+    // 设备已经被 pm::suspend_all 挂起
+    pub(crate) suspended: bool,
     pub(crate) ops: Pin<Box<dyn DeviceOps + Send>>,
 }
 
+impl Driver {
+    // 设备可能可以打开了，唤醒排在最前面的等待者
+    pub(crate) fn wake_open_waiter(&self) {
+        if let Some((_, sem)) = self.waiters.front() {
+            let _ = sem.release();
+        }
+    }
+
+    // 设备被注销，所有等待者都需要返回
+    pub(crate) fn wake_all_open_waiters(&self) {
+        for (_, sem) in self.waiters.iter() {
+            let _ = sem.release();
+        }
+    }
+}
+
+// 设备状态查询
+impl Driver {
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn class(&self) -> DeviceClass {
+        self.ops.class()
+    }
+
+    pub fn is_block_dev(&self) -> bool {
+        self.ops.is_block_dev()
+    }
+
+    // 当前的打开方式，没有被打开时返回 None
+    // 只被一个句柄共享打开时返回 Master，多个句柄共享时返回 User
+    // 共享的数量由 open_num 给出
+    pub fn open_type(&self) -> Option<OpenType> {
+        if !self.open_able {
+            Some(OpenType::Only)
+        } else if self.open_num > 1 {
+            Some(OpenType::User)
+        } else if self.open_num == 1 {
+            Some(OpenType::Master)
+        } else {
+            None
+        }
+    }
+
+    pub fn open_num(&self) -> u32 {
+        self.open_num
+    }
+
+    // 父设备的名称，没有父设备时返回 None
+    pub fn parent(&self) -> Option<String> {
+        let parent = self.parent.as_ref()?;
+        let name = parent.lock().unwrap().name.to_string();
+        Some(name)
+    }
+
+    pub fn children(&self) -> u32 {
+        self.children
+    }
+
+    // 设备不再占用父设备，减少父设备的子设备数量
+    pub(crate) fn detach_parent(&self) {
+        if let Some(p) = &self.parent {
+            let mut p = p.lock().unwrap();
+            p.children = p.children.saturating_sub(1);
+        }
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    // 读写统计的快照
+    pub fn stats(&self) -> DeviceStats {
+        let mut s = self.stats.clone();
+        s.buffer_drops = self.ops.buffer_drops().saturating_sub(self.drops_base) as u64;
+        s
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = DeviceStats::default();
+        self.drops_base = self.ops.buffer_drops();
+    }
+}
+
 pub trait DriverOps {
     fn control(&self, data: &dyn ToMakeStdData) -> Result<(), IOError>;
     fn read(&self, address: usize, len: u32) -> Result<StdData, IOError>;
     fn write(&self, address: usize, data: &dyn ToMakeStdData) -> Result<(), IOError>;
+    // 基于缓冲区的读写，返回实际读写的字节数
+    fn read_into(&self, address: usize, buf: &mut [u8]) -> Result<usize, IOError>;
+    fn write_from(&self, address: usize, buf: &[u8]) -> Result<usize, IOError>;
     // 进行同步，将没有写的写出去
-    fn sync(&self);
+    // timeout 单位为 ms，None 表示一直等待
+    fn sync(&self, timeout: Option<u32>) -> Result<(), IOError>;
+    // 修改打开标志，如从阻塞读切换到中断读，不需要重新打开设备
+    // 参数为 StdData::OpenFlag，返回修改前的标志
     fn chf(&self, data: StdData) -> Result<StdData, IOError>;
     fn is_only(&self) -> bool;
     fn is_master(&self) -> bool;
@@ -30,7 +151,7 @@ pub trait DriverOps {
     fn async_write<'a, 'b>(
         &'a self,
         address: usize,
-        data: &'b dyn ToMakeStdData,
+        data: &'b (dyn ToMakeStdData + Sync),
     ) -> Result<AsyncWriteFuture<'a, 'b, '_>, IOError>;
 
     // 为了兼用 c api 做的接受完成函数
@@ -38,7 +159,20 @@ pub trait DriverOps {
 }
 
 pub(crate) trait DriverAsyncHelper {
-    fn register_read_callback(&self, func: fn(Waker), cx: Waker) -> Result<(), IOError>;
+    fn register_read_callback(
+        &self,
+        id: usize,
+        need: usize,
+        func: fn(Waker),
+        cx: &Waker,
+    ) -> Result<(), IOError>;
+    fn unregister_read_callback(&self, id: usize);
 
-    fn register_write_callback(&self, func: fn(Waker), cx: Waker) -> Result<(), IOError>;
+    fn register_write_callback(
+        &self,
+        id: usize,
+        func: fn(Waker),
+        cx: &Waker,
+    ) -> Result<(), IOError>;
+    fn unregister_write_callback(&self, id: usize);
 }
@@ -0,0 +1,505 @@
+//! embedded-hal 1.0、embedded-hal-async 和 embedded-io 的实现
+//! crates.io 上基于这些 trait 编写的传感器、显示屏驱动可以直接使用框架打开的设备
+//! LED -> OutputPin、串口 -> embedded_io::Read/Write、I2C 设备 -> I2c、SPI 设备 -> SpiDevice
+//! 同步接口在框架内本来就是阻塞的，异步的 I2C、SPI 直接调用同步实现
+//! 串口的异步读等待接收中断，需要以中断或异步读的方式打开
+
+use crate::alloc::vec::Vec;
+use crate::device::i2c_bus::{I2CBusError, I2CMsg, SBusI2C, SBusI2CBase, SBusI2CTrans, I2CRW};
+use crate::device::i2c_device::{I2CCmd, I2CReply};
+use crate::device::led::{LedCmd, LedError};
+use crate::device::serial::SerialError;
+use crate::device::spi_bus::BusSpiOps;
+use crate::device::spi_device::{DeviceSpi, SpiDev, SpiError};
+use crate::driver::DriverOps;
+use crate::error::{DeviceError, IOError};
+use crate::os::{wait_until, Os, OsApi};
+use crate::typed::{I2CGuard, LedGuard, SerialGuard};
+use crate::StdData;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use embedded_hal::digital::{self, OutputPin, StatefulOutputPin};
+use embedded_hal::i2c::{self, I2c, NoAcknowledgeSource, Operation as I2cOperation};
+use embedded_hal::spi::{self, Operation as SpiOperation, SpiDevice};
+
+impl digital::Error for IOError {
+    fn kind(&self) -> digital::ErrorKind {
+        digital::ErrorKind::Other
+    }
+}
+
+impl i2c::Error for IOError {
+    fn kind(&self) -> i2c::ErrorKind {
+        match self {
+            IOError::Device(DeviceError::I2CBus(e)) => i2c::Error::kind(e),
+            _ => i2c::ErrorKind::Other,
+        }
+    }
+}
+
+impl spi::Error for IOError {
+    fn kind(&self) -> spi::ErrorKind {
+        spi::ErrorKind::Other
+    }
+}
+
+impl embedded_io::Error for IOError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        use embedded_io::ErrorKind;
+        if self.is_timeout() {
+            return ErrorKind::TimedOut;
+        }
+        match self {
+            IOError::FindError => ErrorKind::NotFound,
+            IOError::RemovedError => ErrorKind::NotConnected,
+            IOError::DataError => ErrorKind::InvalidInput,
+            IOError::UnsupportedError => ErrorKind::Unsupported,
+            IOError::WriteFull(_) => ErrorKind::WriteZero,
+            IOError::Device(DeviceError::Serial(
+                SerialError::Overrun | SerialError::Framing | SerialError::Parity,
+            )) => ErrorKind::InvalidData,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl digital::Error for LedError {
+    fn kind(&self) -> digital::ErrorKind {
+        digital::ErrorKind::Other
+    }
+}
+
+impl i2c::Error for I2CBusError {
+    fn kind(&self) -> i2c::ErrorKind {
+        match self {
+            // 地址和数据的 NACK 没有区分
+            I2CBusError::Nack(_) => i2c::ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            I2CBusError::Timeout | I2CBusError::Bus => i2c::ErrorKind::Bus,
+            I2CBusError::InitError | I2CBusError::Address(_) => i2c::ErrorKind::Other,
+        }
+    }
+}
+
+impl spi::Error for SpiError {
+    fn kind(&self) -> spi::ErrorKind {
+        spi::ErrorKind::Other
+    }
+}
+
+// 让出一次执行权的 future，用于轮询没有中断通知的状态
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+fn yield_now() -> YieldNow {
+    YieldNow(false)
+}
+
+/* LED */
+
+// 高电平表示点亮，与 LED 实际的有效电平无关
+impl digital::ErrorType for LedGuard<'_> {
+    type Error = IOError;
+}
+
+impl OutputPin for LedGuard<'_> {
+    fn set_low(&mut self) -> Result<(), IOError> {
+        self.off()
+    }
+
+    fn set_high(&mut self) -> Result<(), IOError> {
+        self.on()
+    }
+}
+
+impl StatefulOutputPin for LedGuard<'_> {
+    fn is_set_high(&mut self) -> Result<bool, IOError> {
+        self.is_on()
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, IOError> {
+        self.is_on().map(|a| !a)
+    }
+
+    // 在设备锁内完成读取和切换
+    fn toggle(&mut self) -> Result<(), IOError> {
+        self.raw().command(LedCmd::Toggle).map(|_| ())
+    }
+}
+
+/* 串口 */
+
+impl embedded_io::ErrorType for SerialGuard<'_> {
+    type Error = IOError;
+}
+
+// 没有数据可读时返回 0，不视为错误
+fn serial_read_into(g: &SerialGuard<'_>, buf: &mut [u8]) -> Result<usize, IOError> {
+    match g.raw().read_into(0, buf) {
+        Err(IOError::ReadEmpty) => Ok(0),
+        r => r,
+    }
+}
+
+impl embedded_io::Read for SerialGuard<'_> {
+    // 至少读到一个字节才返回，超时时间为打开标志中的读超时
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let timeout = self.open_flag()?.get_read_timeout();
+        let mut len = 0;
+        wait_until(
+            || {
+                len = serial_read_into(self, buf)?;
+                Ok(len > 0)
+            },
+            timeout,
+        )?;
+        Ok(len)
+    }
+}
+
+impl embedded_io::Write for SerialGuard<'_> {
+    // 发送缓冲区满时等待，至少写入一个字节才返回，超时时间为打开标志中的写超时
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IOError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let timeout = self.open_flag()?.get_write_timeout();
+        let mut len = 0;
+        wait_until(
+            || {
+                len = self.raw().write_from(0, buf)?;
+                Ok(len > 0)
+            },
+            timeout,
+        )?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<(), IOError> {
+        let timeout = self.open_flag()?.get_write_timeout();
+        self.sync(timeout)
+    }
+}
+
+impl embedded_io_async::Read for SerialGuard<'_> {
+    // 缓冲区为空时等待接收中断唤醒
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = serial_read_into(self, buf)?;
+        if len > 0 {
+            return Ok(len);
+        }
+        buf[0] = match self.raw().async_read(0, 1)?.await? {
+            StdData::U32(a) => a as u8,
+            StdData::U8(a) => a,
+            _ => return Err(IOError::DataError),
+        };
+        Ok(1 + serial_read_into(self, &mut buf[1..])?)
+    }
+}
+
+impl embedded_io_async::Write for SerialGuard<'_> {
+    // 发送缓冲区满时让出执行权后重试
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, IOError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let len = self.raw().write_from(0, buf)?;
+            if len > 0 {
+                return Ok(len);
+            }
+            yield_now().await;
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), IOError> {
+        loop {
+            match self.sync(Some(0)) {
+                Err(IOError::Timeout) => yield_now().await,
+                r => return r,
+            }
+        }
+    }
+}
+
+/* I2C */
+
+// 连续的同类操作合并为一条消息，不同类操作之间使用重复起始条件
+fn i2c_msgs(address: u8, operations: &[I2cOperation<'_>]) -> Vec<I2CMsg> {
+    let mut msgs: Vec<I2CMsg> = Vec::new();
+    for op in operations {
+        match (op, msgs.last_mut()) {
+            (I2cOperation::Read(buf), Some(m)) if matches!(m.rw, I2CRW::Read) => {
+                m.len += buf.len() as u16;
+                m.buf.resize(m.len as usize, 0);
+            }
+            (I2cOperation::Write(buf), Some(m)) if matches!(m.rw, I2CRW::Write) => {
+                m.buf.extend_from_slice(buf);
+                m.len = m.buf.len() as u16;
+            }
+            (I2cOperation::Read(buf), _) => {
+                msgs.push(I2CMsg::read(address as u16, buf.len() as u16))
+            }
+            (I2cOperation::Write(buf), _) => msgs.push(I2CMsg::write(address as u16, buf.to_vec())),
+        }
+    }
+    msgs
+}
+
+// 把读消息中的数据拷贝回各个读操作的缓冲区
+fn i2c_copy_back(msgs: &[I2CMsg], operations: &mut [I2cOperation<'_>]) {
+    let mut msgs = msgs.iter();
+    let mut cur: Option<&I2CMsg> = None;
+    let mut pos = 0;
+    let mut last_read = None;
+    for op in operations {
+        let is_read = matches!(op, I2cOperation::Read(_));
+        if last_read != Some(is_read) {
+            cur = msgs.next();
+            pos = 0;
+            last_read = Some(is_read);
+        }
+        if let (I2cOperation::Read(buf), Some(m)) = (op, cur) {
+            buf.copy_from_slice(&m.buf[pos..pos + buf.len()]);
+            pos += buf.len();
+        }
+    }
+}
+
+impl i2c::ErrorType for I2CGuard<'_> {
+    type Error = IOError;
+}
+
+// 使用传入的地址，不使用通过 set_address 设置的地址
+impl I2c for I2CGuard<'_> {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [I2cOperation<'_>],
+    ) -> Result<(), IOError> {
+        let msgs = i2c_msgs(address, operations);
+        match self.raw().command(I2CCmd::Transfer(msgs))? {
+            I2CReply::Transfer(msgs) => {
+                i2c_copy_back(&msgs, operations);
+                Ok(())
+            }
+            _ => Err(IOError::DataError),
+        }
+    }
+}
+
+impl embedded_hal_async::i2c::I2c for I2CGuard<'_> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [I2cOperation<'_>],
+    ) -> Result<(), IOError> {
+        I2c::transaction(self, address, operations)
+    }
+}
+
+impl<T: SBusI2CBase> i2c::ErrorType for SBusI2C<T> {
+    type Error = I2CBusError;
+}
+
+// 直接使用总线，需要调用者保证总线已经初始化并且没有被其他设备同时使用
+impl<T: SBusI2CBase> I2c for SBusI2C<T> {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [I2cOperation<'_>],
+    ) -> Result<(), I2CBusError> {
+        let mut msgs = i2c_msgs(address, operations);
+        self.i2c_trans(&mut msgs[..])?;
+        i2c_copy_back(&msgs, operations);
+        Ok(())
+    }
+}
+
+impl<T: SBusI2CBase> embedded_hal_async::i2c::I2c for SBusI2C<T> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [I2cOperation<'_>],
+    ) -> Result<(), I2CBusError> {
+        I2c::transaction(self, address, operations)
+    }
+}
+
+/* SPI */
+
+// 只读时发送的数据
+const SPI_FILL: u8 = 0x00;
+
+fn spi_trans(bus: &dyn BusSpiOps, operations: &mut [SpiOperation<'_, u8>]) -> Result<(), SpiError> {
+    for op in operations {
+        match op {
+            SpiOperation::Read(buf) => {
+                for b in buf.iter_mut() {
+                    *b = bus.trans_bit(SPI_FILL)?;
+                }
+            }
+            SpiOperation::Write(buf) => {
+                for b in buf.iter() {
+                    bus.trans_bit(*b)?;
+                }
+            }
+            // 两个缓冲区长度不同时，多出的部分只读或只写
+            SpiOperation::Transfer(read, write) => {
+                for i in 0..read.len().max(write.len()) {
+                    let r = bus.trans_bit(write.get(i).copied().unwrap_or(SPI_FILL))?;
+                    if let Some(b) = read.get_mut(i) {
+                        *b = r;
+                    }
+                }
+            }
+            SpiOperation::TransferInPlace(buf) => {
+                for b in buf.iter_mut() {
+                    *b = bus.trans_bit(*b)?;
+                }
+            }
+            // 系统延时的精度为 1ms
+            SpiOperation::DelayNs(ns) => Os::delay((*ns).div_ceil(1_000_000)),
+        }
+    }
+    bus.sync()
+}
+
+impl<T: DeviceSpi> spi::ErrorType for SpiDev<T> {
+    type Error = SpiError;
+}
+
+// 整个传输期间持有总线锁并保持片选有效
+impl<T: DeviceSpi> SpiDevice for SpiDev<T> {
+    fn transaction(&mut self, operations: &mut [SpiOperation<'_, u8>]) -> Result<(), SpiError> {
+        self.ensure_init()?;
+        let bus = self.dev.get_helper().bus;
+        let locked_bus = bus.lock().unwrap();
+        self.dev.cs(true);
+        let ret = spi_trans(&**locked_bus, operations);
+        self.dev.cs(false);
+        ret
+    }
+}
+
+impl<T: DeviceSpi> embedded_hal_async::spi::SpiDevice for SpiDev<T> {
+    async fn transaction(
+        &mut self,
+        operations: &mut [SpiOperation<'_, u8>],
+    ) -> Result<(), SpiError> {
+        SpiDevice::transaction(self, operations)
+    }
+}
+
+/* 延时 */
+
+// 使用系统延时，精度为 1ms，不足 1ms 的延时按 1ms 处理
+pub struct Delay;
+
+impl embedded_hal::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        Os::delay(ns.div_ceil(1_000_000));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        Os::delay(us.div_ceil(1_000));
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        Os::delay(ms);
+    }
+}
+
+// 在等待期间不断让出执行权，直到系统时间到达
+// 系统层没有可以注册唤醒的定时器，每次 poll 都会立即唤醒自己，
+// 执行器在等待期间会一直轮询这个 future，不能进入低功耗；对功耗敏感时使用阻塞的 DelayNs
+struct DelayFuture {
+    start: u32,
+    ms: u32,
+}
+
+impl Future for DelayFuture {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Os::tick_ms().wrapping_sub(self.start) >= self.ms {
+            return Poll::Ready(());
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+fn delay_ms(ms: u32) -> DelayFuture {
+    DelayFuture {
+        start: Os::tick_ms(),
+        ms,
+    }
+}
+
+impl embedded_hal_async::delay::DelayNs for Delay {
+    async fn delay_ns(&mut self, ns: u32) {
+        delay_ms(ns.div_ceil(1_000_000)).await
+    }
+
+    async fn delay_ms(&mut self, ms: u32) {
+        delay_ms(ms).await
+    }
+}
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use crate::bsp::host_sim::testing::sim_uart;
+    use crate::bsp::host_sim::uart::{uart_inject_rx, uart_irq, uart_limit_tx, uart_take_tx};
+    use crate::typed::find_serial;
+    use crate::{IOError, OpenFlag};
+    use embedded_io::{Read, Write};
+
+    #[test]
+    fn read_timeout_from_flag() {
+        let num = sim_uart("test/ehal/read");
+        let dev = find_serial("test/ehal/read").unwrap();
+        let mut g = dev.open(OpenFlag::zero().set_read_timeout(5)).unwrap();
+        let mut buf = [0u8; 4];
+        assert!(matches!(g.read(&mut buf), Err(IOError::Timeout)));
+        uart_inject_rx(num, b"ab");
+        assert_eq!(g.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ab");
+    }
+
+    #[test]
+    fn write_timeout_from_flag() {
+        let num = sim_uart("test/ehal/write");
+        let dev = find_serial("test/ehal/write").unwrap();
+        let mut g = dev.open(OpenFlag::zero().set_write_timeout(5)).unwrap();
+        uart_limit_tx(num, Some(0));
+        // 先填满发送缓冲区，之后的写入和 flush 在写超时后返回
+        let n = g.write(&[0x55; 4096]).unwrap();
+        assert!(n > 0 && n < 4096);
+        assert!(matches!(g.write(b"x"), Err(IOError::Timeout)));
+        assert!(matches!(g.flush(), Err(IOError::Timeout)));
+        assert!(uart_take_tx(num).is_empty());
+        uart_limit_tx(num, None);
+        uart_irq(num);
+        g.flush().unwrap();
+        assert_eq!(uart_take_tx(num).len(), n);
+    }
+}
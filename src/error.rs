@@ -1,4 +1,11 @@
+use crate::device::i2c_bus::I2CBusError;
+use crate::device::i2c_device::I2CDeviceError;
+use crate::device::led::LedError;
+use crate::device::serial::SerialError;
+use crate::device::spi_device::SpiError;
+use crate::device::spi_flash::SpiFlashError;
 use crate::StdData;
+use core::fmt::{Display, Formatter};
 
 #[derive(Debug)]
 pub enum IOError {
@@ -17,6 +24,224 @@ pub enum IOError {
     WriteBusy,
     FindError,
     RegisterError,
+    // 设备仍在被使用，无法注销
+    UnregisterError,
+    // 设备已经被强制注销，之前打开的句柄不再可用
+    RemovedError,
     DataError,
     DeviceOpsError,
+    // 等待超时
+    Timeout,
+    // 设备不支持该命令或操作
+    UnsupportedError,
+    // 驱动给出的具体原因，如串口溢出、I2C 从机没有应答
+    Device(DeviceError),
 }
+
+// 各类设备驱动的错误，保留了驱动给出的具体原因
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceError {
+    Serial(SerialError),
+    Led(LedError),
+    I2CBus(I2CBusError),
+    I2CDevice(I2CDeviceError),
+    Spi(SpiError),
+    SpiFlash(SpiFlashError),
+}
+
+// rt-thread 的错误码，C 接口返回它们的相反数
+pub const RT_EOK: isize = 0;
+pub const RT_ERROR: isize = 1;
+pub const RT_ETIMEOUT: isize = 2;
+pub const RT_EFULL: isize = 3;
+pub const RT_EEMPTY: isize = 4;
+pub const RT_ENOMEM: isize = 5;
+pub const RT_ENOSYS: isize = 6;
+pub const RT_EBUSY: isize = 7;
+pub const RT_EIO: isize = 8;
+pub const RT_EINTR: isize = 9;
+pub const RT_EINVAL: isize = 10;
+
+impl IOError {
+    // 错误的种类名称，不包含附带的数据
+    pub fn name(&self) -> &'static str {
+        match self {
+            IOError::OpenError => "OpenError",
+            IOError::ReadError => "ReadError",
+            IOError::ReadEmpty => "ReadEmpty",
+            IOError::WriteError => "WriteError",
+            IOError::WriteFull(_) => "WriteFull",
+            IOError::CloseError => "CloseError",
+            IOError::ControlError => "ControlError",
+            IOError::WriteBusy => "WriteBusy",
+            IOError::FindError => "FindError",
+            IOError::RegisterError => "RegisterError",
+            IOError::UnregisterError => "UnregisterError",
+            IOError::RemovedError => "RemovedError",
+            IOError::DataError => "DataError",
+            IOError::DeviceOpsError => "DeviceOpsError",
+            IOError::Timeout => "Timeout",
+            IOError::UnsupportedError => "UnsupportedError",
+            IOError::Device(e) => e.name(),
+        }
+    }
+
+    // 驱动给出的具体原因
+    pub fn cause(&self) -> Option<&DeviceError> {
+        match self {
+            IOError::Device(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    // 包括驱动给出的超时，如 flash 等待写完成超时
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            IOError::Timeout => true,
+            IOError::Device(e) => e.is_timeout(),
+            _ => false,
+        }
+    }
+
+    // 对应的 rt-thread 错误码，已经取了负数
+    pub fn to_errno(&self) -> isize {
+        let e = match self {
+            IOError::OpenError => RT_EBUSY,
+            IOError::ReadError => RT_EIO,
+            IOError::ReadEmpty => RT_EEMPTY,
+            IOError::WriteError => RT_EIO,
+            IOError::WriteFull(_) => RT_EFULL,
+            IOError::CloseError => RT_EIO,
+            IOError::ControlError => RT_ERROR,
+            IOError::WriteBusy => RT_EBUSY,
+            IOError::FindError => RT_ERROR,
+            IOError::RegisterError => RT_ERROR,
+            IOError::UnregisterError => RT_EBUSY,
+            IOError::RemovedError => RT_ERROR,
+            IOError::DataError => RT_EINVAL,
+            IOError::DeviceOpsError => RT_EIO,
+            IOError::Timeout => RT_ETIMEOUT,
+            IOError::UnsupportedError => RT_ENOSYS,
+            IOError::Device(e) => {
+                if e.is_timeout() {
+                    RT_ETIMEOUT
+                } else {
+                    RT_EIO
+                }
+            }
+        };
+        -e
+    }
+}
+
+impl DeviceError {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceError::Serial(e) => match e {
+                SerialError::InitError => "Serial.InitError",
+                SerialError::WriteError => "Serial.WriteError",
+                SerialError::ReadError => "Serial.ReadError",
+                SerialError::UninitError => "Serial.UninitError",
+                SerialError::BufferNull => "Serial.BufferNull",
+                SerialError::Overrun => "Serial.Overrun",
+                SerialError::Framing => "Serial.Framing",
+                SerialError::Parity => "Serial.Parity",
+            },
+            DeviceError::Led(e) => match e {
+                LedError::InitError => "Led.InitError",
+                LedError::OnOFFError => "Led.OnOffError",
+                LedError::UninitError => "Led.UninitError",
+            },
+            DeviceError::I2CBus(e) => match e {
+                I2CBusError::InitError => "I2CBus.InitError",
+                I2CBusError::Timeout => "I2CBus.Timeout",
+                I2CBusError::Nack(_) => "I2CBus.Nack",
+                I2CBusError::Bus => "I2CBus.Bus",
+                I2CBusError::Address(_) => "I2CBus.Address",
+            },
+            DeviceError::I2CDevice(e) => match e {
+                I2CDeviceError::InitError => "I2CDevice.InitError",
+                I2CDeviceError::UninitError => "I2CDevice.UninitError",
+            },
+            DeviceError::Spi(e) => match e {
+                SpiError::InitError => "Spi.InitError",
+                SpiError::WriteError => "Spi.WriteError",
+                SpiError::ReadError => "Spi.ReadError",
+                SpiError::UninitError => "Spi.UninitError",
+                SpiError::BufferNull => "Spi.BufferNull",
+                SpiError::Timeout => "Spi.Timeout",
+            },
+            DeviceError::SpiFlash(e) => match e {
+                SpiFlashError::Bus(_) => "SpiFlash.Bus",
+                SpiFlashError::Timeout => "SpiFlash.Timeout",
+                SpiFlashError::Flash => "SpiFlash.Flash",
+            },
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            DeviceError::I2CBus(I2CBusError::Timeout) => true,
+            DeviceError::Spi(SpiError::Timeout) => true,
+            DeviceError::SpiFlash(SpiFlashError::Timeout) => true,
+            DeviceError::SpiFlash(SpiFlashError::Bus(SpiError::Timeout)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Display for IOError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            IOError::OpenError => write!(f, "device open failed"),
+            IOError::ReadError => write!(f, "device read failed"),
+            IOError::ReadEmpty => write!(f, "no data to read"),
+            IOError::WriteError => write!(f, "device write failed"),
+            IOError::WriteFull(d) => write!(f, "write buffer full, {} unwritten", d.len()),
+            IOError::CloseError => write!(f, "device close failed"),
+            IOError::ControlError => write!(f, "device control failed"),
+            IOError::WriteBusy => write!(f, "device busy writing"),
+            IOError::FindError => write!(f, "device not found"),
+            IOError::RegisterError => write!(f, "device register failed"),
+            IOError::UnregisterError => write!(f, "device still in use"),
+            IOError::RemovedError => write!(f, "device has been removed"),
+            IOError::DataError => write!(f, "invalid data"),
+            IOError::DeviceOpsError => write!(f, "device operation failed"),
+            IOError::Timeout => write!(f, "timed out"),
+            IOError::UnsupportedError => write!(f, "operation not supported"),
+            IOError::Device(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Display for DeviceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DeviceError::Serial(e) => write!(f, "serial: {}", e),
+            DeviceError::Led(e) => write!(f, "led: {}", e),
+            DeviceError::I2CBus(e) => write!(f, "i2c bus: {}", e),
+            DeviceError::I2CDevice(e) => write!(f, "i2c device: {}", e),
+            DeviceError::Spi(e) => write!(f, "spi: {}", e),
+            DeviceError::SpiFlash(e) => write!(f, "spi flash: {}", e),
+        }
+    }
+}
+
+impl From<DeviceError> for IOError {
+    fn from(e: DeviceError) -> Self {
+        IOError::Device(e)
+    }
+}
+
+#[cfg(feature = "std_core")]
+impl std::error::Error for IOError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IOError::Device(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std_core")]
+impl std::error::Error for DeviceError {}
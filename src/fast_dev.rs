@@ -1,7 +1,6 @@
 use crate::alloc::boxed::Box;
 use alloc::string::String;
-use core::ops::DerefMut;
-use rtt_rs::Deref;
+use core::ops::{Deref, DerefMut};
 
 pub struct FastDev<T> {
     pub(crate) dev: Box<T>,
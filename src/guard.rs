@@ -1,47 +1,399 @@
+use crate::alloc::borrow::Cow;
+use crate::alloc::boxed::Box;
 use crate::alloc::sync::Arc;
 use crate::api::OpenType;
-use crate::async_rw::{AsyncReadFuture, AsyncWriteFuture};
+use crate::async_rw::{
+    AsyncReadFuture, AsyncWriteFuture, ByteStream, ChunkStream, DataRef, GuardRef, ReadExactFuture,
+    ReadUntilFuture, Waiter,
+};
+use crate::control::{take_reply, ControlCmd};
 use crate::data::{StdData, ToMakeStdData};
 use crate::driver::{Driver, DriverAsyncHelper, DriverOps};
 use crate::error::IOError;
+use crate::os::wait_until;
+use crate::trace::{self, TraceData, TraceOp};
 use crate::Mutex;
+use core::ops::Deref;
 use core::task::Waker;
 
+// 由 DevOpen::open 得到时借用设备
+// 由 DevOpen::open_owned 得到时持有设备的 Arc
 pub struct DriverGuard<'a> {
-    pub(crate) raw: &'a Arc<Mutex<Driver>>,
+    pub(crate) raw: Cow<'a, Arc<Mutex<Driver>>>,
     pub(crate) o_type: OpenType,
 }
 
+// 持有设备所有权的 guard，可以保存在结构体中或者移动到其他任务中
+// 克隆只增加引用计数，所有克隆都释放后才关闭设备
+#[derive(Clone)]
+pub struct OwnedDriverGuard(pub(crate) Arc<DriverGuard<'static>>);
+
+impl Deref for OwnedDriverGuard {
+    type Target = DriverGuard<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl OwnedDriverGuard {
+    // 存在其他克隆时无法升级
+    pub fn try_upgrade(&mut self) -> Result<(), IOError> {
+        match Arc::get_mut(&mut self.0) {
+            Some(g) => g.try_upgrade(),
+            None => Err(IOError::OpenError),
+        }
+    }
+
+    // 存在其他克隆时不做任何操作，返回 false
+    pub fn downgrade(&mut self) -> bool {
+        match Arc::get_mut(&mut self.0) {
+            Some(g) => {
+                g.downgrade();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 得到的 future 持有 guard 的克隆，是 'static 的
+    pub fn async_read_owned(
+        &self,
+        address: usize,
+        len: u32,
+    ) -> Result<AsyncReadFuture<'static, 'static>, IOError> {
+        self.check_async()?;
+        Ok(AsyncReadFuture {
+            0: GuardRef::Owned(self.clone()),
+            1: address,
+            2: len,
+            3: Waiter::read(),
+        })
+    }
+
+    pub fn async_write_owned<D: ToMakeStdData + Send + Sync + 'static>(
+        &self,
+        address: usize,
+        data: D,
+    ) -> Result<AsyncWriteFuture<'static, 'static, 'static>, IOError> {
+        self.check_async()?;
+        Ok(AsyncWriteFuture {
+            0: GuardRef::Owned(self.clone()),
+            1: address,
+            2: DataRef::Owned(Box::new(data)),
+            3: Waiter::write(),
+        })
+    }
+
+    pub fn read_exact_owned(
+        &self,
+        address: usize,
+        len: usize,
+    ) -> Result<ReadExactFuture<'static, 'static>, IOError> {
+        self.check_async()?;
+        Ok(ReadExactFuture::new(
+            GuardRef::Owned(self.clone()),
+            address,
+            len,
+        ))
+    }
+
+    pub fn read_until_owned(
+        &self,
+        address: usize,
+        delim: u8,
+        max: usize,
+    ) -> Result<ReadUntilFuture<'static, 'static>, IOError> {
+        if max == 0 {
+            return Err(IOError::DataError);
+        }
+        self.check_async()?;
+        Ok(ReadUntilFuture::new(
+            GuardRef::Owned(self.clone()),
+            address,
+            delim,
+            max,
+        ))
+    }
+
+    pub fn bytes_owned(&self, address: usize) -> Result<ByteStream<'static, 'static>, IOError> {
+        self.check_async()?;
+        Ok(ByteStream::new(GuardRef::Owned(self.clone()), address))
+    }
+
+    pub fn chunks_owned(
+        &self,
+        address: usize,
+        max: usize,
+    ) -> Result<ChunkStream<'static, 'static>, IOError> {
+        if max == 0 {
+            return Err(IOError::DataError);
+        }
+        self.check_async()?;
+        Ok(ChunkStream::new(
+            GuardRef::Owned(self.clone()),
+            address,
+            max,
+        ))
+    }
+}
+
+impl DriverGuard<'_> {
+    fn check_async(&self) -> Result<(), IOError> {
+        let dev = self.raw.lock().unwrap();
+        if !dev.open_able {
+            Err(IOError::ReadError)
+        } else {
+            Ok(())
+        }
+    }
+
+    // 共享打开升级为独占打开，设备不会被关闭
+    // 只有当前 guard 是唯一的打开者时才能成功
+    pub fn try_upgrade(&mut self) -> Result<(), IOError> {
+        if self.o_type == OpenType::Only {
+            return Ok(());
+        }
+        let mut dev = self.raw.lock().unwrap();
+        if dev.removed {
+            return Err(IOError::RemovedError);
+        }
+        if dev.open_num != 1 {
+            return Err(IOError::OpenError);
+        }
+        // 与 raw_open 中独占打开的状态保持一致
+        dev.open_num = 0;
+        dev.open_able = false;
+        self.o_type = OpenType::Only;
+        Ok(())
+    }
+
+    // 独占打开降级为共享打开，降级后其他人可以共享的打开设备
+    pub fn downgrade(&mut self) {
+        if self.o_type != OpenType::Only {
+            return;
+        }
+        let mut dev = self.raw.lock().unwrap();
+        if dev.removed {
+            return;
+        }
+        // 与 raw_open 中第一次共享打开的状态保持一致
+        dev.open_able = true;
+        dev.open_num = 1;
+        self.o_type = OpenType::Master;
+        // 等待共享打开的人现在可以打开了
+        dev.wake_open_waiter();
+    }
+
+    // 发送类型化的控制命令并取得应答
+    pub fn command<C: ControlCmd>(&self, cmd: C) -> Result<C::Reply, IOError> {
+        let mut dev = self.raw.lock().unwrap();
+        let ret = dev.ops.command(Box::new(cmd)).and_then(take_reply::<C>);
+        dev.stats.record(&ret);
+        trace::emit(
+            &dev,
+            TraceOp::Command,
+            0,
+            TraceData::None,
+            trace::result(&ret),
+        );
+        ret
+    }
+}
+
+// 多字节的异步读，设备需要以中断读或异步读的方式打开
+impl<'c> DriverGuard<'c> {
+    // 读满 len 个字节
+    pub fn read_exact(
+        &self,
+        address: usize,
+        len: usize,
+    ) -> Result<ReadExactFuture<'_, 'c>, IOError> {
+        self.check_async()?;
+        Ok(ReadExactFuture::new(GuardRef::Borrowed(self), address, len))
+    }
+
+    // 读到 delim 为止，结果包含 delim，最多读 max 个字节
+    pub fn read_until(
+        &self,
+        address: usize,
+        delim: u8,
+        max: usize,
+    ) -> Result<ReadUntilFuture<'_, 'c>, IOError> {
+        if max == 0 {
+            return Err(IOError::DataError);
+        }
+        self.check_async()?;
+        Ok(ReadUntilFuture::new(
+            GuardRef::Borrowed(self),
+            address,
+            delim,
+            max,
+        ))
+    }
+
+    // 逐字节的数据流
+    pub fn bytes(&self, address: usize) -> Result<ByteStream<'_, 'c>, IOError> {
+        self.check_async()?;
+        Ok(ByteStream::new(GuardRef::Borrowed(self), address))
+    }
+
+    // 每次返回已经到达的数据，最多 max 个字节
+    pub fn chunks(&self, address: usize, max: usize) -> Result<ChunkStream<'_, 'c>, IOError> {
+        if max == 0 {
+            return Err(IOError::DataError);
+        }
+        self.check_async()?;
+        Ok(ChunkStream::new(GuardRef::Borrowed(self), address, max))
+    }
+}
+
 impl<'c> DriverOps for DriverGuard<'c> {
     fn control(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
-        let dev = self.raw.lock().unwrap();
-        dev.ops.control(data)
+        let mut dev = self.raw.lock().unwrap();
+        let ret = dev.ops.control(data);
+        dev.stats.record(&ret);
+        if trace::enabled(&dev) {
+            let d = data.make_data();
+            trace::emit(
+                &dev,
+                TraceOp::Control,
+                0,
+                TraceData::Data(&d),
+                trace::result(&ret),
+            );
+        }
+        ret
     }
 
     fn read(&self, address: usize, len: u32) -> Result<StdData, IOError> {
-        let dev = self.raw.lock().unwrap();
-        if dev.ops.is_block_dev() {
+        let mut dev = self.raw.lock().unwrap();
+        let ret = if dev.ops.is_block_dev() {
             dev.ops.b_read(address, len as usize)
         } else {
             dev.ops.read(len)
+        };
+        match &ret {
+            Ok(d) => {
+                dev.stats.read_bytes += d.len() as u64;
+                trace::emit(&dev, TraceOp::Read, d.len(), TraceData::Data(d), Ok(()));
+            }
+            Err(e) => {
+                dev.stats.record_error(e);
+                trace::emit(&dev, TraceOp::Read, 0, TraceData::None, Err(e));
+            }
         }
+        ret
     }
 
     fn write(&self, address: usize, data: &dyn ToMakeStdData) -> Result<(), IOError> {
-        let dev = self.raw.lock().unwrap();
-        if dev.ops.is_block_dev() {
+        let mut dev = self.raw.lock().unwrap();
+        let ret = if dev.ops.is_block_dev() {
             dev.ops.b_write(address, data)
         } else {
             dev.ops.write(data)
+        };
+        match &ret {
+            Ok(_) => dev.stats.write_bytes += data.data_len() as u64,
+            Err(IOError::WriteFull(rest)) => {
+                // 只有部分数据被写入
+                let n = data.data_len().saturating_sub(rest.len());
+                dev.stats.write_bytes += n as u64;
+                dev.stats.record(&ret);
+            }
+            Err(e) => dev.stats.record_error(e),
         }
+        if trace::enabled(&dev) {
+            let d = data.make_data();
+            // 写缓冲满时只记录实际写入的部分
+            let n = match &ret {
+                Err(IOError::WriteFull(rest)) => d.len().saturating_sub(rest.len()),
+                _ => d.len(),
+            };
+            let td = match &d {
+                StdData::Bytes(b) if n < b.len() => TraceData::Buf(&b[..n]),
+                _ => TraceData::Data(&d),
+            };
+            trace::emit(&dev, TraceOp::Write, n, td, trace::result(&ret));
+        }
+        ret
     }
 
-    fn sync(&self) {
-        todo!()
+    fn read_into(&self, address: usize, buf: &mut [u8]) -> Result<usize, IOError> {
+        let mut dev = self.raw.lock().unwrap();
+        let ret = if dev.ops.is_block_dev() {
+            dev.ops.b_read_into(address, buf)
+        } else {
+            dev.ops.read_into(buf)
+        };
+        match &ret {
+            Ok(n) => {
+                dev.stats.read_bytes += *n as u64;
+                trace::emit(&dev, TraceOp::Read, *n, TraceData::Buf(&buf[..*n]), Ok(()));
+            }
+            Err(e) => {
+                dev.stats.record_error(e);
+                trace::emit(&dev, TraceOp::Read, 0, TraceData::None, Err(e));
+            }
+        }
+        ret
     }
 
-    fn chf(&self, _data: StdData) -> Result<StdData, IOError> {
-        todo!()
+    fn write_from(&self, address: usize, buf: &[u8]) -> Result<usize, IOError> {
+        let mut dev = self.raw.lock().unwrap();
+        let ret = if dev.ops.is_block_dev() {
+            dev.ops.b_write_from(address, buf)
+        } else {
+            dev.ops.write_from(buf)
+        };
+        match &ret {
+            Ok(n) => {
+                dev.stats.write_bytes += *n as u64;
+                trace::emit(&dev, TraceOp::Write, *n, TraceData::Buf(&buf[..*n]), Ok(()));
+            }
+            Err(e) => {
+                dev.stats.record_error(e);
+                trace::emit(&dev, TraceOp::Write, 0, TraceData::Buf(buf), Err(e));
+            }
+        }
+        ret
+    }
+
+    // 每次检查时才持有设备锁，等待期间其他句柄仍然可以使用设备
+    fn sync(&self, timeout: Option<u32>) -> Result<(), IOError> {
+        let mut last = None;
+        let ret = wait_until(
+            || match self.raw.lock().unwrap().ops.sync(Some(0)) {
+                Ok(()) => Ok(true),
+                Err(e) if e.is_timeout() => {
+                    last = Some(e);
+                    Ok(false)
+                }
+                Err(e) => Err(e),
+            },
+            timeout,
+        );
+        // 超时时返回驱动给出的超时错误
+        let ret = match ret {
+            Err(IOError::Timeout) => Err(last.unwrap_or(IOError::Timeout)),
+            r => r,
+        };
+        let mut dev = self.raw.lock().unwrap();
+        dev.stats.record(&ret);
+        trace::emit(&dev, TraceOp::Sync, 0, TraceData::None, trace::result(&ret));
+        ret
+    }
+
+    fn chf(&self, data: StdData) -> Result<StdData, IOError> {
+        if let StdData::OpenFlag(_) = data {
+            let mut dev = self.raw.lock().unwrap();
+            let ret = dev.ops.chf(data);
+            dev.stats.record(&ret);
+            trace::emit(&dev, TraceOp::Chf, 0, TraceData::None, trace::result(&ret));
+            ret
+        } else {
+            Err(IOError::DataError)
+        }
     }
 
     fn is_only(&self) -> bool {
@@ -66,33 +418,27 @@ impl<'c> DriverOps for DriverGuard<'c> {
     }
 
     fn async_read(&self, address: usize, len: u32) -> Result<AsyncReadFuture, IOError> {
-        let dev = self.raw.lock().unwrap();
-        if !dev.open_able {
-            Err(IOError::ReadError)
-        } else {
-            Ok(AsyncReadFuture {
-                0: self,
-                1: address,
-                2: len,
-            })
-        }
+        self.check_async()?;
+        Ok(AsyncReadFuture {
+            0: GuardRef::Borrowed(self),
+            1: address,
+            2: len,
+            3: Waiter::read(),
+        })
     }
 
     fn async_write<'a, 'b>(
         &'a self,
         address: usize,
-        data: &'b dyn ToMakeStdData,
+        data: &'b (dyn ToMakeStdData + Sync),
     ) -> Result<AsyncWriteFuture<'a, 'b, 'c>, IOError> {
-        let dev = self.raw.lock().unwrap();
-        if !dev.open_able {
-            Err(IOError::ReadError)
-        } else {
-            Ok(AsyncWriteFuture {
-                0: self,
-                1: address,
-                2: data,
-            })
-        }
+        self.check_async()?;
+        Ok(AsyncWriteFuture {
+            0: GuardRef::Borrowed(self),
+            1: address,
+            2: DataRef::Borrowed(data),
+            3: Waiter::write(),
+        })
     }
 
     fn register_rx_indicate(&self, func: fn()) {
@@ -102,33 +448,78 @@ impl<'c> DriverOps for DriverGuard<'c> {
 }
 
 impl DriverAsyncHelper for Arc<Mutex<Driver>> {
-    fn register_read_callback(&self, func: fn(Waker), cx: Waker) -> Result<(), IOError> {
+    fn register_read_callback(
+        &self,
+        id: usize,
+        need: usize,
+        func: fn(Waker),
+        cx: &Waker,
+    ) -> Result<(), IOError> {
+        let dev = self.lock().unwrap();
+        dev.ops.register_read_callback(id, need, func, cx)
+    }
+
+    fn unregister_read_callback(&self, id: usize) {
+        let dev = self.lock().unwrap();
+        dev.ops.unregister_read_callback(id)
+    }
+
+    fn register_write_callback(
+        &self,
+        id: usize,
+        func: fn(Waker),
+        cx: &Waker,
+    ) -> Result<(), IOError> {
         let dev = self.lock().unwrap();
-        dev.ops.register_read_callback(func, cx)
+        dev.ops.register_write_callback(id, func, cx)
     }
 
-    fn register_write_callback(&self, func: fn(Waker), cx: Waker) -> Result<(), IOError> {
+    fn unregister_write_callback(&self, id: usize) {
         let dev = self.lock().unwrap();
-        dev.ops.register_write_callback(func, cx)
+        dev.ops.unregister_write_callback(id)
     }
 }
 
 impl Drop for DriverGuard<'_> {
     fn drop(&mut self) {
         let mut inner_dev = self.raw.lock().unwrap();
-        if inner_dev.open_able == false {
+        if inner_dev.removed {
+            // 设备已经被强制注销，驱动已经关闭
+            return;
+        }
+        inner_dev.stats.closes = inner_dev.stats.closes.wrapping_add(1);
+        let ret = if inner_dev.open_able == false {
             // 独占的打开了设备
             inner_dev.open_able = true;
-            inner_dev.ops.close().unwrap();
+            inner_dev.ops.close()
         } else if inner_dev.open_num > 1 {
             // 非独占的打开了设备
             inner_dev.open_num -= 1;
+            Ok(())
         } else if inner_dev.open_num == 1 {
             inner_dev.open_num = 0;
-            inner_dev.ops.close().unwrap();
+            inner_dev.ops.close()
         } else {
-            panic!()
+            // 打开计数已经不一致，不再关闭驱动
+            Err(IOError::CloseError)
+        };
+        trace::emit(
+            &inner_dev,
+            TraceOp::Close,
+            0,
+            TraceData::None,
+            trace::result(&ret),
+        );
+        // drop 中无法返回错误，关闭失败只记录在统计中
+        inner_dev.stats.record(&ret);
+        if inner_dev.open_able && inner_dev.open_num == 0 {
+            // 最后一个句柄已经关闭，释放父设备
+            // 设备已经被延迟注销时，同时不再作为父设备的子设备
+            if inner_dev.parent_guard.take().is_some() && inner_dev.unregistered {
+                inner_dev.detach_parent();
+            }
         }
+        inner_dev.wake_open_waiter();
     }
 }
 
@@ -138,3 +529,119 @@ macro_rules! write {
         $($dev.write(0, &($data)).unwrap();)*
     }
 }
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use crate::alloc::sync::Arc;
+    use crate::api::{find, DevOpen};
+    use crate::bsp::host_sim::testing::count_dev;
+    use crate::device::{register_device, DeviceOps};
+    use crate::driver::DriverOps;
+    use crate::{IOError, OpenFlag, ToMakeStdData};
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    // control 之后 sync 才完成
+    struct SyncDev(Arc<AtomicBool>);
+
+    impl DeviceOps for SyncDev {
+        fn open(&self, _flag: &OpenFlag) -> Result<(), IOError> {
+            Ok(())
+        }
+        fn close(&self) -> Result<(), IOError> {
+            Ok(())
+        }
+        fn control(&self, _data: &dyn ToMakeStdData) -> Result<(), IOError> {
+            self.0.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+        fn sync(&self, _timeout: Option<u32>) -> Result<(), IOError> {
+            if self.0.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err(IOError::Timeout)
+            }
+        }
+    }
+
+    #[test]
+    fn sync_timeout() {
+        register_device(SyncDev(Arc::new(AtomicBool::new(false))), "test/guard/tmo").unwrap();
+        let dev = find("test/guard/tmo").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        assert!(matches!(g.sync(Some(5)), Err(IOError::Timeout)));
+    }
+
+    #[test]
+    fn sync_releases_device() {
+        register_device(SyncDev(Arc::new(AtomicBool::new(false))), "test/guard/sync").unwrap();
+        let t = thread::spawn(|| {
+            let dev = find("test/guard/sync").unwrap();
+            let g = dev.open(&OpenFlag::zero()).unwrap();
+            g.sync(Some(2000)).is_ok()
+        });
+        thread::sleep(Duration::from_millis(20));
+        // 等待 sync 期间其他句柄可以使用设备
+        let dev = find("test/guard/sync").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        g.control(&0u32).unwrap();
+        assert!(t.join().unwrap());
+    }
+
+    #[test]
+    fn upgrade_downgrade() {
+        count_dev("test/guard/upgrade");
+        let dev = find("test/guard/upgrade").unwrap();
+        let mut a = dev.open(&OpenFlag::zero()).unwrap();
+        let b = dev.open(&OpenFlag::zero()).unwrap();
+        // 还有其他共享者时不能升级
+        assert!(matches!(a.try_upgrade(), Err(IOError::OpenError)));
+        drop(b);
+        a.try_upgrade().unwrap();
+        assert!(a.is_only());
+        assert!(matches!(
+            dev.open(&OpenFlag::zero()),
+            Err(IOError::OpenError)
+        ));
+        a.downgrade();
+        assert!(a.is_master());
+        let b = dev.open(&OpenFlag::zero()).unwrap();
+        assert!(b.is_user());
+        drop(b);
+        drop(a);
+        // 升级降级都不会关闭设备
+        assert_eq!(dev.lock().unwrap().open_type(), None);
+    }
+
+    #[test]
+    fn owned_upgrade_with_clones() {
+        let (_, closes) = count_dev("test/guard/owned");
+        let dev = find("test/guard/owned").unwrap();
+        let mut a = dev.open_owned(&OpenFlag::zero()).unwrap();
+        let b = a.clone();
+        assert!(a.try_upgrade().is_err());
+        assert!(!a.downgrade());
+        drop(b);
+        a.try_upgrade().unwrap();
+        assert!(a.is_only());
+        assert!(a.downgrade());
+        drop(a);
+        assert_eq!(closes.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn downgrade_wakes_waiter() {
+        count_dev("test/guard/down");
+        let dev = find("test/guard/down").unwrap();
+        let mut g = dev.open(OpenFlag::zero().set_only(true)).unwrap();
+        let waiter = thread::spawn(|| {
+            let dev = find("test/guard/down").unwrap();
+            let ret = dev.open_wait(&OpenFlag::zero(), Some(2000));
+            ret.map(|g| g.is_user()).unwrap_or(false)
+        });
+        thread::sleep(Duration::from_millis(20));
+        g.downgrade();
+        assert!(waiter.join().unwrap());
+    }
+}
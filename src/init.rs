@@ -0,0 +1,185 @@
+//! 声明式的设备注册
+//! 板卡和驱动用 device_table! 声明设备表，启动时由 register_tables 统一注册
+//! 设备按 order 从小到大注册，order 相同时按声明的顺序，总线应该先于挂在上面的设备
+//! 每个设备的注册结果单独返回，一个设备失败不会影响其他设备
+//! 设备注册成功后设置别名失败时，设备仍然可用，失败的别名单独记录在 alias_errors 中
+//!
+//! 设备表没有通过链接段在链接时自动收集：RT-Thread 的链接脚本不会保留未知的段，
+//! 开启 gc-sections 时设备表会被直接丢掉并且没有任何报错，
+//! 所以每个板卡在 dev_init! 的初始化函数中把自己的设备表交给 register_tables
+//!
+//! ```ignore
+//! device_table! {
+//!     pub static DEVICES = [
+//!         { name: "led0", order: 10, dev: Led::new(led::BspLed::new()) },
+//!         #[cfg(feature = "art_pi")]
+//!         { name: "uart1", order: 20, dev: Serial::new(uart::BspUart::new(1)), alias: ["console"] },
+//!         { name: "bus/i2c1/bmp280", order: 30, parent: "bus/i2c1", dev: I2CDev::new(Bmp280::new()) },
+//!     ];
+//! }
+//! ```
+
+use crate::alloc::vec::Vec;
+use crate::api::set_alias;
+use crate::device::{register_child_device, register_device, DeviceOps};
+use crate::error::IOError;
+
+// 设备表中的一项，由 device_table! 生成
+pub struct DeviceEntry {
+    pub name: &'static str,
+    // 注册顺序，小的先注册
+    pub order: u32,
+    // 父设备，必须在该设备之前注册
+    pub parent: Option<&'static str>,
+    // 注册成功后设置的别名
+    pub aliases: &'static [&'static str],
+    // 构造设备并注册，设备在注册时才被构造
+    pub register: fn(&DeviceEntry) -> Result<(), IOError>,
+}
+
+// 一个设备的注册结果
+#[derive(Debug)]
+pub struct RegisterReport {
+    pub name: &'static str,
+    // 设备本身的注册结果
+    pub result: Result<(), IOError>,
+    // 设置失败的别名
+    pub alias_errors: Vec<(&'static str, IOError)>,
+}
+
+impl RegisterReport {
+    pub fn new(name: &'static str, result: Result<(), IOError>) -> RegisterReport {
+        RegisterReport {
+            name,
+            result,
+            alias_errors: Vec::new(),
+        }
+    }
+
+    // 设备注册成功后设置别名，失败的别名记录在 alias_errors 中
+    pub(crate) fn set_aliases(&mut self, aliases: &[&'static str]) {
+        if self.result.is_err() {
+            return;
+        }
+        for alias in aliases {
+            if let Err(e) = set_alias(alias, self.name) {
+                self.alias_errors.push((alias, e));
+            }
+        }
+    }
+}
+
+// 由 device_table! 生成的代码调用，别名由 register_tables 设置
+pub fn register_entry<T: DeviceOps + Send + 'static>(
+    dev: T,
+    entry: &DeviceEntry,
+) -> Result<(), IOError> {
+    match entry.parent {
+        Some(parent) => register_child_device(dev, entry.name, parent),
+        None => register_device(dev, entry.name),
+    }
+}
+
+// 注册若干张设备表，返回每个设备的注册结果
+pub fn register_tables(tables: &[&[DeviceEntry]]) -> Vec<RegisterReport> {
+    let mut entries: Vec<&DeviceEntry> = tables.iter().flat_map(|t| t.iter()).collect();
+    // 稳定排序，order 相同时保持声明的顺序
+    entries.sort_by_key(|e| e.order);
+    entries
+        .into_iter()
+        .map(|e| {
+            let mut r = RegisterReport::new(e.name, (e.register)(e));
+            r.set_aliases(e.aliases);
+            r
+        })
+        .collect()
+}
+
+// 声明一张设备表，每一项可以用 #[cfg(...)] 按 feature 选择
+// parent 和 alias 可以省略
+#[macro_export]
+macro_rules! device_table {
+    (
+        $vis:vis static $table:ident = [
+            $(
+                $(#[$m:meta])*
+                {
+                    name: $name:expr,
+                    order: $order:expr,
+                    $(parent: $parent:expr,)?
+                    dev: $dev:expr
+                    $(, alias: [$($alias:expr),* $(,)?])?
+                    $(,)?
+                }
+            ),* $(,)?
+        ];
+    ) => {
+        $vis static $table: &[$crate::init::DeviceEntry] = &[
+            $(
+                $(#[$m])*
+                $crate::init::DeviceEntry {
+                    name: $name,
+                    order: $order,
+                    parent: $crate::device_table!(@parent $($parent)?),
+                    aliases: &[$($($alias),*)?],
+                    register: |e| $crate::init::register_entry($dev, e),
+                },
+            )*
+        ];
+    };
+    (@parent) => {
+        None
+    };
+    (@parent $parent:expr) => {
+        Some($parent)
+    };
+}
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use super::*;
+    use crate::api::{find, resolve};
+    use crate::bsp::host_sim::testing::count_dev;
+    use crate::{OpenFlag, ToMakeStdData};
+
+    struct D;
+
+    impl DeviceOps for D {
+        fn open(&self, _flag: &OpenFlag) -> Result<(), IOError> {
+            Ok(())
+        }
+        fn close(&self) -> Result<(), IOError> {
+            Ok(())
+        }
+        fn control(&self, _data: &dyn ToMakeStdData) -> Result<(), IOError> {
+            Ok(())
+        }
+    }
+
+    device_table! {
+        static ALIASES = [
+            { name: "test/init/dev", order: 10, dev: D, alias: ["test/init/taken", "test/init/alias"] },
+            { name: "test/init/dup", order: 20, dev: D, alias: ["test/init/dup_alias"] },
+            { name: "test/init/dup", order: 30, dev: D, alias: ["test/init/never"] },
+        ];
+    }
+
+    #[test]
+    fn alias_errors_reported_separately() {
+        count_dev("test/init/taken");
+        let r = register_tables(&[ALIASES]);
+        // 别名与已有设备同名，设备本身注册成功
+        assert!(r[0].result.is_ok());
+        assert_eq!(r[0].alias_errors.len(), 1);
+        assert_eq!(r[0].alias_errors[0].0, "test/init/taken");
+        assert!(matches!(r[0].alias_errors[0].1, IOError::RegisterError));
+        assert!(find("test/init/dev").is_ok());
+        assert_eq!(resolve("test/init/alias"), "test/init/dev");
+
+        assert!(r[1].result.is_ok() && r[1].alias_errors.is_empty());
+        // 设备注册失败时不设置别名
+        assert!(matches!(r[2].result, Err(IOError::RegisterError)));
+        assert!(r[2].alias_errors.is_empty());
+        assert_eq!(resolve("test/init/never"), "test/init/never");
+    }
+}
@@ -1,10 +1,7 @@
-#![no_std]
+#![cfg_attr(not(feature = "std_core"), no_std)]
 
 use lazy_static::lazy_static;
-#[cfg(feature = "r_core")]
-pub(crate) use mlib::Mutex;
-#[cfg(feature = "c_core")]
-pub(crate) use rtt_rs::mutex::Mutex;
+pub(crate) use os::Mutex;
 
 use crate::alloc::boxed::Box;
 use crate::alloc::collections::BTreeMap;
@@ -13,22 +10,36 @@ use crate::alloc::sync::Arc;
 use crate::driver::Driver;
 use core::any::Any;
 
+pub mod adapter;
 pub mod api;
 pub mod async_rw;
+pub mod board;
 mod bsp;
+#[cfg(feature = "c_core")]
 pub mod c_api;
+pub mod control;
 pub mod data;
 pub mod device;
 pub mod driver;
+pub mod ehal;
 pub mod error;
 mod fast_dev;
 pub mod guard;
+pub mod init;
+pub mod os;
+pub mod pm;
+pub mod stats;
+pub mod trace;
+pub mod typed;
 
 /* 导出的函数 */
+#[cfg(feature = "host_sim")]
+pub use bsp::host_sim;
 pub use data::*;
 pub use driver::DriverOps as DevOPS;
 pub use error::*;
 pub(crate) extern crate alloc;
+#[cfg(feature = "c_core")]
 pub(crate) extern crate rtt_rs;
 
 lazy_static! {
@@ -36,6 +47,12 @@ lazy_static! {
         Mutex::new(BTreeMap::new()).unwrap();
 }
 
+lazy_static! {
+    // 设备别名，如 console -> uart1
+    pub static ref ALIAS_LIST: Mutex<BTreeMap<String, String>> =
+        Mutex::new(BTreeMap::new()).unwrap();
+}
+
 lazy_static! {
     pub static ref FAST_DEVICE_LIST: Mutex<BTreeMap<String, Box<dyn Any + Send>>> =
         Mutex::new(BTreeMap::new()).unwrap();
@@ -0,0 +1,118 @@
+//! std 后端，让框架可以在 Linux 等主机上编译运行
+//! 临界区使用一把全局锁模拟，同一线程内允许嵌套
+
+use super::{OsApi, OsSemaphore};
+use core::cell::Cell;
+use core::task::Waker;
+use core::time::Duration;
+use std::sync::{Condvar, Mutex as StdMutex, MutexGuard, OnceLock};
+use std::time::Instant;
+
+// 与 rtt_rs::mutex::Mutex 保持相同的接口
+// 持有锁的线程 panic 后不会让锁失效，方便测试继续运行
+pub struct Mutex<T>(StdMutex<T>);
+
+impl<T> Mutex<T> {
+    pub fn new(val: T) -> Result<Mutex<T>, ()> {
+        Ok(Mutex(StdMutex::new(val)))
+    }
+
+    pub fn lock(&self) -> Result<MutexGuard<'_, T>, ()> {
+        Ok(self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+pub struct Semaphore {
+    value: StdMutex<u32>,
+    cond: Condvar,
+}
+
+impl Semaphore {
+    pub fn new(value: u32) -> Semaphore {
+        Semaphore {
+            value: StdMutex::new(value),
+            cond: Condvar::new(),
+        }
+    }
+}
+
+impl OsSemaphore for Semaphore {
+    fn take_wait_forever(&self) -> Result<(), ()> {
+        let value = self.value.lock().map_err(|_| ())?;
+        let mut value = self.cond.wait_while(value, |v| *v == 0).map_err(|_| ())?;
+        *value -= 1;
+        Ok(())
+    }
+
+    fn take_wait(&self, ms: u32) -> Result<(), ()> {
+        let value = self.value.lock().map_err(|_| ())?;
+        let (mut value, _) = self
+            .cond
+            .wait_timeout_while(value, Duration::from_millis(ms as _), |v| *v == 0)
+            .map_err(|_| ())?;
+        if *value == 0 {
+            return Err(());
+        }
+        *value -= 1;
+        Ok(())
+    }
+
+    fn release(&self) -> Result<(), ()> {
+        let mut value = self.value.lock().map_err(|_| ())?;
+        *value += 1;
+        self.cond.notify_one();
+        Ok(())
+    }
+}
+
+static IRQ_LOCK: StdMutex<()> = StdMutex::new(());
+
+std::thread_local! {
+    static IRQ_DEPTH: Cell<u32> = Cell::new(0);
+}
+
+pub struct Os;
+
+impl OsApi for Os {
+    type Semaphore = Semaphore;
+
+    fn yield_now() {
+        std::thread::yield_now();
+    }
+
+    fn delay(ms: u32) {
+        std::thread::sleep(Duration::from_millis(ms as _));
+    }
+
+    fn no_irq<T, F: FnOnce() -> T>(f: F) -> T {
+        let nested = IRQ_DEPTH.with(|d| {
+            let n = d.get();
+            d.set(n + 1);
+            n != 0
+        });
+        let guard = if nested {
+            None
+        } else {
+            Some(IRQ_LOCK.lock().unwrap_or_else(|e| e.into_inner()))
+        };
+        let ret = f();
+        drop(guard);
+        IRQ_DEPTH.with(|d| d.set(d.get() - 1));
+        ret
+    }
+
+    fn device_wake(w: Waker) {
+        w.wake()
+    }
+
+    fn tick_ms() -> u32 {
+        START.get_or_init(Instant::now).elapsed().as_millis() as u32
+    }
+
+    fn semaphore() -> Result<Semaphore, ()> {
+        Ok(Semaphore::new(0))
+    }
+}
+
+// 第一次取时间的时刻作为系统启动的时刻
+static START: OnceLock<Instant> = OnceLock::new();
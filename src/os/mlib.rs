@@ -0,0 +1,56 @@
+//! mlib 后端，对接 rust 编写的 rt-thread 内核
+
+use super::{OsApi, OsSemaphore};
+use core::task::Waker;
+
+pub use mlib::Mutex;
+pub use mlib::Semaphore;
+
+// mlib 没有导出 tick 频率，需要与内核配置的 tick 频率一致
+// 延时和超时直接把 ms 当作 tick 传给内核，只在 1kHz 时成立
+const RT_TICK_PER_SECOND: u64 = 1000;
+const _: () = assert!(RT_TICK_PER_SECOND == 1000, "mlib 后端假定 tick 频率为 1kHz");
+
+pub struct Os;
+
+impl OsApi for Os {
+    type Semaphore = Semaphore;
+
+    fn yield_now() {
+        mlib::Thread::_yield();
+    }
+
+    fn delay(ms: u32) {
+        mlib::Thread::delay(ms as _);
+    }
+
+    fn no_irq<T, F: FnOnce() -> T>(f: F) -> T {
+        mlib::no_irq(f)
+    }
+
+    fn device_wake(w: Waker) {
+        mlib::device_wake(w)
+    }
+
+    fn tick_ms() -> u32 {
+        (mlib::tick_get() as u64 * 1000 / RT_TICK_PER_SECOND) as u32
+    }
+
+    fn semaphore() -> Result<Semaphore, ()> {
+        Semaphore::new("drv_wait").map_err(|_| ())
+    }
+}
+
+impl OsSemaphore for Semaphore {
+    fn take_wait_forever(&self) -> Result<(), ()> {
+        Semaphore::take_wait_forever(self).map_err(|_| ())
+    }
+
+    fn take_wait(&self, ms: u32) -> Result<(), ()> {
+        Semaphore::take(self, ms as _).map_err(|_| ())
+    }
+
+    fn release(&self) -> Result<(), ()> {
+        Semaphore::release(self).map_err(|_| ())
+    }
+}
@@ -0,0 +1,208 @@
+//! 操作系统抽象层
+//! 框架里所有和内核相关的操作都经过这里：
+//! 互斥锁、信号量、线程让出、延时、临界区、异步唤醒、系统时间
+//! 具体的后端由 feature 选择：
+//! c_core -> rtt_rs，r_core -> mlib，std_core -> 主机上的 std
+//! Mutex 是泛型类型，无法放进 trait 里，各个后端直接导出同名类型，
+//! 并保持 `Mutex::new(v).unwrap()`、`lock().unwrap()` 的用法一致
+
+use crate::error::IOError;
+use core::task::Waker;
+
+// 同一时间只能选择一个后端
+#[cfg(any(
+    all(feature = "c_core", feature = "r_core"),
+    all(feature = "c_core", feature = "std_core"),
+    all(feature = "r_core", feature = "std_core"),
+))]
+compile_error!(
+    "c_core、r_core、std_core 只能打开一个，在主机上测试时使用 --no-default-features --features host_sim"
+);
+#[cfg(not(any(feature = "c_core", feature = "r_core", feature = "std_core")))]
+compile_error!("需要打开 c_core、r_core、std_core 中的一个");
+
+#[cfg(feature = "std_core")]
+mod host;
+#[cfg(feature = "r_core")]
+mod mlib;
+#[cfg(feature = "c_core")]
+mod rtt;
+
+#[cfg(feature = "r_core")]
+pub use self::mlib::{Mutex, Os};
+#[cfg(feature = "std_core")]
+pub use host::{Mutex, Os};
+#[cfg(feature = "c_core")]
+pub use rtt::{Mutex, Os};
+
+pub type Semaphore = <Os as OsApi>::Semaphore;
+
+pub trait OsApi {
+    type Semaphore: OsSemaphore + Send + Sync;
+
+    // 让出当前线程
+    fn yield_now();
+    // 线程延时，单位 ms
+    fn delay(ms: u32);
+    // 在关中断的环境下执行，用于保护中断与线程共享的数据
+    fn no_irq<T, F: FnOnce() -> T>(f: F) -> T;
+    // 异步运行时提供的唤醒函数，由设备在中断中调用
+    fn device_wake(w: Waker);
+    // 系统启动以来的时间，单位 ms，溢出后回绕
+    fn tick_ms() -> u32;
+    // 创建初始值为 0 的信号量
+    fn semaphore() -> Result<Self::Semaphore, ()>;
+}
+
+pub trait OsSemaphore {
+    fn take_wait_forever(&self) -> Result<(), ()>;
+    // 等待指定的时间，超时返回错误
+    fn take_wait(&self, ms: u32) -> Result<(), ()>;
+    fn release(&self) -> Result<(), ()>;
+}
+
+// 等待条件满足，每 1ms 检查一次
+// timeout 为 None 时一直等待，超时返回 IOError::Timeout
+// 超时按 tick_ms 计算，f 本身耗费的时间也计算在内
+pub fn wait_until<F: FnMut() -> Result<bool, IOError>>(
+    mut f: F,
+    timeout: Option<u32>,
+) -> Result<(), IOError> {
+    let start = Os::tick_ms();
+    loop {
+        if f()? {
+            return Ok(());
+        }
+        if let Some(t) = timeout {
+            if Os::tick_ms().wrapping_sub(start) >= t {
+                return Err(IOError::Timeout);
+            }
+        }
+        Os::delay(1);
+    }
+}
+
+// 忙等条件满足，用于只需要等待几个时钟周期的硬件标志
+// 不让出也不睡眠，超过 timeout ms 后返回 IOError::Timeout
+pub fn spin_until<F: FnMut() -> bool>(mut f: F, timeout: u32) -> Result<(), IOError> {
+    let start = Os::tick_ms();
+    loop {
+        if f() {
+            return Ok(());
+        }
+        // tick 的精度为 1ms，多等一个 tick 保证至少等待了 timeout
+        if Os::tick_ms().wrapping_sub(start) > timeout {
+            return Err(IOError::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+}
+
+// 阻塞等待条件满足
+// 没有超时时间时使用 yield 轮询，否则每 1ms 检查一次
+pub fn block_until<F: FnMut() -> bool>(mut f: F, timeout: Option<u32>) -> Result<(), IOError> {
+    match timeout {
+        None => {
+            while !f() {
+                Os::yield_now();
+            }
+            Ok(())
+        }
+        Some(_) => wait_until(|| Ok(f()), timeout),
+    }
+}
+
+#[cfg(all(test, feature = "std_core"))]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    #[test]
+    fn wait_until_timeout() {
+        let start = Os::tick_ms();
+        assert!(matches!(
+            wait_until(|| Ok(false), Some(20)),
+            Err(IOError::Timeout)
+        ));
+        assert!(Os::tick_ms().wrapping_sub(start) >= 20);
+        // 超时时间为 0 时只检查一次
+        assert!(matches!(
+            wait_until(|| Ok(false), Some(0)),
+            Err(IOError::Timeout)
+        ));
+        assert!(wait_until(|| Ok(true), Some(0)).is_ok());
+    }
+
+    #[test]
+    fn wait_until_counts_check_time() {
+        // 每次检查耗时 10ms，超时按经过的时间计算而不是检查次数
+        let start = Os::tick_ms();
+        let ret = wait_until(
+            || {
+                Os::delay(10);
+                Ok(false)
+            },
+            Some(20),
+        );
+        assert!(matches!(ret, Err(IOError::Timeout)));
+        assert!(Os::tick_ms().wrapping_sub(start) < 100);
+    }
+
+    #[test]
+    fn spin_until_timeout() {
+        let start = Os::tick_ms();
+        assert!(matches!(spin_until(|| false, 5), Err(IOError::Timeout)));
+        assert!(Os::tick_ms().wrapping_sub(start) >= 5);
+        assert!(spin_until(|| true, 0).is_ok());
+    }
+
+    #[test]
+    fn wait_until_ready_and_error() {
+        let n = Cell::new(0);
+        let ret = wait_until(
+            || {
+                n.set(n.get() + 1);
+                Ok(n.get() == 3)
+            },
+            None,
+        );
+        assert!(ret.is_ok());
+        assert_eq!(n.get(), 3);
+        assert!(matches!(
+            wait_until(|| Err(IOError::ReadError), Some(10)),
+            Err(IOError::ReadError)
+        ));
+    }
+
+    #[test]
+    fn block_until_timeout() {
+        assert!(matches!(
+            block_until(|| false, Some(5)),
+            Err(IOError::Timeout)
+        ));
+        let n = Cell::new(0);
+        let ret = block_until(
+            || {
+                n.set(n.get() + 1);
+                n.get() > 10
+            },
+            None,
+        );
+        assert!(ret.is_ok());
+    }
+
+    #[test]
+    fn no_irq_nested() {
+        let v = Os::no_irq(|| Os::no_irq(|| 1) + 1);
+        assert_eq!(v, 2);
+    }
+
+    #[test]
+    fn semaphore_timeout() {
+        let sem = Semaphore::new(1);
+        assert!(sem.take_wait(0).is_ok());
+        assert!(sem.take_wait(5).is_err());
+        sem.release().unwrap();
+        assert!(sem.take_wait_forever().is_ok());
+    }
+}
@@ -0,0 +1,68 @@
+//! rtt_rs 后端，对接 C 语言的 rt-thread 内核
+
+use super::{OsApi, OsSemaphore};
+use core::task::Waker;
+use rtt_rs::raw_api::no_irq;
+use rtt_rs::thread::Thread;
+
+pub use rtt_rs::mutex::Mutex;
+pub use rtt_rs::semaphore::Semaphore;
+
+pub struct Os;
+
+impl OsApi for Os {
+    type Semaphore = Semaphore;
+
+    fn yield_now() {
+        Thread::_yield();
+    }
+
+    fn delay(ms: u32) {
+        Thread::delay(ms_to_tick(ms) as _);
+    }
+
+    fn no_irq<T, F: FnOnce() -> T>(f: F) -> T {
+        no_irq(f)
+    }
+
+    fn device_wake(w: Waker) {
+        rtt_rs::embassy_async::executor::device_wake(w)
+    }
+
+    // rt_tick_from_millisecond(1000) 即为 RT_TICK_PER_SECOND
+    // 在 64 位上换算，tick 回绕时结果不连续，与 rt_tick_get_millisecond 相同
+    fn tick_ms() -> u32 {
+        unsafe {
+            let per_second = rt_tick_from_millisecond(1000).max(1) as u64;
+            (rt_tick_get() as u64 * 1000 / per_second) as u32
+        }
+    }
+
+    fn semaphore() -> Result<Semaphore, ()> {
+        Semaphore::new("drv_wait").map_err(|_| ())
+    }
+}
+
+extern "C" {
+    fn rt_tick_get() -> u32;
+    fn rt_tick_from_millisecond(ms: i32) -> u32;
+}
+
+// 内核的延时和超时以 tick 为单位，框架的时间单位是 ms
+fn ms_to_tick(ms: u32) -> u32 {
+    unsafe { rt_tick_from_millisecond(ms.min(i32::MAX as u32) as i32) }
+}
+
+impl OsSemaphore for Semaphore {
+    fn take_wait_forever(&self) -> Result<(), ()> {
+        Semaphore::take_wait_forever(self).map_err(|_| ())
+    }
+
+    fn take_wait(&self, ms: u32) -> Result<(), ()> {
+        Semaphore::take(self, ms_to_tick(ms) as _).map_err(|_| ())
+    }
+
+    fn release(&self) -> Result<(), ()> {
+        Semaphore::release(self).map_err(|_| ())
+    }
+}
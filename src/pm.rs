@@ -0,0 +1,269 @@
+//! 电源管理
+//! suspend_all 先挂起子设备再挂起父设备，resume_all 按相反的顺序恢复，
+//! 保证恢复子设备时所在的总线已经可用
+//! 只有已经打开的设备会被挂起，挂起期间不应该再访问设备
+
+use crate::alloc::sync::Arc;
+use crate::alloc::vec::Vec;
+use crate::driver::Driver;
+use crate::error::IOError;
+use crate::trace::{self, TraceData, TraceOp};
+use crate::Mutex;
+use crate::DEVICE_LIST;
+
+// 设备在设备树中的深度，没有父设备时为 0
+fn depth(dev: &Driver) -> usize {
+    let mut n = 0;
+    let mut parent = dev.parent.clone();
+    while let Some(p) = parent {
+        n += 1;
+        parent = p.lock().unwrap().parent.clone();
+    }
+    n
+}
+
+// 设备链表的快照，深度大的设备在前
+fn snapshot() -> Vec<Arc<Mutex<Driver>>> {
+    let list: Vec<Arc<Mutex<Driver>>> = DEVICE_LIST.lock().unwrap().values().cloned().collect();
+    let mut devs: Vec<(usize, Arc<Mutex<Driver>>)> = list
+        .into_iter()
+        .map(|dev| {
+            let n = depth(&dev.lock().unwrap());
+            (n, dev)
+        })
+        .collect();
+    devs.sort_by_key(|d| core::cmp::Reverse(d.0));
+    devs.into_iter().map(|(_, dev)| dev).collect()
+}
+
+// 挂起所有已经打开的设备
+// 某个设备挂起失败时，恢复已经挂起的设备并返回该错误
+pub fn suspend_all() -> Result<(), IOError> {
+    suspend_list(&snapshot())
+}
+
+fn suspend_list(devs: &[Arc<Mutex<Driver>>]) -> Result<(), IOError> {
+    for (i, dev) in devs.iter().enumerate() {
+        let mut inner_dev = dev.lock().unwrap();
+        if inner_dev.suspended || inner_dev.open_type().is_none() {
+            continue;
+        }
+        let ret = inner_dev.ops.suspend();
+        trace::emit(
+            &inner_dev,
+            TraceOp::Suspend,
+            0,
+            TraceData::None,
+            trace::result(&ret),
+        );
+        if let Err(e) = ret {
+            inner_dev.stats.record_error(&e);
+            drop(inner_dev);
+            let _ = resume_list(devs[..i].iter().rev());
+            return Err(e);
+        }
+        inner_dev.suspended = true;
+    }
+    Ok(())
+}
+
+// 恢复所有被挂起的设备
+// 某个设备恢复失败时继续恢复其他设备，返回第一个错误
+pub fn resume_all() -> Result<(), IOError> {
+    resume_list(snapshot().iter().rev())
+}
+
+fn resume_list<'a, I>(devs: I) -> Result<(), IOError>
+where
+    I: Iterator<Item = &'a Arc<Mutex<Driver>>>,
+{
+    let mut first = Ok(());
+    for dev in devs {
+        let mut inner_dev = dev.lock().unwrap();
+        if !inner_dev.suspended {
+            continue;
+        }
+        inner_dev.suspended = false;
+        if inner_dev.open_type().is_none() {
+            // 挂起期间已经被关闭，不需要恢复
+            continue;
+        }
+        let ret = inner_dev.ops.resume();
+        trace::emit(
+            &inner_dev,
+            TraceOp::Resume,
+            0,
+            TraceData::None,
+            trace::result(&ret),
+        );
+        if let Err(e) = ret {
+            inner_dev.stats.record_error(&e);
+            if first.is_ok() {
+                first = Err(e);
+            }
+        }
+    }
+    first
+}
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use super::*;
+    use crate::api::{find, DevOpen};
+    use crate::bsp::host_sim::led::{led_is_on, SimLed};
+    use crate::bsp::host_sim::testing::sim_uart;
+    use crate::bsp::host_sim::uart::{uart_baud, uart_inject_rx, uart_suspended};
+    use crate::device::led::Led;
+    use crate::device::serial::SerialBaudRate;
+    use crate::device::{register_child_device, register_device, DeviceOps};
+    use crate::driver::DriverOps;
+    use crate::typed::find_serial;
+    use crate::{OpenFlag, ToMakeStdData};
+    use std::sync::Mutex as StdMutex;
+
+    // 测试并行运行，只挂起名称以 prefix 开头的设备
+    fn own(prefix: &str) -> Vec<Arc<Mutex<Driver>>> {
+        snapshot()
+            .into_iter()
+            .filter(|d| d.lock().unwrap().name.starts_with(prefix))
+            .collect()
+    }
+
+    // 记录挂起、恢复的顺序，fail 为 true 时挂起失败
+    struct LogDev(&'static str, bool, Arc<StdMutex<Vec<&'static str>>>);
+
+    impl DeviceOps for LogDev {
+        fn open(&self, _flag: &OpenFlag) -> Result<(), IOError> {
+            Ok(())
+        }
+        fn close(&self) -> Result<(), IOError> {
+            Ok(())
+        }
+        fn control(&self, _data: &dyn ToMakeStdData) -> Result<(), IOError> {
+            Ok(())
+        }
+        fn suspend(&self) -> Result<(), IOError> {
+            self.2.lock().unwrap().push(self.0);
+            if self.1 {
+                return Err(IOError::Timeout);
+            }
+            Ok(())
+        }
+        fn resume(&self) -> Result<(), IOError> {
+            self.2.lock().unwrap().push(self.0);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn order() {
+        let log = Arc::new(StdMutex::new(Vec::new()));
+        register_device(LogDev("bus", false, log.clone()), "test/pm/order").unwrap();
+        register_child_device(
+            LogDev("child", false, log.clone()),
+            "test/pm/order/child",
+            "test/pm/order",
+        )
+        .unwrap();
+        register_device(LogDev("closed", false, log.clone()), "test/pm/orderx").unwrap();
+        let child = find("test/pm/order/child").unwrap();
+        let g = child.open(&OpenFlag::zero()).unwrap();
+
+        let devs = own("test/pm/order");
+        suspend_list(&devs).unwrap();
+        // 先子设备后父设备，没有打开的设备不挂起
+        assert_eq!(*log.lock().unwrap(), ["child", "bus"]);
+        assert!(child.lock().unwrap().is_suspended());
+        assert!(!find("test/pm/orderx")
+            .unwrap()
+            .lock()
+            .unwrap()
+            .is_suspended());
+        // 重复挂起不会再调用驱动
+        suspend_list(&devs).unwrap();
+        assert_eq!(log.lock().unwrap().len(), 2);
+
+        log.lock().unwrap().clear();
+        resume_list(devs.iter().rev()).unwrap();
+        assert_eq!(*log.lock().unwrap(), ["bus", "child"]);
+        assert!(!child.lock().unwrap().is_suspended());
+        drop(g);
+    }
+
+    #[test]
+    fn rollback() {
+        let log = Arc::new(StdMutex::new(Vec::new()));
+        register_device(LogDev("bus", false, log.clone()), "test/pm/fail").unwrap();
+        register_child_device(
+            LogDev("ok", false, log.clone()),
+            "test/pm/fail/a",
+            "test/pm/fail",
+        )
+        .unwrap();
+        register_child_device(
+            LogDev("bad", true, log.clone()),
+            "test/pm/fail/a/b",
+            "test/pm/fail/a",
+        )
+        .unwrap();
+        let ok = find("test/pm/fail/a").unwrap();
+        let bad = find("test/pm/fail/a/b").unwrap();
+        let g1 = ok.open(&OpenFlag::zero()).unwrap();
+        let g2 = bad.open(&OpenFlag::zero()).unwrap();
+
+        let devs = own("test/pm/fail");
+        assert!(matches!(suspend_list(&devs), Err(IOError::Timeout)));
+        // 最深的设备最先挂起并失败，之后的设备都没有被挂起
+        assert_eq!(*log.lock().unwrap(), ["bad"]);
+        assert!(!bad.lock().unwrap().is_suspended());
+        assert_eq!(bad.lock().unwrap().stats().timeouts, 1);
+        drop((g1, g2));
+    }
+
+    #[test]
+    fn rollback_resumes_suspended() {
+        let log = Arc::new(StdMutex::new(Vec::new()));
+        register_device(LogDev("bus", true, log.clone()), "test/pm/back").unwrap();
+        register_child_device(
+            LogDev("child", false, log.clone()),
+            "test/pm/back/child",
+            "test/pm/back",
+        )
+        .unwrap();
+        let child = find("test/pm/back/child").unwrap();
+        let g = child.open(&OpenFlag::zero()).unwrap();
+
+        assert!(suspend_list(&own("test/pm/back")).is_err());
+        // 父设备挂起失败，已经挂起的子设备被恢复
+        assert_eq!(*log.lock().unwrap(), ["child", "bus", "child"]);
+        assert!(!child.lock().unwrap().is_suspended());
+        drop(g);
+    }
+
+    #[test]
+    fn serial_and_led_state() {
+        let num = sim_uart("test/pm/uart");
+        let uart = find_serial("test/pm/uart").unwrap();
+        let gu = uart.open(OpenFlag::zero().set_read_int(true)).unwrap();
+        gu.set_baud(SerialBaudRate::B9600).unwrap();
+        register_device(Led::new(SimLed::new(3)), "test/pm/led").unwrap();
+        let led = find("test/pm/led").unwrap();
+        let gl = led.open(&OpenFlag::zero()).unwrap();
+        gl.write(0, &1u32).unwrap();
+
+        let devs = own("test/pm/");
+        suspend_list(&devs).unwrap();
+        assert!(uart_suspended(num));
+        assert!(!led_is_on(3));
+
+        resume_list(devs.iter().rev()).unwrap();
+        assert!(!uart_suspended(num));
+        // 关闭时钟后丢失的波特率在恢复时重新配置
+        assert_eq!(uart_baud(num), 9600);
+        assert!(led_is_on(3));
+        // 接收中断也被重新打开
+        uart_inject_rx(num, b"x");
+        assert_eq!(gu.read_byte().unwrap(), Some(b'x'));
+        drop((gu, gl));
+    }
+}
@@ -0,0 +1,104 @@
+//! 设备的读写统计
+//! 统计由框架在 DriverGuard 中完成，驱动不需要关心
+//! 环形缓冲区的丢弃数量由驱动通过 DeviceOps::buffer_drops 给出
+
+use crate::alloc::collections::BTreeMap;
+use crate::api::find;
+use crate::error::IOError;
+
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStats {
+    pub opens: u32,
+    pub closes: u32,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    // 写缓冲满，返回 WriteFull 的次数
+    pub write_full: u32,
+    // 非阻塞读没有数据，返回 ReadEmpty 的次数
+    pub read_empty: u32,
+    // 等待超时的次数，包括驱动给出的超时
+    pub timeouts: u32,
+    // 环形缓冲区满时被抛弃的数据数量，如串口接收溢出
+    pub buffer_drops: u64,
+    // 按错误种类统计的错误次数，键为 IOError::name
+    // 缓冲区满、没有数据、超时是正常的流控结果，只计入上面的计数，不算作错误
+    pub errors: BTreeMap<&'static str, u32>,
+}
+
+impl DeviceStats {
+    pub fn error_count(&self, name: &str) -> u32 {
+        self.errors.get(name).copied().unwrap_or(0)
+    }
+
+    pub fn total_errors(&self) -> u32 {
+        self.errors.values().sum()
+    }
+
+    pub(crate) fn record_error(&mut self, e: &IOError) {
+        match e {
+            IOError::WriteFull(_) => {
+                self.write_full = self.write_full.wrapping_add(1);
+                return;
+            }
+            IOError::ReadEmpty => {
+                self.read_empty = self.read_empty.wrapping_add(1);
+                return;
+            }
+            e if e.is_timeout() => {
+                self.timeouts = self.timeouts.wrapping_add(1);
+                return;
+            }
+            _ => {}
+        }
+        let n = self.errors.entry(e.name()).or_insert(0);
+        *n = n.wrapping_add(1);
+    }
+
+    pub(crate) fn record<T>(&mut self, ret: &Result<T, IOError>) {
+        if let Err(e) = ret {
+            self.record_error(e);
+        }
+    }
+}
+
+// 查询设备的统计信息
+pub fn device_stats(name: &str) -> Result<DeviceStats, IOError> {
+    let dev = find(name)?;
+    let dev = dev.lock().unwrap();
+    Ok(dev.stats())
+}
+
+// 清空设备的统计信息
+// 缓冲区的丢弃数量由驱动记录，清空后从当前值重新计算
+pub fn reset_device_stats(name: &str) -> Result<(), IOError> {
+    let dev = find(name)?;
+    let mut dev = dev.lock().unwrap();
+    dev.reset_stats();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::StdData;
+    use crate::device::i2c_bus::I2CBusError;
+
+    #[test]
+    fn flow_control_not_errors() {
+        let mut s = DeviceStats::default();
+        s.record::<()>(&Err(IOError::WriteFull(StdData::Null)));
+        s.record::<()>(&Err(IOError::ReadEmpty));
+        s.record::<()>(&Err(IOError::Timeout));
+        s.record::<()>(&Err(I2CBusError::Timeout.into()));
+        s.record::<()>(&Ok(()));
+        assert_eq!(s.write_full, 1);
+        assert_eq!(s.read_empty, 1);
+        assert_eq!(s.timeouts, 2);
+        assert_eq!(s.total_errors(), 0);
+
+        s.record::<()>(&Err(IOError::ReadError));
+        s.record::<()>(&Err(I2CBusError::Nack(0x50).into()));
+        assert_eq!(s.error_count("ReadError"), 1);
+        assert_eq!(s.total_errors(), 2);
+    }
+}
@@ -0,0 +1,224 @@
+//! 设备访问的跟踪钩子
+//! DriverGuard 在打开、关闭、读写、控制时调用注册的钩子
+//! 可以注册全局的钩子，也可以为单个设备注册钩子，两者都存在时都会被调用
+//! 钩子在设备锁内被调用，钩子中不能再操作同一个设备，并且应该尽快返回
+
+use crate::alloc::format;
+use crate::alloc::string::{String, ToString};
+use crate::api::find;
+use crate::data::StdData;
+use crate::driver::Driver;
+use crate::error::IOError;
+use crate::os::{Os, OsApi};
+use crate::Mutex;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TraceOp {
+    Open,
+    Close,
+    Read,
+    Write,
+    Control,
+    Command,
+    Sync,
+    Chf,
+    Suspend,
+    Resume,
+}
+
+impl TraceOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TraceOp::Open => "open",
+            TraceOp::Close => "close",
+            TraceOp::Read => "read",
+            TraceOp::Write => "write",
+            TraceOp::Control => "control",
+            TraceOp::Command => "command",
+            TraceOp::Sync => "sync",
+            TraceOp::Chf => "chf",
+            TraceOp::Suspend => "suspend",
+            TraceOp::Resume => "resume",
+        }
+    }
+}
+
+// 操作携带的数据
+#[derive(Debug, Copy, Clone)]
+pub enum TraceData<'a> {
+    None,
+    Data(&'a StdData),
+    Buf(&'a [u8]),
+}
+
+#[derive(Debug)]
+pub struct TraceEvent<'a> {
+    pub name: &'a str,
+    pub op: TraceOp,
+    // 读写的数据长度，其他操作为 0
+    pub size: usize,
+    pub data: TraceData<'a>,
+    pub result: Result<(), &'a IOError>,
+    // 系统时间，单位 ms
+    pub time: u32,
+}
+
+pub type TraceHook = fn(&TraceEvent);
+
+lazy_static! {
+    static ref GLOBAL_HOOK: Mutex<Option<TraceHook>> = Mutex::new(None).unwrap();
+}
+
+// 是否设置了全局钩子，没有设置时每次读写不需要去锁 GLOBAL_HOOK
+static GLOBAL_SET: AtomicBool = AtomicBool::new(false);
+
+// 设置全局的钩子，None 表示取消
+pub fn set_global_trace(hook: Option<TraceHook>) {
+    let mut g = GLOBAL_HOOK.lock().unwrap();
+    *g = hook;
+    GLOBAL_SET.store(hook.is_some(), Ordering::Release);
+}
+
+// 设置单个设备的钩子，None 表示取消
+pub fn set_device_trace(name: &str, hook: Option<TraceHook>) -> Result<(), IOError> {
+    let dev = find(name)?;
+    dev.lock().unwrap().trace = hook;
+    Ok(())
+}
+
+fn global_hook() -> Option<TraceHook> {
+    if !GLOBAL_SET.load(Ordering::Acquire) {
+        return None;
+    }
+    *GLOBAL_HOOK.lock().unwrap()
+}
+
+// 是否有钩子需要调用，用于避免无用的数据构造
+pub(crate) fn enabled(dev: &Driver) -> bool {
+    dev.trace.is_some() || global_hook().is_some()
+}
+
+pub(crate) fn emit(
+    dev: &Driver,
+    op: TraceOp,
+    size: usize,
+    data: TraceData,
+    result: Result<(), &IOError>,
+) {
+    let global = global_hook();
+    if dev.trace.is_none() && global.is_none() {
+        return;
+    }
+    let ev = TraceEvent {
+        name: dev.name.as_str(),
+        op,
+        size,
+        data,
+        result,
+        time: Os::tick_ms(),
+    };
+    if let Some(hook) = dev.trace {
+        hook(&ev);
+    }
+    if let Some(hook) = global {
+        hook(&ev);
+    }
+}
+
+// 跟踪的结果只关心是否出错
+pub(crate) fn result<T>(ret: &Result<T, IOError>) -> Result<(), &IOError> {
+    ret.as_ref().map(|_| ())
+}
+
+// 用于打印日志的摘要，只显示前 8 个字节
+pub fn summary(data: &TraceData) -> String {
+    let bytes = match data {
+        TraceData::None => return "-".to_string(),
+        TraceData::Buf(b) => *b,
+        TraceData::Data(StdData::Bytes(b)) => b.as_slice(),
+        TraceData::Data(StdData::U32(a)) => return a.to_string(),
+        TraceData::Data(StdData::U8(a)) => return a.to_string(),
+        TraceData::Data(StdData::Null) => return "null".to_string(),
+        TraceData::Data(_) => return "..".to_string(),
+    };
+    let mut s = String::new();
+    for b in bytes.iter().take(8) {
+        s.push_str(&format!("{:02x} ", b));
+    }
+    if bytes.len() > 8 {
+        s.push_str("..");
+    }
+    s
+}
+
+#[cfg(all(test, feature = "host_sim"))]
+mod tests {
+    use super::*;
+    use crate::alloc::vec::Vec;
+    use crate::api::DevOpen;
+    use crate::device::{register_device, DeviceOps};
+    use crate::driver::DriverOps;
+    use crate::{OpenFlag, ToMakeStdData};
+
+    // 每次最多写入两个字节
+    struct HalfDev;
+
+    impl DeviceOps for HalfDev {
+        fn open(&self, _flag: &OpenFlag) -> Result<(), IOError> {
+            Ok(())
+        }
+        fn close(&self) -> Result<(), IOError> {
+            Ok(())
+        }
+        fn control(&self, _data: &dyn ToMakeStdData) -> Result<(), IOError> {
+            Ok(())
+        }
+        fn write(&self, data: &dyn ToMakeStdData) -> Result<(), IOError> {
+            match data.make_data() {
+                StdData::Bytes(b) if b.len() > 2 => {
+                    Err(IOError::WriteFull(StdData::Bytes(b[2..].to_vec())))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    lazy_static! {
+        static ref WRITES: Mutex<Vec<(usize, String)>> = Mutex::new(Vec::new()).unwrap();
+    }
+
+    fn record(ev: &TraceEvent) {
+        if let TraceOp::Write = ev.op {
+            assert_eq!(ev.name, "test/trace/half");
+            WRITES.lock().unwrap().push((ev.size, summary(&ev.data)));
+        }
+    }
+
+    #[test]
+    fn write_full_size() {
+        register_device(HalfDev, "test/trace/half").unwrap();
+        set_device_trace("test/trace/half", Some(record)).unwrap();
+        let dev = find("test/trace/half").unwrap();
+        let g = dev.open(&OpenFlag::zero()).unwrap();
+        assert!(g.write(0, &[1u8, 2, 3, 4].to_vec()).is_err());
+        g.write(0, &[5u8].to_vec()).unwrap();
+        let w = WRITES.lock().unwrap();
+        assert_eq!(w[0].0, 2);
+        assert_eq!(w[0].1.trim(), "01 02");
+        assert_eq!(w[1].0, 1);
+    }
+
+    fn ignore(_ev: &TraceEvent) {}
+
+    #[test]
+    fn global_flag() {
+        set_global_trace(None);
+        assert!(global_hook().is_none());
+        set_global_trace(Some(ignore));
+        assert!(global_hook().is_some());
+        set_global_trace(None);
+        assert!(global_hook().is_none());
+    }
+}
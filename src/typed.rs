@@ -0,0 +1,233 @@
+//! 按类别查找设备
+//! find_xxx 在查找时检查设备的类别，返回对应类别的设备
+//! 打开后得到的 guard 提供该类设备专有的操作，不需要再手动构造 StdData
+//! 打开时仍然使用 raw_open 的独占/共享逻辑
+
+use crate::alloc::sync::Arc;
+use crate::alloc::vec::Vec;
+use crate::api::{find, open_static, DevOpen};
+use crate::async_rw::{ByteStream, ChunkStream, ReadExactFuture, ReadUntilFuture};
+use crate::data::{OpenFlag, StdData};
+use crate::device::i2c_bus::I2CAddressType;
+use crate::device::i2c_device::{I2CCmd, I2CDevConfig, I2CReply};
+use crate::device::led::LedState;
+use crate::device::serial::{SerialBaudRate, SerialCmd, SerialConfig, SerialReply};
+use crate::device::spi_flash::{SpiFlashCmd, SpiFlashReply};
+use crate::device::DeviceClass;
+use crate::driver::{Driver, DriverOps};
+use crate::error::IOError;
+use crate::guard::DriverGuard;
+use crate::Mutex;
+
+fn find_class(name: &str, class: DeviceClass) -> Result<Arc<Mutex<Driver>>, IOError> {
+    let dev = find(name)?;
+    if dev.lock().unwrap().class() != class {
+        return Err(IOError::FindError);
+    }
+    Ok(dev)
+}
+
+macro_rules! typed_device {
+    ($dev: ident, $guard: ident, $find: ident, $class: expr) => {
+        pub struct $dev(Arc<Mutex<Driver>>);
+
+        impl $dev {
+            pub fn open(&self, f: &OpenFlag) -> Result<$guard<'_>, IOError> {
+                self.0.open(f).map(|g| $guard(g))
+            }
+
+            // 得到的 guard 持有设备的所有权
+            pub fn open_owned(&self, f: &OpenFlag) -> Result<$guard<'static>, IOError> {
+                open_static(&self.0, f).map(|g| $guard(g))
+            }
+
+            pub fn open_wait(
+                &self,
+                f: &OpenFlag,
+                timeout: Option<u32>,
+            ) -> Result<$guard<'_>, IOError> {
+                self.0.open_wait(f, timeout).map(|g| $guard(g))
+            }
+        }
+
+        pub struct $guard<'a>(DriverGuard<'a>);
+
+        impl<'a> $guard<'a> {
+            // 取得原始的 guard，用于类型化接口没有覆盖的操作
+            pub fn raw(&self) -> &DriverGuard<'a> {
+                &self.0
+            }
+
+            pub fn try_upgrade(&mut self) -> Result<(), IOError> {
+                self.0.try_upgrade()
+            }
+
+            pub fn downgrade(&mut self) {
+                self.0.downgrade()
+            }
+        }
+
+        pub fn $find(name: &str) -> Result<$dev, IOError> {
+            find_class(name, $class).map(|d| $dev(d))
+        }
+    };
+}
+
+typed_device!(SerialDevice, SerialGuard, find_serial, DeviceClass::Serial);
+typed_device!(LedDevice, LedGuard, find_led, DeviceClass::Led);
+typed_device!(I2CDevice, I2CGuard, find_i2c, DeviceClass::I2CDevice);
+typed_device!(
+    SpiFlashDevice,
+    SpiFlashGuard,
+    find_spi_flash,
+    DeviceClass::SpiFlash
+);
+
+impl SerialGuard<'_> {
+    // 没有数据可读时返回 None
+    pub fn read_byte(&self) -> Result<Option<u8>, IOError> {
+        match self.0.read(0, 1) {
+            Ok(StdData::U32(a)) => Ok(Some(a as u8)),
+            Ok(StdData::U8(a)) => Ok(Some(a)),
+            Ok(StdData::Null) | Err(IOError::ReadEmpty) => Ok(None),
+            Ok(_) => Err(IOError::DataError),
+            Err(e) => Err(e),
+        }
+    }
+
+    // 最多读取 len 个字节，没有数据时提前返回
+    pub fn read_bytes(&self, len: usize) -> Result<Vec<u8>, IOError> {
+        let mut ret = Vec::with_capacity(len);
+        while ret.len() < len {
+            match self.read_byte()? {
+                Some(a) => ret.push(a),
+                None => break,
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn write_bytes(&self, data: &[u8]) -> Result<(), IOError> {
+        self.0.write(0, &data)
+    }
+
+    pub fn set_baud(&self, baud: SerialBaudRate) -> Result<(), IOError> {
+        self.0.control(&SerialConfig::Baud(baud))
+    }
+
+    pub fn get_baud(&self) -> Result<SerialBaudRate, IOError> {
+        match self.0.command(SerialCmd::GetBaud)? {
+            SerialReply::Baud(b) => Ok(b),
+            _ => Err(IOError::DataError),
+        }
+    }
+
+    pub fn open_flag(&self) -> Result<OpenFlag, IOError> {
+        match self.0.command(SerialCmd::GetFlag)? {
+            SerialReply::Flag(f) => Ok(f),
+            _ => Err(IOError::DataError),
+        }
+    }
+
+    // 等待发送完成
+    pub fn sync(&self, timeout: Option<u32>) -> Result<(), IOError> {
+        self.0.sync(timeout)
+    }
+
+    pub fn set_read_buf_size(&self, size: u32) -> Result<(), IOError> {
+        self.0.control(&SerialConfig::RBufSize(size))
+    }
+
+    pub fn set_write_buf_size(&self, size: u32) -> Result<(), IOError> {
+        self.0.control(&SerialConfig::WBufSize(size))
+    }
+}
+
+// 异步读，需要以中断读或异步读的方式打开
+impl<'a> SerialGuard<'a> {
+    pub fn read_exact(&self, len: usize) -> Result<ReadExactFuture<'_, 'a>, IOError> {
+        self.0.read_exact(0, len)
+    }
+
+    // 读一行可以使用 read_until(b'\n', max)，超过 max 个字节没有换行时返回 DataError
+    pub fn read_until(&self, delim: u8, max: usize) -> Result<ReadUntilFuture<'_, 'a>, IOError> {
+        self.0.read_until(0, delim, max)
+    }
+
+    pub fn bytes(&self) -> Result<ByteStream<'_, 'a>, IOError> {
+        self.0.bytes(0)
+    }
+
+    pub fn chunks(&self, max: usize) -> Result<ChunkStream<'_, 'a>, IOError> {
+        self.0.chunks(0, max)
+    }
+}
+
+impl LedGuard<'_> {
+    pub fn on(&self) -> Result<(), IOError> {
+        self.0.write(0, &LedState::On)
+    }
+
+    pub fn off(&self) -> Result<(), IOError> {
+        self.0.write(0, &LedState::Off)
+    }
+
+    pub fn is_on(&self) -> Result<bool, IOError> {
+        match self.0.read(0, 1)? {
+            StdData::U32(a) => Ok(a != 0),
+            _ => Err(IOError::DataError),
+        }
+    }
+}
+
+impl I2CGuard<'_> {
+    pub fn set_address(&self, address: u32, address_type: I2CAddressType) -> Result<(), IOError> {
+        self.0.control(&I2CDevConfig {
+            address,
+            address_type,
+        })
+    }
+
+    pub fn get_address(&self) -> Result<I2CDevConfig, IOError> {
+        match self.0.command(I2CCmd::GetAddress)? {
+            I2CReply::Address(a) => Ok(a),
+            _ => Err(IOError::DataError),
+        }
+    }
+
+    pub fn read_bytes(&self, len: usize) -> Result<Vec<u8>, IOError> {
+        self.0
+            .read(0, len as _)?
+            .take_bytes()
+            .map_err(|_| IOError::DataError)
+    }
+
+    pub fn write_bytes(&self, data: &[u8]) -> Result<(), IOError> {
+        self.0.write(0, &data)
+    }
+}
+
+impl SpiFlashGuard<'_> {
+    pub fn jedec_id(&self) -> Result<[u8; 3], IOError> {
+        match self.0.command(SpiFlashCmd::ReadJedecId)? {
+            SpiFlashReply::JedecId(id) => Ok(id),
+            _ => Err(IOError::DataError),
+        }
+    }
+
+    pub fn read_bytes(&self, address: usize, len: usize) -> Result<Vec<u8>, IOError> {
+        self.0
+            .read(address, len as _)?
+            .take_bytes()
+            .map_err(|_| IOError::DataError)
+    }
+
+    pub fn write_bytes(&self, address: usize, data: &[u8]) -> Result<(), IOError> {
+        self.0.write(address, &data)
+    }
+
+    // 等待 flash 写入完成
+    pub fn sync(&self, timeout: Option<u32>) -> Result<(), IOError> {
+        self.0.sync(timeout)
+    }
+}